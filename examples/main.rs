@@ -82,24 +82,26 @@ pub async fn main() -> Result<(), Box<dyn Error>> {
     tracing::subscriber::set_global_default(subscriber)
         .expect("Failed on subscribe tracing");
 
-    let mut conf = Config::default();
-    conf.public_address = matches
-        .get_one::<String>("public_address")
-        .expect("public_address to have a value")
-        .to_string();
-    conf.listen_address = matches
-        .get_one::<String>("listen_address")
-        .map(|a| a.to_string());
-    conf.bootstrapping_nodes = matches
-        .get_many::<String>("bootstrap")
-        .unwrap_or_default()
-        .map(|s| s.to_string())
-        .collect();
+    let conf = Config {
+        public_address: matches
+            .get_one::<String>("public_address")
+            .expect("public_address to have a value")
+            .to_string(),
+        listen_address: matches
+            .get_one::<String>("listen_address")
+            .map(|a| a.to_string()),
+        bootstrapping_nodes: matches
+            .get_many::<String>("bootstrap")
+            .unwrap_or_default()
+            .map(|s| s.to_string())
+            .collect(),
+        ..Default::default()
+    };
 
     let peer = Peer::new(conf, DummyListener {})?;
     loop {
         let stdin = io::stdin();
-        for message in stdin.lock().lines().flatten() {
+        for message in stdin.lock().lines().map_while(Result::ok) {
             match &message[..] {
                 "report" => {
                     peer.report().await;
@@ -0,0 +1,299 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use blake2::{Blake2s256, Digest};
+use rand::RngCore;
+use tokio::time::Instant;
+
+/// Address token, carried in the header of the messages
+pub(crate) type Token = [u8; 2];
+
+/// Token of the messages not proving any round trip
+pub(crate) const NO_TOKEN: Token = [0; 2];
+
+/// Interval between two rotations of the secret the tokens derive from.
+///
+/// The tokens issued are accepted until the second rotation, the tokens
+/// received are kept as long.
+const TOKEN_ROTATION: Duration = Duration::from_secs(5 * 60);
+
+/// Time during which the tokens of the replies to a request are accepted
+const REPLY_WINDOW: Duration = Duration::from_secs(30);
+
+/// Max addresses whose requests or tokens are tracked, the oldest being
+/// dropped first
+const MAX_ADDRESSES: usize = 10_000;
+
+/// Tokens proving that the source of a request received a previous reply.
+///
+/// Every `Pong` and `Nodes` reply carries a token bound to the address it is
+/// sent to, which the receiver echoes in the following `Ping` and `FindNodes`
+/// requests. A request carrying a valid token can't come from a spoofed
+/// address, so that its reply can't be reflected towards a victim.
+#[derive(Clone, Default)]
+pub(crate) struct AddressTokens {
+    state: Arc<Mutex<TokenState>>,
+}
+
+struct TokenState {
+    secret: [u8; 32],
+    previous_secret: [u8; 32],
+    rotated: Instant,
+    /// Time of the last request sent to each address
+    requested: HashMap<SocketAddr, Instant>,
+    received: HashMap<SocketAddr, (Token, Instant)>,
+    last_pruned: Instant,
+}
+
+impl Default for TokenState {
+    fn default() -> Self {
+        let now = Instant::now();
+        Self {
+            secret: random_secret(),
+            previous_secret: random_secret(),
+            rotated: now,
+            requested: HashMap::new(),
+            received: HashMap::new(),
+            last_pruned: now,
+        }
+    }
+}
+
+fn random_secret() -> [u8; 32] {
+    let mut secret = [0; 32];
+    rand::thread_rng().fill_bytes(&mut secret);
+    secret
+}
+
+fn derive(secret: &[u8; 32], address: &SocketAddr) -> Token {
+    let ip = match address.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    let hash = Blake2s256::new()
+        .chain_update(secret)
+        .chain_update(ip)
+        .chain_update(address.port().to_le_bytes())
+        .finalize();
+    match [hash[0], hash[1]] {
+        NO_TOKEN => [0, 1],
+        token => token,
+    }
+}
+
+/// Insert `value` in `map`, dropping the oldest entry if it is full
+fn insert_bounded<V>(
+    map: &mut HashMap<SocketAddr, V>,
+    address: SocketAddr,
+    value: V,
+    time: impl Fn(&V) -> Instant,
+) {
+    if map.len() >= MAX_ADDRESSES && !map.contains_key(&address) {
+        let oldest = map
+            .iter()
+            .min_by_key(|(_, value)| time(value))
+            .map(|(address, _)| *address);
+        if let Some(oldest) = oldest {
+            map.remove(&oldest);
+        }
+    }
+    map.insert(address, value);
+}
+
+impl TokenState {
+    fn rotate(&mut self) {
+        if self.rotated.elapsed() > TOKEN_ROTATION {
+            self.previous_secret = self.secret;
+            self.secret = random_secret();
+            self.rotated = Instant::now();
+        }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        if now.duration_since(self.last_pruned) > REPLY_WINDOW {
+            self.requested
+                .retain(|_, sent| now.duration_since(*sent) < REPLY_WINDOW);
+            self.received.retain(|_, (_, received)| {
+                now.duration_since(*received) < TOKEN_ROTATION
+            });
+            self.last_pruned = now;
+        }
+    }
+
+    fn request(&mut self, address: SocketAddr) -> Token {
+        let now = Instant::now();
+        self.prune(now);
+        insert_bounded(&mut self.requested, address, now, |sent| *sent);
+        self.token_for(&address)
+    }
+
+    fn record(&mut self, address: SocketAddr, token: Token) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+        let solicited = self
+            .requested
+            .get(&address)
+            .is_some_and(|sent| now.duration_since(*sent) < REPLY_WINDOW);
+        if token == NO_TOKEN || !solicited {
+            return false;
+        }
+        let known = self.token_for(&address) != NO_TOKEN;
+        insert_bounded(&mut self.received, address, (token, now), |r| r.1);
+        !known
+    }
+
+    fn token_for(&self, address: &SocketAddr) -> Token {
+        match self.received.get(address) {
+            Some((token, received)) if received.elapsed() < TOKEN_ROTATION => {
+                *token
+            }
+            _ => NO_TOKEN,
+        }
+    }
+}
+
+impl AddressTokens {
+    /// Token to send to `address` along with a reply
+    pub(crate) fn issue(&self, address: &SocketAddr) -> Token {
+        let mut state = self.state.lock().expect("Unpoisoned lock");
+        state.rotate();
+        derive(&state.secret, address)
+    }
+
+    /// Check that `token` has been issued to `address`
+    pub(crate) fn verify(&self, address: &SocketAddr, token: Token) -> bool {
+        let mut state = self.state.lock().expect("Unpoisoned lock");
+        state.rotate();
+        token != NO_TOKEN
+            && (token == derive(&state.secret, address)
+                || token == derive(&state.previous_secret, address))
+    }
+
+    /// Token to echo in a request sent to `address`, whose reply is then
+    /// expected to carry a new token
+    pub(crate) fn request(&self, address: SocketAddr) -> Token {
+        self.state.lock().expect("Unpoisoned lock").request(address)
+    }
+
+    /// Store the token of a reply received from `address`, returning `true`
+    /// if none was known.
+    ///
+    /// The token is ignored unless a request has been sent to `address`
+    /// recently, so that unsolicited replies can't plant tokens.
+    pub(crate) fn record(&self, address: SocketAddr, token: Token) -> bool {
+        self.state
+            .lock()
+            .expect("Unpoisoned lock")
+            .record(address, token)
+    }
+}
+
+/// Token bucket limiting the work done for unverified sources, such as the
+/// replies they receive, allowing bursts of up to one second worth of work
+pub(crate) struct Budget {
+    per_sec: f64,
+    available: f64,
+    updated: Instant,
+}
+
+impl Budget {
+    pub(crate) fn new(per_sec: u32) -> Self {
+        Self {
+            per_sec: per_sec as f64,
+            available: per_sec as f64,
+            updated: Instant::now(),
+        }
+    }
+
+    /// Take a unit of work from the budget, returning `false` if exhausted
+    pub(crate) fn take(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.available =
+            (self.available + elapsed * self.per_sec).min(self.per_sec);
+        self.updated = now;
+        if self.available < 1.0 {
+            return false;
+        }
+        self.available -= 1.0;
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_address_tokens() {
+        let tokens = AddressTokens::default();
+        let address: SocketAddr = "10.1.2.3:9000".parse().unwrap();
+        let other_port: SocketAddr = "10.1.2.3:9001".parse().unwrap();
+
+        let token = tokens.issue(&address);
+        assert!(tokens.verify(&address, token));
+        assert!(!tokens.verify(&other_port, token));
+        assert!(!tokens.verify(&address, NO_TOKEN));
+
+        // Still valid after a rotation, not after two
+        tokio::time::advance(TOKEN_ROTATION * 2).await;
+        assert!(tokens.verify(&address, token));
+        tokio::time::advance(TOKEN_ROTATION * 2).await;
+        assert!(!tokens.verify(&address, token));
+
+        // The tokens of the unsolicited replies are ignored
+        assert!(!tokens.record(address, token));
+        assert_eq!(tokens.request(address), NO_TOKEN);
+        assert!(!tokens.record(address, NO_TOKEN));
+        assert!(tokens.record(address, token));
+        // Shared between the clones of the handle
+        assert!(!tokens.clone().record(address, [1, 2]));
+        assert_eq!(tokens.request(address), [1, 2]);
+        assert!(!tokens.record(other_port, [1, 2]));
+        assert_eq!(tokens.request(other_port), NO_TOKEN);
+
+        // Replies are expected for a while only
+        tokio::time::advance(REPLY_WINDOW).await;
+        assert!(!tokens.record(other_port, [1, 2]));
+        tokio::time::advance(TOKEN_ROTATION).await;
+        assert_eq!(tokens.request(address), NO_TOKEN);
+    }
+
+    #[test]
+    fn test_insert_bounded() {
+        let start = Instant::now();
+        let mut map = HashMap::new();
+        for i in 0..=MAX_ADDRESSES as u32 {
+            let address = SocketAddr::from((i.to_be_bytes(), 9000));
+            let time = start + Duration::from_millis(i as u64);
+            insert_bounded(&mut map, address, time, |time| *time);
+        }
+        assert_eq!(map.len(), MAX_ADDRESSES);
+        assert!(!map.contains_key(&SocketAddr::from(([0; 4], 9000))));
+
+        // Updating a known address drops nothing
+        let address = SocketAddr::from((1u32.to_be_bytes(), 9000));
+        insert_bounded(&mut map, address, start, |time| *time);
+        assert_eq!(map.len(), MAX_ADDRESSES);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_budget() {
+        let mut budget = Budget::new(10);
+        for _ in 0..10 {
+            assert!(budget.take());
+        }
+        assert!(!budget.take());
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert!(budget.take());
+        assert!(!budget.take());
+    }
+}
@@ -0,0 +1,273 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+/// Range of IP addresses sharing the same `prefix` bits (CIDR notation)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl IpRange {
+    /// Create the range of the addresses sharing the first `prefix` bits of
+    /// `ip`.
+    ///
+    /// Returns `None` if `prefix` exceeds the length of the address (32 bits
+    /// for IPv4, 128 bits for IPv6).
+    pub fn new(ip: IpAddr, prefix: u8) -> Option<Self> {
+        let network = match ip.to_canonical() {
+            IpAddr::V4(ip) => {
+                let shift = 32u32.checked_sub(prefix as u32)?;
+                let mask = u32::MAX.checked_shl(shift).unwrap_or_default();
+                Ipv4Addr::from(u32::from(ip) & mask).into()
+            }
+            IpAddr::V6(ip) => {
+                let shift = 128u32.checked_sub(prefix as u32)?;
+                let mask = u128::MAX.checked_shl(shift).unwrap_or_default();
+                Ipv6Addr::from(u128::from(ip) & mask).into()
+            }
+        };
+        Some(Self { network, prefix })
+    }
+
+    /// Returns the first address of the range
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+
+    /// Returns the amount of bits shared by the addresses of the range
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Check if `ip` belongs to the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        IpRange::new(ip, self.prefix).is_some_and(|range| range == *self)
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// Network source blocked by a [Peer](crate::Peer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockedSource {
+    /// A single socket address
+    Address(SocketAddr),
+    /// Every port of an IP address
+    Ip(IpAddr),
+    /// Every address of an IP range
+    Range(IpRange),
+}
+
+impl BlockedSource {
+    /// Check if `address` belongs to the source
+    pub fn contains(&self, address: &SocketAddr) -> bool {
+        match self {
+            BlockedSource::Address(source) => source == address,
+            BlockedSource::Ip(ip) => {
+                ip.to_canonical() == address.ip().to_canonical()
+            }
+            BlockedSource::Range(range) => range.contains(address.ip()),
+        }
+    }
+}
+
+impl From<SocketAddr> for BlockedSource {
+    fn from(address: SocketAddr) -> Self {
+        BlockedSource::Address(address)
+    }
+}
+
+impl From<IpAddr> for BlockedSource {
+    fn from(ip: IpAddr) -> Self {
+        BlockedSource::Ip(ip)
+    }
+}
+
+impl From<IpRange> for BlockedSource {
+    fn from(range: IpRange) -> Self {
+        BlockedSource::Range(range)
+    }
+}
+
+impl fmt::Display for BlockedSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockedSource::Address(address) => address.fmt(f),
+            BlockedSource::Ip(ip) => ip.fmt(f),
+            BlockedSource::Range(range) => range.fmt(f),
+        }
+    }
+}
+
+/// Entry of the blocklist, as returned by
+/// [Peer::blocked_sources](crate::Peer::blocked_sources)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Ban {
+    /// The blocked source
+    pub source: BlockedSource,
+    /// Time left before the ban expires, `None` if permanent
+    pub expires_in: Option<Duration>,
+}
+
+/// Sources blocked by a [Peer](crate::Peer), along with the expiration of
+/// their ban (if any)
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Blocklist {
+    exact: HashMap<BlockedSource, Option<Instant>>,
+    ranges: HashMap<IpRange, Option<Instant>>,
+}
+
+fn is_active(expiration: &Option<Instant>, now: Instant) -> bool {
+    expiration.is_none_or(|expiration| expiration > now)
+}
+
+impl Blocklist {
+    /// Block `source`, for `duration` if any, or permanently.
+    ///
+    /// A ban already in place for the same source is replaced.
+    pub(crate) fn block(
+        &mut self,
+        source: BlockedSource,
+        duration: Option<Duration>,
+    ) {
+        let now = Instant::now();
+        self.remove_expired(now);
+        let expiration = duration.map(|duration| now + duration);
+        match source {
+            BlockedSource::Range(range) => {
+                self.ranges.insert(range, expiration);
+            }
+            BlockedSource::Ip(ip) => {
+                self.exact.insert(ip.to_canonical().into(), expiration);
+            }
+            address => {
+                self.exact.insert(address, expiration);
+            }
+        }
+    }
+
+    /// Remove the ban of `source`, returning `true` if it was blocked
+    pub(crate) fn unblock(&mut self, source: &BlockedSource) -> bool {
+        let now = Instant::now();
+        self.remove_expired(now);
+        match source {
+            BlockedSource::Range(range) => self.ranges.remove(range),
+            BlockedSource::Ip(ip) => {
+                self.exact.remove(&ip.to_canonical().into())
+            }
+            address => self.exact.remove(address),
+        }
+        .is_some()
+    }
+
+    /// Check if `address` belongs to a source currently blocked
+    pub(crate) fn is_blocked(&self, address: &SocketAddr) -> bool {
+        let now = Instant::now();
+        let ip = address.ip().to_canonical();
+        [BlockedSource::Address(*address), BlockedSource::Ip(ip)]
+            .iter()
+            .filter_map(|source| self.exact.get(source))
+            .chain(
+                self.ranges
+                    .iter()
+                    .filter(|(range, _)| range.contains(ip))
+                    .map(|(_, expiration)| expiration),
+            )
+            .any(|expiration| is_active(expiration, now))
+    }
+
+    /// Returns the bans currently in place
+    pub(crate) fn bans(&self) -> Vec<Ban> {
+        let now = Instant::now();
+        let exact = self.exact.iter().map(|(source, e)| (*source, e));
+        let ranges = self.ranges.iter().map(|(range, e)| ((*range).into(), e));
+        exact
+            .chain(ranges)
+            .filter(|(_, expiration)| is_active(expiration, now))
+            .map(|(source, expiration)| Ban {
+                source,
+                expires_in: expiration.map(|e| e - now),
+            })
+            .collect()
+    }
+
+    fn remove_expired(&mut self, now: Instant) {
+        self.exact
+            .retain(|_, expiration| is_active(expiration, now));
+        self.ranges
+            .retain(|_, expiration| is_active(expiration, now));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_range() {
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        let range = IpRange::new(ip, 16).unwrap();
+        assert_eq!(range.to_string(), "10.1.0.0/16");
+        assert!(range.contains("10.1.255.1".parse().unwrap()));
+        assert!(range.contains("::ffff:10.1.0.1".parse().unwrap()));
+        assert!(!range.contains("10.2.0.1".parse().unwrap()));
+        assert!(!range.contains("::1".parse().unwrap()));
+        assert!(
+            IpRange::new(ip, 0)
+                .unwrap()
+                .contains("1.1.1.1".parse().unwrap())
+        );
+        assert!(IpRange::new(ip, 33).is_none());
+
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let range = IpRange::new(ip, 48).unwrap();
+        assert_eq!(range.to_string(), "2001:db8::/48");
+        assert!(range.contains("2001:db8:0:ff::".parse().unwrap()));
+        assert!(IpRange::new(ip, 129).is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_expiring_bans() {
+        let address: SocketAddr = "10.1.2.3:9000".parse().unwrap();
+        let other_port: SocketAddr = "10.1.2.3:9001".parse().unwrap();
+        let range = IpRange::new(address.ip(), 24).unwrap();
+        let mut blocklist = Blocklist::default();
+
+        blocklist.block(address.into(), None);
+        assert!(blocklist.is_blocked(&address));
+        assert!(!blocklist.is_blocked(&other_port));
+
+        blocklist.block(range.into(), Some(Duration::from_secs(60)));
+        assert!(blocklist.is_blocked(&other_port));
+        assert_eq!(blocklist.bans().len(), 2);
+
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert!(!blocklist.is_blocked(&other_port));
+        assert_eq!(
+            blocklist.bans(),
+            vec![Ban {
+                source: address.into(),
+                expires_in: None,
+            }]
+        );
+
+        assert!(blocklist.unblock(&address.into()));
+        assert!(!blocklist.unblock(&address.into()));
+        assert!(!blocklist.is_blocked(&address));
+    }
+}
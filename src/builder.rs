@@ -0,0 +1,103 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::sync::Arc;
+
+use tokio::task;
+
+use crate::config::Config;
+use crate::handling::{BroadcastValidator, Hooks};
+use crate::transport::Transport;
+use crate::{
+    Identity, KadcastError, MessageReceiver, NetworkListen, Peer, Responder,
+};
+
+/// Builder for a [Peer] which needs application hooks besides its [Config].
+///
+/// [Peer::new] and [Peer::new_with_receiver] are shortcuts for a builder
+/// without any hook.
+pub struct PeerBuilder {
+    pub(crate) config: Config,
+    pub(crate) hooks: Hooks,
+    pub(crate) transport: Option<Arc<dyn Transport>>,
+    pub(crate) identity: Option<Identity>,
+}
+
+impl PeerBuilder {
+    pub(crate) fn new(config: Config) -> Self {
+        Self {
+            config,
+            hooks: Hooks::default(),
+            transport: None,
+            identity: None,
+        }
+    }
+
+    /// Set the [BroadcastValidator] consulted before any incoming broadcast
+    /// is notified and propagated.
+    pub fn validator<V: BroadcastValidator + 'static>(
+        mut self,
+        validator: V,
+    ) -> Self {
+        self.hooks.validator = Some(Arc::new(validator));
+        self
+    }
+
+    /// Set the [Responder] answering the requests sent by remote peers
+    /// through [Peer::request].
+    ///
+    /// Without a responder, incoming requests are discarded.
+    pub fn responder<R: Responder + 'static>(mut self, responder: R) -> Self {
+        self.hooks.responder = Some(Arc::new(responder));
+        self
+    }
+
+    /// Set the [Transport] used to exchange datagrams, instead of the
+    /// default UDP sockets.
+    ///
+    /// When set, [Config::listen_address] is ignored and
+    /// [NetworkConfig](crate::config::NetworkConfig) only affects the UDP
+    /// transport.
+    pub fn transport<T: Transport + 'static>(mut self, transport: T) -> Self {
+        self.transport = Some(Arc::new(transport));
+        self
+    }
+
+    /// Set the [Identity] of the peer.
+    ///
+    /// The peer id is derived from the public key of the identity instead of
+    /// [Config::public_address], and every message broadcasted or sent by the
+    /// peer is signed. Each signed datagram carries 128 more bytes, which
+    /// should be taken into account when tuning the FEC MTU.
+    pub fn identity(mut self, identity: Identity) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    /// Create the [Peer].
+    ///
+    /// * `listener` - The [NetworkListen] impl notified each time a broadcasted
+    ///   message is received from the network
+    pub fn build<L: NetworkListen + 'static>(
+        self,
+        listener: L,
+    ) -> Result<Peer, KadcastError> {
+        let (mut peer, listener_channel_rx) = Peer::start(self)?;
+        peer.tasks
+            .push(task::spawn(Peer::notifier(listener_channel_rx, listener)));
+        Ok(peer)
+    }
+
+    /// Create the [Peer] along with the [MessageReceiver] of the broadcasted
+    /// messages.
+    ///
+    /// See [Peer::new_with_receiver]
+    pub fn build_with_receiver(
+        self,
+    ) -> Result<(Peer, MessageReceiver), KadcastError> {
+        Peer::start(self)
+    }
+}
@@ -4,8 +4,12 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::path::PathBuf;
 use std::time::Duration;
+use std::{error, fmt};
 
+use semver::{Version, VersionReq};
 use serde_derive::{Deserialize, Serialize};
 
 use crate::transport::encoding::{
@@ -22,12 +26,34 @@ pub const BUCKET_DEFAULT_NODE_TTL_MILLIS: u64 = 30000;
 /// Default value after which a node can be evicted if requested
 pub const BUCKET_DEFAULT_NODE_EVICT_AFTER_MILLIS: u64 = 5000;
 
+/// Default max amount of nodes a bucket can contain
+pub const BUCKET_DEFAULT_K: usize = 20;
+
 /// Default value after which a bucket is considered idle
 pub const BUCKET_DEFAULT_TTL_SECS: u64 = 60 * 60;
 
+/// Default max amount of nodes of a bucket sharing the same subnet
+pub const BUCKET_DEFAULT_SUBNET_LIMIT: usize = 2;
+
+/// Default max amount of nodes of the routing table sharing the same subnet
+pub const TABLE_DEFAULT_SUBNET_LIMIT: usize = 10;
+
 /// Default behaviour for propagation of incoming broadcast messages
 pub const ENABLE_BROADCAST_PROPAGATION: bool = true;
 
+/// Default amount of nodes picked from each bucket when broadcasting
+pub const DEFAULT_BETA: usize = 3;
+
+/// Default amount of nodes contacted in parallel when looking for new nodes
+pub const DEFAULT_ALPHA: usize = 3;
+
+/// Default time a node lookup waits for the replies of each round
+pub const DEFAULT_LOOKUP_TIMEOUT_MILLIS: u64 = 1000;
+
+/// Default max requests answered concurrently by the
+/// [Responder](crate::Responder)
+pub const DEFAULT_MAX_CONCURRENT_REQUESTS: usize = 64;
+
 /// Default internal channel size
 pub const DEFAULT_CHANNEL_SIZE: usize = 1000;
 
@@ -35,12 +61,79 @@ pub const DEFAULT_SEND_RETRY_COUNT: u8 = 3;
 pub const DEFAULT_SEND_RETRY_SLEEP_MILLIS: u64 = 5;
 pub const DEFAULT_BLOCKLIST_REFRESH_SECS: u64 = 10;
 
+/// Default max replies per second to the sources without a valid address
+/// token
+pub const DEFAULT_UNVERIFIED_REPLIES_PER_SEC: u32 = 200;
+
+/// Default max handshakes per second authenticated for the encrypted sessions
+pub const DEFAULT_HANDSHAKES_PER_SEC: u32 = 100;
+
+/// Default interval between two routing table snapshots
+pub const DEFAULT_SNAPSHOT_INTERVAL_SECS: u64 = 5 * 60;
+
+/// Default score reached by a misbehaving source before being banned
+pub const DEFAULT_BAN_THRESHOLD: f64 = 100.0;
+
+/// Default time needed by a misbehaviour score to decay by half
+pub const DEFAULT_SCORE_HALF_LIFE_SECS: u64 = 60;
+
+/// Default duration of the bans of the misbehaving sources
+pub const DEFAULT_BAN_DURATION_SECS: u64 = 60 * 60;
+
 /// Default minimum peers required for network integration without bootstrapping
 pub const DEFAULT_MIN_PEERS_FOR_INTEGRATION: usize = 3;
 
 const DEFAULT_VERSION: &str = "0.0.1";
 const DEFAULT_VERSION_MATCH: &str = "*";
 
+const fn default_bucket_k() -> usize {
+    BUCKET_DEFAULT_K
+}
+
+const fn default_bucket_subnet_limit() -> usize {
+    BUCKET_DEFAULT_SUBNET_LIMIT
+}
+
+const fn default_table_subnet_limit() -> usize {
+    TABLE_DEFAULT_SUBNET_LIMIT
+}
+
+const fn default_beta() -> usize {
+    DEFAULT_BETA
+}
+
+const fn default_alpha() -> usize {
+    DEFAULT_ALPHA
+}
+
+const fn default_lookup_timeout() -> Duration {
+    Duration::from_millis(DEFAULT_LOOKUP_TIMEOUT_MILLIS)
+}
+
+const fn default_max_concurrent_requests() -> usize {
+    DEFAULT_MAX_CONCURRENT_REQUESTS
+}
+
+const fn default_unverified_replies_per_sec() -> u32 {
+    DEFAULT_UNVERIFIED_REPLIES_PER_SEC
+}
+
+const fn default_handshakes_per_sec() -> u32 {
+    DEFAULT_HANDSHAKES_PER_SEC
+}
+
+const fn default_ban_threshold() -> f64 {
+    DEFAULT_BAN_THRESHOLD
+}
+
+const fn default_score_half_life() -> Duration {
+    Duration::from_secs(DEFAULT_SCORE_HALF_LIFE_SECS)
+}
+
+const fn default_ban_duration() -> Option<Duration> {
+    Some(Duration::from_secs(DEFAULT_BAN_DURATION_SECS))
+}
+
 const fn default_min_peers() -> usize {
     DEFAULT_MIN_PEERS_FOR_INTEGRATION
 }
@@ -84,6 +177,56 @@ pub struct Config {
     ///
     /// Default value [ENABLE_BROADCAST_PROPAGATION]
     pub auto_propagate: bool,
+
+    /// Add a penalty to the reputation score of the sender of the broadcasts
+    /// rejected by the [BroadcastValidator](crate::BroadcastValidator), see
+    /// [ReputationConfig]
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub penalize_rejected: bool,
+
+    /// Discard the broadcasts not signed by their origin.
+    ///
+    /// Signed broadcasts are always verified, while unsigned ones are
+    /// accepted unless this is enabled. See [Identity](crate::Identity)
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub require_signatures: bool,
+
+    /// Redundancy factor for broadcast: max amount of nodes each broadcast
+    /// (or propagated) message is sent to for every bucket
+    ///
+    /// It can be overridden for a single message with
+    /// [BroadcastOptions::beta](crate::BroadcastOptions::beta)
+    ///
+    /// Default value [DEFAULT_BETA]
+    #[serde(default = "default_beta")]
+    pub beta: usize,
+
+    /// Redundancy factor for lookup: max amount of alive nodes contacted
+    /// when searching new nodes for idle buckets
+    ///
+    /// Default value [DEFAULT_ALPHA]
+    #[serde(default = "default_alpha")]
+    pub alpha: usize,
+
+    /// Max time [Peer::find_closest](crate::Peer::find_closest) waits for
+    /// the queried nodes to reply, before considering them unreachable
+    ///
+    /// Default value [DEFAULT_LOOKUP_TIMEOUT_MILLIS]
+    #[serde(default = "default_lookup_timeout")]
+    #[serde(with = "humantime_serde")]
+    pub lookup_timeout: Duration,
+
+    /// Max requests answered concurrently by the
+    /// [Responder](crate::Responder). The requests received while the limit
+    /// is reached are dropped.
+    ///
+    /// Default value [DEFAULT_MAX_CONCURRENT_REQUESTS]
+    #[serde(default = "default_max_concurrent_requests")]
+    pub max_concurrent_requests: usize,
     pub channel_size: usize,
 
     /// Send a `FindNodes` message to every Peer inside `Nodes` message
@@ -102,6 +245,14 @@ pub struct Config {
     /// FEC configuration
     pub fec: FECConfig,
 
+    /// Routing table snapshot configuration
+    #[serde(default)]
+    pub snapshot: SnapshotConfig,
+
+    /// Misbehaving sources scoring and banning configuration
+    #[serde(default)]
+    pub reputation: ReputationConfig,
+
     #[serde(default = "default_version")]
     pub version: String,
     #[serde(default = "default_version_match")]
@@ -116,19 +267,252 @@ impl Default for Config {
             listen_address: None,
             bootstrapping_nodes: vec![],
             auto_propagate: ENABLE_BROADCAST_PROPAGATION,
+            penalize_rejected: false,
+            require_signatures: false,
+            beta: default_beta(),
+            alpha: default_alpha(),
+            lookup_timeout: default_lookup_timeout(),
+            max_concurrent_requests: default_max_concurrent_requests(),
             channel_size: DEFAULT_CHANNEL_SIZE,
             recursive_discovery: true,
             network: NetworkConfig::default(),
             bucket: BucketConfig::default(),
             fec: FECConfig::default(),
+            snapshot: SnapshotConfig::default(),
+            reputation: ReputationConfig::default(),
             version: default_version(),
             version_match: default_version_match(),
         }
     }
 }
 
+/// Problem found by [Config::validate]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    /// The address of `field` can't be used
+    InvalidAddress {
+        field: &'static str,
+        address: String,
+        reason: String,
+    },
+    /// A bootstrapping node can't be resolved
+    UnresolvedBootstrapNode { address: String, reason: String },
+    /// The version or version requirement of `field` is not valid semver
+    InvalidVersion {
+        field: &'static str,
+        version: String,
+        reason: String,
+    },
+    /// The value of `field` must be greater than zero
+    ZeroValue(&'static str),
+    /// The duration of `shorter` must be shorter than the one of `longer`
+    DurationOrder {
+        shorter: &'static str,
+        longer: &'static str,
+    },
+    /// The FEC MTU is out of the supported range, the default one would be
+    /// used
+    MtuOutOfRange { mtu: u16, min: u16, max: u16 },
+    /// The FEC redundancy is negative or not a number
+    InvalidFecRedundancy(f32),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::InvalidAddress {
+                field,
+                address,
+                reason,
+            } => write!(f, "invalid {field} {address}: {reason}"),
+            ConfigError::UnresolvedBootstrapNode { address, reason } => {
+                write!(
+                    f,
+                    "unable to resolve bootstrap node {address}: {reason}"
+                )
+            }
+            ConfigError::InvalidVersion {
+                field,
+                version,
+                reason,
+            } => write!(f, "invalid {field} {version}: {reason}"),
+            ConfigError::ZeroValue(field) => {
+                write!(f, "{field} must be greater than zero")
+            }
+            ConfigError::DurationOrder { shorter, longer } => {
+                write!(f, "{shorter} must be shorter than {longer}")
+            }
+            ConfigError::MtuOutOfRange { mtu, min, max } => {
+                write!(f, "MTU={mtu} must be between {min} and {max}")
+            }
+            ConfigError::InvalidFecRedundancy(redundancy) => {
+                write!(f, "FEC redundancy={redundancy} must be positive")
+            }
+        }
+    }
+}
+
+impl error::Error for ConfigError {}
+
+impl Config {
+    /// Check the whole configuration, reporting every problem found.
+    ///
+    /// Besides the values preventing a [Peer](crate::Peer) from starting,
+    /// the values which would be silently replaced at runtime are reported
+    /// too. The bootstrapping nodes are resolved, which may block on DNS
+    /// queries.
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = vec![];
+
+        if let Err(e) = self.public_address.parse::<SocketAddr>() {
+            errors.push(ConfigError::InvalidAddress {
+                field: "public_address",
+                address: self.public_address.clone(),
+                reason: e.to_string(),
+            });
+        }
+        if let Some(address) = &self.listen_address
+            && let Err(reason) = resolve(address)
+        {
+            errors.push(ConfigError::InvalidAddress {
+                field: "listen_address",
+                address: address.clone(),
+                reason,
+            });
+        }
+        for address in &self.bootstrapping_nodes {
+            if let Err(reason) = resolve(address) {
+                errors.push(ConfigError::UnresolvedBootstrapNode {
+                    address: address.clone(),
+                    reason,
+                });
+            }
+        }
+
+        if let Err(e) = Version::parse(&self.version) {
+            errors.push(ConfigError::InvalidVersion {
+                field: "version",
+                version: self.version.clone(),
+                reason: e.to_string(),
+            });
+        }
+        if let Err(e) = VersionReq::parse(&self.version_match) {
+            errors.push(ConfigError::InvalidVersion {
+                field: "version_match",
+                version: self.version_match.clone(),
+                reason: e.to_string(),
+            });
+        }
+
+        let zero_values = [
+            ("beta", self.beta == 0),
+            ("alpha", self.alpha == 0),
+            ("lookup_timeout", self.lookup_timeout.is_zero()),
+            ("channel_size", self.channel_size == 0),
+            ("max_concurrent_requests", self.max_concurrent_requests == 0),
+            ("bucket.k", self.bucket.k == 0),
+            (
+                "bucket.bucket_subnet_limit",
+                self.bucket.bucket_subnet_limit == 0,
+            ),
+            (
+                "bucket.table_subnet_limit",
+                self.bucket.table_subnet_limit == 0,
+            ),
+            (
+                "network.udp_send_retry_count",
+                self.network.udp_send_retry_count == 0,
+            ),
+            (
+                "network.udp_send_backoff_timeout",
+                self.network.udp_send_backoff_timeout == Some(Duration::ZERO),
+            ),
+            (
+                "snapshot.interval",
+                self.snapshot.path.is_some()
+                    && self.snapshot.interval.is_zero(),
+            ),
+            (
+                "network.source_packets_per_sec",
+                self.network.source_packets_per_sec == Some(0),
+            ),
+            (
+                "network.source_bytes_per_sec",
+                self.network.source_bytes_per_sec == Some(0),
+            ),
+            (
+                "network.udp_send_peer_bytes_per_sec",
+                self.network.udp_send_peer_bytes_per_sec == Some(0),
+            ),
+            (
+                "network.udp_send_bytes_per_sec",
+                self.network.udp_send_bytes_per_sec == Some(0),
+            ),
+            (
+                "network.unverified_replies_per_sec",
+                self.network.unverified_replies_per_sec == 0,
+            ),
+            (
+                "network.handshakes_per_sec",
+                self.network.handshakes_per_sec == 0,
+            ),
+            (
+                "reputation.ban_threshold",
+                self.reputation.ban_threshold.is_nan()
+                    || self.reputation.ban_threshold <= 0.0,
+            ),
+            (
+                "reputation.score_half_life",
+                self.reputation.score_half_life.is_zero(),
+            ),
+            (
+                "reputation.ban_duration",
+                self.reputation.ban_duration == Some(Duration::ZERO),
+            ),
+        ];
+        errors.extend(
+            zero_values
+                .into_iter()
+                .filter(|(_, zero)| *zero)
+                .map(|(field, _)| ConfigError::ZeroValue(field)),
+        );
+        if self.bucket.node_evict_after >= self.bucket.node_ttl {
+            errors.push(ConfigError::DurationOrder {
+                shorter: "bucket.node_evict_after",
+                longer: "bucket.node_ttl",
+            });
+        }
+
+        errors.extend(TransportEncoder::validate(&self.fec.encoder));
+        errors.extend(TransportDecoder::validate(&self.fec.decoder));
+
+        match errors.is_empty() {
+            true => Ok(()),
+            false => Err(errors),
+        }
+    }
+}
+
+/// Resolve `address`, requiring at least one socket address
+fn resolve(address: &str) -> Result<(), String> {
+    match address.to_socket_addrs() {
+        Ok(mut addresses) => match addresses.next() {
+            Some(_) => Ok(()),
+            None => Err("no address found".to_string()),
+        },
+        Err(e) => Err(e.to_string()),
+    }
+}
+
 #[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub struct BucketConfig {
+    /// Max amount of nodes a bucket can contain (the Kademlia `K`). It is also
+    /// the max amount of nodes sent in reply to a `FindNodes` message
+    ///
+    /// Default value [BUCKET_DEFAULT_K]
+    #[serde(default = "default_bucket_k")]
+    pub k: usize,
+
     /// Sets the maximum duration for a node to be considered alive (no
     /// eviction will be requested).
     ///
@@ -157,17 +541,37 @@ pub struct BucketConfig {
     /// Default value [DEFAULT_MIN_PEERS_FOR_INTEGRATION]
     #[serde(default = "default_min_peers")]
     pub min_peers: usize,
+
+    /// Max amount of nodes of a bucket sharing the same subnet (/24 for
+    /// IPv4, /48 for IPv6), so that a single network operator can't fill a
+    /// bucket.
+    ///
+    /// Nodes with a loopback, private or link-local address are not limited.
+    ///
+    /// Default value [BUCKET_DEFAULT_SUBNET_LIMIT]
+    #[serde(default = "default_bucket_subnet_limit")]
+    pub bucket_subnet_limit: usize,
+
+    /// Max amount of nodes of the routing table sharing the same subnet, see
+    /// [BucketConfig::bucket_subnet_limit]
+    ///
+    /// Default value [TABLE_DEFAULT_SUBNET_LIMIT]
+    #[serde(default = "default_table_subnet_limit")]
+    pub table_subnet_limit: usize,
 }
 
 impl Default for BucketConfig {
     fn default() -> Self {
         Self {
+            k: default_bucket_k(),
             node_evict_after: Duration::from_millis(
                 BUCKET_DEFAULT_NODE_EVICT_AFTER_MILLIS,
             ),
             node_ttl: Duration::from_millis(BUCKET_DEFAULT_NODE_TTL_MILLIS),
             bucket_ttl: Duration::from_secs(BUCKET_DEFAULT_TTL_SECS),
             min_peers: default_min_peers(),
+            bucket_subnet_limit: default_bucket_subnet_limit(),
+            table_subnet_limit: default_table_subnet_limit(),
         }
     }
 }
@@ -181,6 +585,16 @@ pub struct FECConfig {
 #[derive(Serialize, Deserialize, Clone, Copy)]
 pub struct NetworkConfig {
     pub udp_recv_buffer_size: Option<usize>,
+    /// Min interval between two datagrams sent, whatever their destination
+    /// and size.
+    ///
+    /// As with the other pacing rates (see
+    /// [udp_send_peer_bytes_per_sec](NetworkConfig::udp_send_peer_bytes_per_sec)),
+    /// the datagrams exceeding it are delayed, except the ones of the
+    /// [Priority::Control](crate::Priority::Control) and
+    /// [Priority::High](crate::Priority::High) lanes.
+    ///
+    /// Default value `None`
     #[serde(default)]
     #[serde(with = "humantime_serde")]
     pub udp_send_backoff_timeout: Option<Duration>,
@@ -189,6 +603,221 @@ pub struct NetworkConfig {
     pub udp_send_retry_count: u8,
     #[serde(with = "humantime_serde")]
     pub blocklist_refresh_interval: Duration,
+
+    /// Encryption of the datagrams exchanged with the other peers.
+    ///
+    /// Encryption requires an [Identity](crate::Identity): if none is set
+    /// through [PeerBuilder::identity](crate::PeerBuilder::identity), a
+    /// random one is generated when the peer starts.
+    ///
+    /// Default value [Encryption::Disabled]
+    #[serde(default)]
+    pub encryption: Encryption,
+
+    /// Max handshakes per second authenticated for the encrypted sessions,
+    /// allowing bursts of up to one second worth of handshakes.
+    ///
+    /// Authenticating a new handshake costs a signature verification and a
+    /// key exchange, before its sender is known. The datagrams carrying a
+    /// handshake exceeding the limit are dropped.
+    ///
+    /// Default value [DEFAULT_HANDSHAKES_PER_SEC]
+    #[serde(default = "default_handshakes_per_sec")]
+    pub handshakes_per_sec: u32,
+
+    /// Max datagrams per second received from each source IP, allowing
+    /// bursts of up to one second worth of datagrams. The datagrams
+    /// exceeding the limit are dropped before being decoded.
+    ///
+    /// Default value `None` (unlimited)
+    #[serde(default)]
+    pub source_packets_per_sec: Option<u32>,
+
+    /// Max bytes per second received from each source IP, allowing bursts
+    /// of up to one second worth of bytes. The datagrams exceeding the limit
+    /// are dropped before being decoded.
+    ///
+    /// Default value `None` (unlimited)
+    #[serde(default)]
+    pub source_bytes_per_sec: Option<u64>,
+
+    /// Max replies per second to the `Ping` and `FindNodes` requests not
+    /// carrying a valid address token, which may come from a spoofed source.
+    ///
+    /// Every `Pong` and `Nodes` reply carries a token bound to its
+    /// destination, which the receiver echoes in its following requests. The
+    /// `Nodes` replies to the requests without a valid token contain a single
+    /// peer, so that they can't amplify the traffic towards a victim.
+    ///
+    /// Default value [DEFAULT_UNVERIFIED_REPLIES_PER_SEC]
+    #[serde(default = "default_unverified_replies_per_sec")]
+    pub unverified_replies_per_sec: u32,
+
+    /// Max bytes per second sent to each destination over UDP.
+    ///
+    /// The datagrams exceeding the rate are delayed, spreading the chunks of
+    /// the large payloads over time, while the datagrams to the other
+    /// destinations are sent meanwhile. The datagrams of the
+    /// [Priority::Control](crate::Priority::Control) and
+    /// [Priority::High](crate::Priority::High) lanes, such as the protocol
+    /// messages, are never delayed but count towards the rate.
+    ///
+    /// Default value `None` (unlimited)
+    #[serde(default)]
+    pub udp_send_peer_bytes_per_sec: Option<u64>,
+
+    /// Max bytes per second sent over UDP, to every destination.
+    ///
+    /// As with
+    /// [udp_send_peer_bytes_per_sec](NetworkConfig::udp_send_peer_bytes_per_sec),
+    /// the datagrams exceeding the rate are delayed, except the ones of the
+    /// [Priority::Control](crate::Priority::Control) and
+    /// [Priority::High](crate::Priority::High) lanes.
+    ///
+    /// Default value `None` (unlimited)
+    #[serde(default)]
+    pub udp_send_bytes_per_sec: Option<u64>,
+}
+
+/// Encryption of the datagrams exchanged with the other peers.
+///
+/// Peers with encryption enabled append a signed handshake to the plaintext
+/// datagrams they send, establishing a session with every peer replying with
+/// its own handshake. The following datagrams are encrypted with the session
+/// keys, while peers without encryption ignore the handshakes.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Encryption {
+    /// Datagrams are exchanged in plaintext
+    #[default]
+    Disabled,
+    /// Datagrams are encrypted towards the peers supporting it, while
+    /// plaintext datagrams are still accepted
+    Optional,
+    /// Plaintext datagrams are accepted only when carrying a valid handshake
+    /// along with a discovery message (Ping, Pong, FindNodes or Nodes), and
+    /// the other messages are never sent in plaintext
+    Required,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotConfig {
+    /// File where the routing table is saved, periodically and on
+    /// [Peer::shutdown](crate::Peer::shutdown).
+    ///
+    /// If the file exists when the [Peer](crate::Peer) is created, the nodes
+    /// it contains are contacted along with the bootstrappers. They are added
+    /// back to the routing table only once they reply.
+    ///
+    /// Default value `None` (snapshots disabled)
+    pub path: Option<PathBuf>,
+
+    /// Interval between two snapshots
+    ///
+    /// Default value [DEFAULT_SNAPSHOT_INTERVAL_SECS]
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+impl Default for SnapshotConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval: Duration::from_secs(DEFAULT_SNAPSHOT_INTERVAL_SECS),
+        }
+    }
+}
+
+/// Scoring of the sources sending invalid headers, undecodable datagrams,
+/// bogus RaptorQ chunks, messages failing their integrity check or, with
+/// [Config::penalize_rejected], broadcasts rejected by the
+/// [BroadcastValidator](crate::BroadcastValidator).
+///
+/// Every misbehaviour adds a penalty to the score of the source IP, which
+/// decays over time. Once the score reaches
+/// [ban_threshold](ReputationConfig::ban_threshold), the source is blocked
+/// (see [Peer::block_source](crate::Peer::block_source)) if
+/// [auto_ban](ReputationConfig::auto_ban) is enabled, and its score reset.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReputationConfig {
+    /// Block the sources reaching the ban threshold.
+    ///
+    /// The misbehaviours are detected before their source is authenticated,
+    /// so that forged datagrams with a spoofed source address can get an
+    /// honest peer banned.
+    ///
+    /// Default value `false`
+    #[serde(default)]
+    pub auto_ban: bool,
+
+    /// Score reached by a source before being banned
+    ///
+    /// Default value [DEFAULT_BAN_THRESHOLD]
+    #[serde(default = "default_ban_threshold")]
+    pub ban_threshold: f64,
+
+    /// Time needed by a score to decay by half
+    ///
+    /// Default value [DEFAULT_SCORE_HALF_LIFE_SECS]
+    #[serde(default = "default_score_half_life")]
+    #[serde(with = "humantime_serde")]
+    pub score_half_life: Duration,
+
+    /// Duration of the automatic bans, `None` for permanent bans (encoded as
+    /// `"permanent"`)
+    ///
+    /// Default value [DEFAULT_BAN_DURATION_SECS]
+    #[serde(default = "default_ban_duration")]
+    #[serde(with = "ban_duration")]
+    pub ban_duration: Option<Duration>,
+}
+
+impl Default for ReputationConfig {
+    fn default() -> Self {
+        Self {
+            auto_ban: false,
+            ban_threshold: default_ban_threshold(),
+            score_half_life: default_score_half_life(),
+            ban_duration: default_ban_duration(),
+        }
+    }
+}
+
+/// Encoding of [ReputationConfig::ban_duration], either a duration or
+/// `"permanent"`
+mod ban_duration {
+    use std::time::Duration;
+
+    use humantime_serde::re::humantime;
+    use serde::de::Error;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const PERMANENT: &str = "permanent";
+
+    pub(super) fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(duration) => serializer.serialize_str(
+                &humantime::format_duration(*duration).to_string(),
+            ),
+            None => serializer.serialize_str(PERMANENT),
+        }
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match String::deserialize(deserializer)?.as_str() {
+            PERMANENT => Ok(None),
+            duration => humantime::parse_duration(duration)
+                .map(Some)
+                .map_err(D::Error::custom),
+        }
+    }
 }
 
 impl Default for FECConfig {
@@ -211,6 +840,92 @@ impl Default for NetworkConfig {
             blocklist_refresh_interval: Duration::from_secs(
                 DEFAULT_BLOCKLIST_REFRESH_SECS,
             ),
+            encryption: Encryption::default(),
+            handshakes_per_sec: default_handshakes_per_sec(),
+            source_packets_per_sec: None,
+            source_bytes_per_sec: None,
+            unverified_replies_per_sec: default_unverified_replies_per_sec(),
+            udp_send_peer_bytes_per_sec: None,
+            udp_send_bytes_per_sec: None,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate() {
+        assert_eq!(Config::default().validate(), Ok(()));
+
+        let mut config = Config {
+            public_address: "localhost:9000".to_string(),
+            bootstrapping_nodes: vec!["127.0.0.1".to_string()],
+            version: "1".to_string(),
+            beta: 0,
+            alpha: 0,
+            lookup_timeout: Duration::ZERO,
+            channel_size: 0,
+            ..Default::default()
+        };
+        config.bucket.node_evict_after = config.bucket.node_ttl;
+        config.network.udp_send_retry_count = 0;
+        config.network.handshakes_per_sec = 0;
+        config.reputation.ban_threshold = f64::NAN;
+
+        let errors = config.validate().expect_err("invalid config");
+        let fields: Vec<_> = errors
+            .iter()
+            .map(|e| match e {
+                ConfigError::InvalidAddress { field, .. } => *field,
+                ConfigError::UnresolvedBootstrapNode { .. } => "bootstrap",
+                ConfigError::InvalidVersion { field, .. } => *field,
+                ConfigError::ZeroValue(field) => *field,
+                ConfigError::DurationOrder { shorter, .. } => *shorter,
+                ConfigError::MtuOutOfRange { .. } => "mtu",
+                ConfigError::InvalidFecRedundancy(_) => "redundancy",
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            [
+                "public_address",
+                "bootstrap",
+                "version",
+                "beta",
+                "alpha",
+                "lookup_timeout",
+                "channel_size",
+                "network.udp_send_retry_count",
+                "network.handshakes_per_sec",
+                "reputation.ban_threshold",
+                "bucket.node_evict_after",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reputation_serde() {
+        let config: ReputationConfig = toml::from_str("").unwrap();
+        assert!(!config.auto_ban);
+        assert_eq!(config.ban_duration, default_ban_duration());
+
+        let config: ReputationConfig =
+            toml::from_str("auto_ban = true\nban_duration = \"10m\"").unwrap();
+        assert!(config.auto_ban);
+        assert_eq!(config.ban_duration, Some(Duration::from_secs(600)));
+
+        let config = ReputationConfig {
+            ban_duration: None,
+            ..Default::default()
+        };
+        let encoded = toml::to_string(&config).unwrap();
+        assert!(encoded.contains("ban_duration = \"permanent\""));
+        let decoded: ReputationConfig = toml::from_str(&encoded).unwrap();
+        assert_eq!(decoded.ban_duration, None);
+        assert!(
+            toml::from_str::<ReputationConfig>("ban_duration = \"x\"").is_err()
+        );
+    }
+}
@@ -26,10 +26,11 @@ mod tests {
     use semver::Version;
 
     use super::Marshallable;
+    use crate::Identity;
     use crate::encoding::header::Header;
     use crate::encoding::message::Message;
     use crate::encoding::payload::{
-        BroadcastPayload, NodePayload, PeerEncodedInfo,
+        BroadcastPayload, NodePayload, PeerEncodedInfo, RequestPayload,
     };
     use crate::peer::PeerNode;
     use crate::tests::Result;
@@ -61,12 +62,17 @@ mod tests {
     #[test]
     fn test_encode_nodes() -> Result<()> {
         let peer = PeerNode::generate("192.168.0.1:666", 0)?;
-        let nodes = vec![
+        let nodes = [
             PeerNode::generate("192.168.1.1:666", 0)?,
             PeerNode::generate(
                 "[2001:0db8:85a3:0000:0000:8a2e:0370:7334]:666",
                 0,
             )?,
+            PeerNode::generate_with_identity(
+                "192.168.1.2:666",
+                &Identity::generate(),
+                0,
+            )?,
         ]
         .iter()
         .map(|f| f.as_peer_info())
@@ -79,6 +85,33 @@ mod tests {
         test_kadkast_marshal(a)
     }
 
+    #[test]
+    fn test_decode_nodes_without_keys() -> Result<()> {
+        let peers: Vec<_> = [
+            PeerNode::generate("192.168.1.1:666", 0)?,
+            PeerNode::generate("192.168.1.2:666", 0)?,
+        ]
+        .iter()
+        .map(|f| f.as_peer_info())
+        .collect();
+        let payload = NodePayload {
+            peers: peers.clone(),
+        };
+        let mut bytes = vec![];
+        payload.marshal_binary(&mut bytes)?;
+
+        // Payload sent by a peer predating the identities
+        let legacy = &bytes[..bytes.len() - peers.len()];
+        let decoded = NodePayload::unmarshal_binary(&mut &legacy[..])?;
+        assert_eq!(decoded, payload);
+
+        // Missing only some of the keys
+        let truncated = &bytes[..bytes.len() - 1];
+        NodePayload::unmarshal_binary(&mut &truncated[..])
+            .expect_err("NodePayload::unmarshal_binary should fail");
+        Ok(())
+    }
+
     #[test]
     fn test_encode_empty_nodes() -> Result<()> {
         let peer = PeerNode::generate("192.168.0.1:666", 0)?;
@@ -97,10 +130,53 @@ mod tests {
             BroadcastPayload {
                 height: 10,
                 gossip_frame: vec![3, 5, 6, 7],
+                origin: None,
             },
         );
         test_kadkast_marshal(a)
     }
+    #[test]
+    fn test_encode_signed_broadcast() -> Result<()> {
+        let identity = Identity::generate();
+        let peer =
+            PeerNode::generate_with_identity("192.168.0.1:666", &identity, 0)?;
+        let gossip_frame = vec![3, 5, 6, 7];
+        let payload = BroadcastPayload {
+            height: 10,
+            origin: Some(identity.sign(0, &gossip_frame)),
+            gossip_frame,
+        };
+        // The key proof is only encoded by the discovery messages
+        let header = peer.to_header();
+        let unproven = Header {
+            key_proof: None,
+            ..header
+        };
+        assert_eq!(
+            Message::broadcast(header, payload.clone()).bytes()?,
+            Message::broadcast(unproven, payload.clone()).bytes()?
+        );
+        test_kadkast_marshal(Message::broadcast(unproven, payload))?;
+        test_kadkast_marshal(Message::Ping(header, VERSION))
+    }
+    #[test]
+    fn test_encode_request() -> Result<()> {
+        let peer = PeerNode::generate("192.168.0.1:666", 0)?;
+        let payload = RequestPayload {
+            request_id: 42,
+            body: vec![3, 5, 6, 7],
+        };
+        test_kadkast_marshal(Message::Request(peer.to_header(), payload))
+    }
+    #[test]
+    fn test_encode_response() -> Result<()> {
+        let peer = PeerNode::generate("192.168.0.1:666", 0)?;
+        let payload = RequestPayload {
+            request_id: u64::MAX,
+            body: vec![],
+        };
+        test_kadkast_marshal(Message::Response(peer.to_header(), payload))
+    }
 
     fn test_kadkast_marshal(messge: Message) -> Result<()> {
         println!("orig: {:?}", messge);
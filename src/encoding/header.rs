@@ -7,6 +7,8 @@
 use std::io::{self, Error, Read, Write};
 
 use super::Marshallable;
+use crate::amplification::Token;
+use crate::identity::{PublicKey, SIGNATURE_LEN};
 use crate::kbucket::BinaryID;
 use crate::{K_ID_LEN_BYTES, K_NONCE_LEN};
 
@@ -15,7 +17,21 @@ pub struct Header {
     pub(crate) binary_id: BinaryID,
     pub(crate) sender_port: u16,
     pub(crate) network_id: u8,
-    pub(crate) reserved: [u8; 2],
+    /// Address token issued by the receiver, echoed in the requests to
+    /// prove the sender address (see
+    /// [AddressTokens](crate::amplification::AddressTokens))
+    pub(crate) token: Token,
+    /// Public key of the sender, if it runs with an
+    /// [Identity](crate::Identity).
+    ///
+    /// It is encoded by the message, right after the header.
+    pub(crate) public_key: Option<PublicKey>,
+    /// Signature of the sender public address by its public key (see
+    /// [Identity](crate::Identity)).
+    ///
+    /// It is only encoded by the discovery messages, right after the public
+    /// key.
+    pub(crate) key_proof: Option<[u8; SIGNATURE_LEN]>,
 }
 
 impl Header {
@@ -33,7 +49,7 @@ impl Marshallable for Header {
         writer.write_all(self.binary_id.nonce())?;
         writer.write_all(&self.sender_port.to_le_bytes())?;
         writer.write_all(&[self.network_id])?;
-        writer.write_all(&self.reserved)?;
+        writer.write_all(&self.token)?;
         Ok(())
     }
 
@@ -56,14 +72,16 @@ impl Marshallable for Header {
         reader.read_exact(&mut network_id)?;
         let network_id = network_id[0];
 
-        let mut reserved = [0; 2];
-        reader.read_exact(&mut reserved)?;
+        let mut token = [0; 2];
+        reader.read_exact(&mut token)?;
 
         Ok(Header {
             binary_id,
             sender_port,
             network_id,
-            reserved,
+            token,
+            public_key: None,
+            key_proof: None,
         })
     }
 }
@@ -10,7 +10,11 @@ use semver::Version;
 
 pub use super::Marshallable;
 pub use super::header::Header;
-pub(crate) use super::payload::{BroadcastPayload, NodePayload};
+pub(crate) use super::payload::{
+    BroadcastPayload, NodePayload, Origin, RequestPayload,
+};
+use crate::amplification::Token;
+use crate::identity::{PublicKey, SIGNATURE_LEN};
 use crate::kbucket::BinaryKey;
 
 // PingMsg wire Ping message id.
@@ -28,6 +32,23 @@ const ID_MSG_NODES: u8 = 3;
 // BroadcastMsg Message propagation type.
 const ID_MSG_BROADCAST: u8 = 10;
 
+// SignedBroadcastMsg Message propagation type, carrying the origin signature.
+const ID_MSG_SIGNED_BROADCAST: u8 = 11;
+
+// RequestMsg wire Request message id.
+const ID_MSG_REQUEST: u8 = 20;
+
+// ResponseMsg wire Response message id.
+const ID_MSG_RESPONSE: u8 = 21;
+
+// Flag set on the message id when the header is followed by the sender public
+// key.
+const FLAG_PUBLIC_KEY: u8 = 0x80;
+
+// Flag set on the id of a discovery message when the public key is followed by
+// the signature of the sender address.
+const FLAG_KEY_PROOF: u8 = 0x20;
+
 #[derive(Debug, PartialEq)]
 pub(crate) enum Message {
     Ping(Header, Version),
@@ -35,6 +56,8 @@ pub(crate) enum Message {
     FindNodes(Header, Version, BinaryKey),
     Nodes(Header, Version, NodePayload), //should we pass node[] as ref?
     Broadcast(Header, BroadcastPayload, [u8; 32]),
+    Request(Header, RequestPayload),
+    Response(Header, RequestPayload),
 }
 
 impl Message {
@@ -48,7 +71,12 @@ impl Message {
             Message::Pong(..) => ID_MSG_PONG,
             Message::FindNodes(..) => ID_MSG_FIND_NODES,
             Message::Nodes(..) => ID_MSG_NODES,
-            Message::Broadcast(..) => ID_MSG_BROADCAST,
+            Message::Broadcast(_, payload, _) => match payload.origin {
+                Some(_) => ID_MSG_SIGNED_BROADCAST,
+                None => ID_MSG_BROADCAST,
+            },
+            Message::Request(..) => ID_MSG_REQUEST,
+            Message::Response(..) => ID_MSG_RESPONSE,
         }
     }
 
@@ -59,6 +87,27 @@ impl Message {
             Message::FindNodes(header, ..) => header,
             Message::Nodes(header, ..) => header,
             Message::Broadcast(header, ..) => header,
+            Message::Request(header, ..) => header,
+            Message::Response(header, ..) => header,
+        }
+    }
+
+    /// Returns a copy of a `Ping` or `FindNodes` request carrying `token`,
+    /// `None` for the other messages
+    pub(crate) fn request_with_token(&self, token: Token) -> Option<Message> {
+        match self {
+            Message::Ping(header, version) => Some(Message::Ping(
+                Header { token, ..*header },
+                version.clone(),
+            )),
+            Message::FindNodes(header, version, target) => {
+                Some(Message::FindNodes(
+                    Header { token, ..*header },
+                    version.clone(),
+                    *target,
+                ))
+            }
+            _ => None,
         }
     }
 
@@ -68,6 +117,41 @@ impl Message {
         Ok(bytes)
     }
 
+    /// Check if `type_byte` identifies a message used to discover the
+    /// network (Ping, Pong, FindNodes or Nodes)
+    pub(crate) fn is_discovery(type_byte: u8) -> bool {
+        matches!(
+            type_byte & !(FLAG_PUBLIC_KEY | FLAG_KEY_PROOF),
+            ID_MSG_PING | ID_MSG_PONG | ID_MSG_FIND_NODES | ID_MSG_NODES
+        )
+    }
+
+    /// Read the type byte of a marshalled message, followed by its header
+    /// along with the public key and the key proof it carries
+    pub(crate) fn unmarshal_header<R: Read>(
+        reader: &mut R,
+    ) -> io::Result<(u8, Header)> {
+        let mut message_type = [0; 1];
+        reader.read_exact(&mut message_type)?;
+        let mut header = Header::unmarshal_binary(reader)?;
+        if message_type[0] & FLAG_PUBLIC_KEY != 0 {
+            let mut public_key = PublicKey::default();
+            reader.read_exact(&mut public_key)?;
+            header.public_key = Some(public_key);
+        }
+        if message_type[0] & FLAG_KEY_PROOF != 0 {
+            if header.public_key.is_none()
+                || !Message::is_discovery(message_type[0])
+            {
+                return Err(Error::other("Unexpected key proof"));
+            }
+            let mut key_proof = [0; SIGNATURE_LEN];
+            reader.read_exact(&mut key_proof)?;
+            header.key_proof = Some(key_proof);
+        }
+        Ok((message_type[0], header))
+    }
+
     pub(crate) fn version(&self) -> Option<&Version> {
         match self {
             Message::Ping(_, version) => Some(version),
@@ -111,8 +195,23 @@ impl Marshallable for semver::Version {
 
 impl Marshallable for Message {
     fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
-        writer.write_all(&[self.type_byte()])?;
-        self.header().marshal_binary(writer)?;
+        let header = self.header();
+        let key_proof = header.key_proof.filter(|_| {
+            self.version().is_some() && header.public_key.is_some()
+        });
+        let flags = match (header.public_key, key_proof) {
+            (Some(_), Some(_)) => FLAG_PUBLIC_KEY | FLAG_KEY_PROOF,
+            (Some(_), None) => FLAG_PUBLIC_KEY,
+            (None, _) => 0,
+        };
+        writer.write_all(&[self.type_byte() | flags])?;
+        header.marshal_binary(writer)?;
+        if let Some(public_key) = &header.public_key {
+            writer.write_all(public_key)?;
+        }
+        if let Some(key_proof) = &key_proof {
+            writer.write_all(key_proof)?;
+        }
         match self {
             Message::Ping(_, version) | Message::Pong(_, version) => {
                 version.marshal_binary(writer)?;
@@ -126,18 +225,22 @@ impl Marshallable for Message {
                 node_payload.marshal_binary(writer)?;
             }
             Message::Broadcast(_, broadcast_payload, ..) => {
+                if let Some(origin) = &broadcast_payload.origin {
+                    origin.marshal_binary(writer)?;
+                }
                 broadcast_payload.marshal_binary(writer)?;
             }
+            Message::Request(_, payload) | Message::Response(_, payload) => {
+                payload.marshal_binary(writer)?;
+            }
         };
         writer.flush()?;
         Ok(())
     }
 
     fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
-        let mut message_type = [0; 1];
-        reader.read_exact(&mut message_type)?;
-        let header = Header::unmarshal_binary(reader)?;
-        match message_type[0] {
+        let (type_byte, header) = Message::unmarshal_header(reader)?;
+        match type_byte & !(FLAG_PUBLIC_KEY | FLAG_KEY_PROOF) {
             ID_MSG_PING => {
                 let version = Version::unmarshal_binary(reader)?;
                 Ok(Message::Ping(header, version))
@@ -160,6 +263,22 @@ impl Marshallable for Message {
                 let payload = BroadcastPayload::unmarshal_binary(reader)?;
                 Ok(Message::broadcast(header, payload))
             }
+            ID_MSG_SIGNED_BROADCAST => {
+                let origin = Origin::unmarshal_binary(reader)?;
+                let payload = BroadcastPayload {
+                    origin: Some(origin),
+                    ..BroadcastPayload::unmarshal_binary(reader)?
+                };
+                Ok(Message::broadcast(header, payload))
+            }
+            ID_MSG_REQUEST => {
+                let payload = RequestPayload::unmarshal_binary(reader)?;
+                Ok(Message::Request(header, payload))
+            }
+            ID_MSG_RESPONSE => {
+                let payload = RequestPayload::unmarshal_binary(reader)?;
+                Ok(Message::Response(header, payload))
+            }
             unknown => Err(Error::other(format!(
                 "Invalid message type: '{}'",
                 unknown
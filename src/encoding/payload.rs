@@ -6,8 +6,12 @@
 
 pub(super) mod broadcast;
 pub(super) mod nodes;
+pub(super) mod request;
 pub use nodes::IpInfo;
 pub(crate) use nodes::PeerEncodedInfo;
 
-pub(crate) use crate::encoding::payload::broadcast::BroadcastPayload;
+pub(crate) use crate::encoding::payload::broadcast::{
+    BroadcastPayload, Origin,
+};
 pub(crate) use crate::encoding::payload::nodes::NodePayload;
+pub(crate) use crate::encoding::payload::request::RequestPayload;
@@ -7,57 +7,104 @@
 use std::io::{self, Read, Write};
 
 use crate::encoding::Marshallable;
+use crate::identity::{PUBLIC_KEY_LEN, PublicKey, SIGNATURE_LEN};
 
 const DEFAULT_ALLOCATION_SIZE: usize = 64 * 1024; // 64 KiB
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) struct BroadcastPayload {
     pub(crate) height: u8,
     pub(crate) gossip_frame: Vec<u8>,
+    /// Signature of the peer which originally broadcasted the frame, carried
+    /// unchanged by the propagating peers.
+    ///
+    /// It is encoded by the signed broadcast message only.
+    pub(crate) origin: Option<Origin>,
+}
+
+/// Public key of the peer originating a broadcast, along with its signature of
+/// the gossip frame
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub(crate) struct Origin {
+    pub(crate) public_key: PublicKey,
+    pub(crate) signature: [u8; SIGNATURE_LEN],
+}
+
+impl Marshallable for Origin {
+    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.public_key)?;
+        writer.write_all(&self.signature)?;
+        Ok(())
+    }
+
+    fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut public_key = [0; PUBLIC_KEY_LEN];
+        reader.read_exact(&mut public_key)?;
+        let mut signature = [0; SIGNATURE_LEN];
+        reader.read_exact(&mut signature)?;
+        Ok(Origin {
+            public_key,
+            signature,
+        })
+    }
 }
 
 impl Marshallable for BroadcastPayload {
     fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
         writer.write_all(&[self.height])?;
-        let len = self.gossip_frame.len() as u32;
-        writer.write_all(&len.to_le_bytes())?;
-        writer.write_all(&self.gossip_frame)?;
-        Ok(())
+        write_frame(writer, &self.gossip_frame)
     }
 
     fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
         let mut height_buf = [0; 1];
         reader.read_exact(&mut height_buf)?;
-        let mut gossip_length_buf = [0; 4];
-        reader.read_exact(&mut gossip_length_buf)?;
-        let gossip_length = u32::from_le_bytes(gossip_length_buf) as usize;
-
-        // To prevent inefficient memory usage, we read the gossip frame in
-        // chunks if its length exceeds a reasonable threshold.
-        let gossip_frame = if gossip_length > DEFAULT_ALLOCATION_SIZE {
-            let mut gossip_frame = Vec::with_capacity(DEFAULT_ALLOCATION_SIZE);
-            let mut bytes_left = gossip_length;
-            let mut buffer = [0u8; DEFAULT_ALLOCATION_SIZE];
-            while bytes_left > 0 {
-                let to_read = bytes_left.min(buffer.len());
-                reader.read_exact(&mut buffer[..to_read])?;
-                gossip_frame.extend_from_slice(&buffer[..to_read]);
-                bytes_left -= to_read;
-            }
-            gossip_frame
-        } else {
-            let mut gossip_frame = vec![0; gossip_length];
-            reader.read_exact(&mut gossip_frame)?;
-            gossip_frame
-        };
+        let gossip_frame = read_frame(reader)?;
 
         Ok(BroadcastPayload {
             height: height_buf[0],
             gossip_frame,
+            origin: None,
         })
     }
 }
 
+/// Write a frame prepended by its length
+pub(super) fn write_frame<W: Write>(
+    writer: &mut W,
+    frame: &[u8],
+) -> io::Result<()> {
+    let len = frame.len() as u32;
+    writer.write_all(&len.to_le_bytes())?;
+    writer.write_all(frame)?;
+    Ok(())
+}
+
+/// Read a frame written by [write_frame]
+pub(super) fn read_frame<R: Read>(reader: &mut R) -> io::Result<Vec<u8>> {
+    let mut frame_length_buf = [0; 4];
+    reader.read_exact(&mut frame_length_buf)?;
+    let frame_length = u32::from_le_bytes(frame_length_buf) as usize;
+
+    // To prevent inefficient memory usage, we read the frame in chunks if its
+    // length exceeds a reasonable threshold.
+    if frame_length > DEFAULT_ALLOCATION_SIZE {
+        let mut frame = Vec::with_capacity(DEFAULT_ALLOCATION_SIZE);
+        let mut bytes_left = frame_length;
+        let mut buffer = [0u8; DEFAULT_ALLOCATION_SIZE];
+        while bytes_left > 0 {
+            let to_read = bytes_left.min(buffer.len());
+            reader.read_exact(&mut buffer[..to_read])?;
+            frame.extend_from_slice(&buffer[..to_read]);
+            bytes_left -= to_read;
+        }
+        Ok(frame)
+    } else {
+        let mut frame = vec![0; frame_length];
+        reader.read_exact(&mut frame)?;
+        Ok(frame)
+    }
+}
+
 #[cfg(test)]
 mod tests {
 
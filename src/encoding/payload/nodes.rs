@@ -5,11 +5,12 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::convert::TryInto;
-use std::io::{self, Read, Write};
+use std::io::{self, ErrorKind, Read, Write};
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
 
 use crate::K_ID_LEN_BYTES;
 use crate::encoding::Marshallable;
+use crate::identity::PublicKey;
 use crate::kbucket::BinaryKey;
 
 #[derive(Debug, PartialEq)]
@@ -17,13 +18,18 @@ pub(crate) struct NodePayload {
     pub(crate) peers: Vec<PeerEncodedInfo>,
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub(crate) struct PeerEncodedInfo {
     pub(crate) ip: IpInfo,
     pub(crate) port: u16,
     pub(crate) id: BinaryKey,
+    /// Public key the id derives from, if the peer runs with an identity.
+    ///
+    /// Not part of the [Marshallable] encoding, see
+    /// [PeerEncodedInfo::marshal_public_key].
+    pub(crate) public_key: Option<PublicKey>,
 }
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone)]
 pub enum IpInfo {
     IPv4([u8; 4]),
     IPv6([u8; 16]),
@@ -44,6 +50,45 @@ impl PeerEncodedInfo {
             )),
         }
     }
+
+    /// Write the public key of the peer, as a flag followed by the key if
+    /// any
+    pub(crate) fn marshal_public_key<W: Write>(
+        &self,
+        writer: &mut W,
+    ) -> io::Result<()> {
+        match &self.public_key {
+            Some(public_key) => {
+                writer.write_all(&[1])?;
+                writer.write_all(public_key)
+            }
+            None => writer.write_all(&[0]),
+        }
+    }
+
+    /// Read the public key written by [PeerEncodedInfo::marshal_public_key]
+    pub(crate) fn unmarshal_public_key<R: Read>(
+        &mut self,
+        reader: &mut R,
+    ) -> io::Result<()> {
+        let mut flag = [0; 1];
+        reader.read_exact(&mut flag)?;
+        self.public_key = match flag[0] {
+            0 => None,
+            1 => {
+                let mut public_key = PublicKey::default();
+                reader.read_exact(&mut public_key)?;
+                Some(public_key)
+            }
+            flag => {
+                return Err(io::Error::new(
+                    ErrorKind::InvalidData,
+                    format!("Invalid public key flag {flag}"),
+                ));
+            }
+        };
+        Ok(())
+    }
 }
 
 impl Marshallable for PeerEncodedInfo {
@@ -88,7 +133,12 @@ impl Marshallable for PeerEncodedInfo {
         let port = u16::from_le_bytes(port);
         let mut id = [0; K_ID_LEN_BYTES];
         reader.read_exact(&mut id)?;
-        Ok(PeerEncodedInfo { ip, port, id })
+        Ok(PeerEncodedInfo {
+            ip,
+            port,
+            id,
+            public_key: None,
+        })
     }
 }
 
@@ -99,6 +149,9 @@ impl Marshallable for NodePayload {
         for peer in &self.peers {
             peer.marshal_binary(writer)?
         }
+        for peer in &self.peers {
+            peer.marshal_public_key(writer)?
+        }
         Ok(())
     }
 
@@ -109,6 +162,16 @@ impl Marshallable for NodePayload {
         for _ in 0..u16::from_le_bytes(len) {
             peers.push(PeerEncodedInfo::unmarshal_binary(reader)?)
         }
+        // The public keys are missing from the payloads sent by the peers
+        // predating the identities
+        for (i, peer) in peers.iter_mut().enumerate() {
+            match peer.unmarshal_public_key(reader) {
+                Err(e) if i == 0 && e.kind() == ErrorKind::UnexpectedEof => {
+                    break;
+                }
+                result => result?,
+            }
+        }
         Ok(NodePayload { peers })
     }
 }
@@ -0,0 +1,34 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io::{self, Read, Write};
+
+use super::broadcast::{read_frame, write_frame};
+use crate::encoding::Marshallable;
+
+/// Payload of both the `Request` and the `Response` messages
+#[derive(Debug, PartialEq)]
+pub(crate) struct RequestPayload {
+    pub(crate) request_id: u64,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Marshallable for RequestPayload {
+    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.request_id.to_le_bytes())?;
+        write_frame(writer, &self.body)
+    }
+
+    fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut request_id = [0; 8];
+        reader.read_exact(&mut request_id)?;
+        let body = read_frame(reader)?;
+        Ok(RequestPayload {
+            request_id: u64::from_le_bytes(request_id),
+            body,
+        })
+    }
+}
@@ -0,0 +1,65 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::AddrParseError;
+use std::{error, fmt, io};
+
+use crate::config::ConfigError;
+
+/// Errors preventing a [Peer](crate::Peer) from starting
+#[derive(Debug)]
+pub enum KadcastError {
+    /// The [public_address](crate::config::Config::public_address) is not a
+    /// valid socket address
+    InvalidAddress(String, AddrParseError),
+    /// The [version](crate::config::Config::version) is not a valid semver
+    /// version
+    InvalidVersion(String, semver::Error),
+    /// The [version_match](crate::config::Config::version_match) is not a
+    /// valid semver requirement
+    InvalidVersionMatch(String, semver::Error),
+    /// A socket could not be bound to the given address
+    Bind(String, io::Error),
+    /// The [Config](crate::config::Config) is not valid, see
+    /// [Config::validate](crate::config::Config::validate)
+    InvalidConfig(Vec<ConfigError>),
+}
+
+impl fmt::Display for KadcastError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KadcastError::InvalidAddress(address, e) => {
+                write!(f, "invalid address {address}: {e}")
+            }
+            KadcastError::InvalidVersion(version, e) => {
+                write!(f, "invalid version {version}: {e}")
+            }
+            KadcastError::InvalidVersionMatch(version_match, e) => {
+                write!(f, "invalid version match {version_match}: {e}")
+            }
+            KadcastError::Bind(address, e) => {
+                write!(f, "unable to bind {address}: {e}")
+            }
+            KadcastError::InvalidConfig(errors) => {
+                let errors: Vec<_> =
+                    errors.iter().map(ConfigError::to_string).collect();
+                write!(f, "invalid configuration: {}", errors.join(", "))
+            }
+        }
+    }
+}
+
+impl error::Error for KadcastError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            KadcastError::InvalidAddress(_, e) => Some(e),
+            KadcastError::InvalidVersion(_, e) => Some(e),
+            KadcastError::InvalidVersionMatch(_, e) => Some(e),
+            KadcastError::Bind(_, e) => Some(e),
+            KadcastError::InvalidConfig(_) => None,
+        }
+    }
+}
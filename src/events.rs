@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::BTreeSet;
+use std::net::SocketAddr;
+
+use tokio::sync::broadcast;
+
+use crate::blocklist::BlockedSource;
+use crate::kbucket::{
+    BinaryKey, BucketChange, BucketHeight, InsertOk, NodeInsertOk, Tree,
+};
+use crate::peer::{PeerInfo, PeerNode};
+
+/// Change of the routing table or of the blocklist, as notified by
+/// [Peer::events](crate::Peer::events)
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum PeerEvent {
+    /// A node has been inserted in the routing table
+    PeerAdded {
+        id: BinaryKey,
+        address: SocketAddr,
+        height: BucketHeight,
+    },
+    /// A message has been received from a node of the routing table
+    PeerRefreshed {
+        id: BinaryKey,
+        address: SocketAddr,
+        height: BucketHeight,
+    },
+    /// The least recently seen node of a full bucket has been pinged, and it
+    /// will be evicted unless it replies in time
+    PeerPendingEviction {
+        id: BinaryKey,
+        address: SocketAddr,
+        height: BucketHeight,
+    },
+    /// A node has been removed from the routing table, either because it
+    /// didn't reply before its eviction or because it has been idle for too
+    /// long
+    PeerEvicted {
+        id: BinaryKey,
+        address: SocketAddr,
+        height: BucketHeight,
+    },
+    /// A network source has been blocked, and the nodes it covers removed
+    /// from the routing table
+    PeerBlocked { source: BlockedSource },
+    /// The last node of a bucket has been removed
+    BucketEmpty { height: BucketHeight },
+}
+
+/// Receiver of the [PeerEvent]s, as returned by
+/// [Peer::events](crate::Peer::events)
+pub type EventReceiver = broadcast::Receiver<PeerEvent>;
+
+/// Sender of the [PeerEvent]s, shared by the tasks updating the routing table
+#[derive(Clone)]
+pub(crate) struct EventSender(broadcast::Sender<PeerEvent>);
+
+impl EventSender {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self(broadcast::channel(capacity).0)
+    }
+
+    pub(crate) fn subscribe(&self) -> EventReceiver {
+        self.0.subscribe()
+    }
+
+    pub(crate) fn emit(&self, event: PeerEvent) {
+        // Sending fails only if nobody is subscribed
+        let _ = self.0.send(event);
+    }
+
+    /// Emit the events of the changes performed on the routing table as a
+    /// side effect of the last operations
+    pub(crate) fn emit_table_changes(&self, table: &mut Tree<PeerInfo>) {
+        let changes = table.take_changes();
+        if changes.is_empty() {
+            return;
+        }
+        let mut shrunk = BTreeSet::new();
+        for (height, change) in changes {
+            let event = match change {
+                BucketChange::Flagged(id) => table.peer(&id).map(|node| {
+                    let (id, address) = identify(node);
+                    PeerEvent::PeerPendingEviction {
+                        id,
+                        address,
+                        height,
+                    }
+                }),
+                BucketChange::Promoted(id) => table.peer(&id).map(|node| {
+                    let (id, address) = identify(node);
+                    PeerEvent::PeerAdded {
+                        id,
+                        address,
+                        height,
+                    }
+                }),
+                BucketChange::Removed(node) => {
+                    shrunk.insert(height);
+                    let (id, address) = identify(&node);
+                    Some(PeerEvent::PeerEvicted {
+                        id,
+                        address,
+                        height,
+                    })
+                }
+            };
+            if let Some(event) = event {
+                self.emit(event);
+            }
+        }
+        self.emit_empty_buckets(table, shrunk);
+    }
+
+    /// Emit [PeerEvent::BucketEmpty] for the buckets at `heights` which
+    /// don't contain any node
+    pub(crate) fn emit_empty_buckets(
+        &self,
+        table: &Tree<PeerInfo>,
+        heights: impl IntoIterator<Item = BucketHeight>,
+    ) {
+        heights
+            .into_iter()
+            .filter(|&height| table.bucket_size(height) == 0)
+            .for_each(|height| self.emit(PeerEvent::BucketEmpty { height }));
+    }
+}
+
+/// Return the event of a node insertion (if any)
+pub(crate) fn insert_event(
+    result: &InsertOk<PeerInfo>,
+    height: BucketHeight,
+) -> Option<PeerEvent> {
+    match result {
+        NodeInsertOk::Inserted { inserted } => {
+            let (id, address) = identify(inserted);
+            Some(PeerEvent::PeerAdded {
+                id,
+                address,
+                height,
+            })
+        }
+        NodeInsertOk::Updated { updated, .. } => {
+            let (id, address) = identify(updated);
+            Some(PeerEvent::PeerRefreshed {
+                id,
+                address,
+                height,
+            })
+        }
+        NodeInsertOk::Pending { .. } | NodeInsertOk::NoAction => None,
+    }
+}
+
+fn identify(node: &PeerNode) -> (BinaryKey, SocketAddr) {
+    (*node.id().as_binary(), *node.value().address())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::config::BucketConfig;
+    use crate::tests::Result;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_table_events() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let config = BucketConfig {
+            node_ttl: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let mut table = Tree::new(root, config);
+        let node = PeerNode::generate("192.168.0.2:666", 0)?;
+        let (id, address) = identify(&node);
+        let height = table
+            .root()
+            .calculate_distance(&node)
+            .expect("a valid distance");
+        assert!(table.insert(node).is_ok());
+
+        let events = EventSender::new(10);
+        let mut receiver = events.subscribe();
+        events.emit_table_changes(&mut table);
+        assert!(receiver.try_recv().is_err());
+
+        tokio::time::advance(Duration::from_millis(200)).await;
+        table.remove_idle_nodes();
+        events.emit_table_changes(&mut table);
+        assert_eq!(
+            receiver.try_recv()?,
+            PeerEvent::PeerEvicted {
+                id,
+                address,
+                height
+            }
+        );
+        assert_eq!(receiver.try_recv()?, PeerEvent::BucketEmpty { height });
+        assert!(receiver.try_recv().is_err());
+        Ok(())
+    }
+}
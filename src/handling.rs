@@ -4,20 +4,39 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
+use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 
 use semver::{Version, VersionReq};
 use tokio::sync::mpsc::{Receiver, Sender};
+use tokio::sync::{Semaphore, broadcast};
+use tokio::task::{JoinHandle, JoinSet};
 use tracing::*;
 
+use crate::RwLock;
+use crate::amplification::{AddressTokens, Budget};
 use crate::config::Config;
 use crate::encoding::message::{
-    BroadcastPayload, Header, Message, NodePayload,
+    BroadcastPayload, Header, Message, NodePayload, Origin, RequestPayload,
 };
-use crate::kbucket::{BinaryKey, NodeInsertError, NodeInsertOk, Tree};
+use crate::events::{EventSender, insert_event};
+use crate::identity::PublicKey;
+use crate::kbucket::{
+    BinaryKey, BucketHeight, NodeInsertError, NodeInsertOk, Tree,
+};
+use crate::lookup::NodesReply;
+use crate::metrics::{Counter, Metrics};
 use crate::peer::{PeerInfo, PeerNode};
+use crate::reputation::{Misbehaviour, Reputation};
+use crate::request::{PendingRequests, Responder};
+use crate::transport::scheduler::Priority;
 use crate::transport::{MessageBeanIn, MessageBeanOut};
-use crate::{K_K, RwLock};
+
+/// Max amount of peers sent in reply to a `FindNodes` not carrying a valid
+/// address token, so that the reply is not much larger than the request
+const UNVERIFIED_MAX_NODES: usize = 1;
 
 /// Message metadata for incoming message notifications
 #[derive(Debug)]
@@ -25,6 +44,7 @@ pub struct MessageInfo {
     pub(crate) src: SocketAddr,
     pub(crate) height: u8,
     pub(crate) ray_id: [u8; 32],
+    pub(crate) origin: Option<Origin>,
 }
 
 impl MessageInfo {
@@ -42,15 +62,103 @@ impl MessageInfo {
     pub fn ray_id(&self) -> &[u8] {
         &self.ray_id
     }
+
+    /// Returns the public key of the peer which originally broadcasted the
+    /// message, if it has been signed.
+    ///
+    /// The signature is verified before the message is notified.
+    pub fn origin(&self) -> Option<&PublicKey> {
+        self.origin.as_ref().map(|origin| &origin.public_key)
+    }
+}
+
+/// Outcome of the validation of an incoming broadcast
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Validation {
+    /// The message is notified and propagated
+    Accept,
+    /// The message is discarded
+    Ignore,
+    /// The message is discarded and, if [Config::penalize_rejected] is
+    /// enabled, a penalty is added to the reputation score of its sender.
+    ///
+    /// The sender is the peer forwarding the message, not necessarily its
+    /// author: it is banned only once its score reaches
+    /// [ReputationConfig::ban_threshold](crate::config::ReputationConfig::ban_threshold),
+    /// if [ReputationConfig::auto_ban](crate::config::ReputationConfig::auto_ban)
+    /// is enabled.
+    Reject,
+}
+
+/// Future returned by [BroadcastValidator::validate]
+pub type ValidationFuture<'a> =
+    Pin<Box<dyn Future<Output = Validation> + Send + 'a>>;
+
+/// The [BroadcastValidator] trait is consulted whenever a broadcasted message
+/// is received from the network, before it is notified to the application
+/// and propagated to the lower buckets.
+///
+/// Incoming messages are processed one at a time, so the validation should
+/// complete quickly.
+pub trait BroadcastValidator: Send + Sync {
+    fn validate<'a>(
+        &'a self,
+        message: &'a [u8],
+        metadata: &'a MessageInfo,
+    ) -> ValidationFuture<'a>;
+}
+
+/// Application hooks registered through the [PeerBuilder](crate::PeerBuilder)
+#[derive(Default)]
+pub(crate) struct Hooks {
+    pub(crate) validator: Option<Arc<dyn BroadcastValidator>>,
+    pub(crate) responder: Option<Arc<dyn Responder>>,
+}
+
+/// Channels used by the [MessageHandler] to notify the rest of the peer
+pub(crate) struct Notifiers {
+    /// Incoming broadcasted messages, towards the application
+    pub(crate) listener: Sender<(Vec<u8>, MessageInfo)>,
+    /// `Nodes` replies, towards the running lookups
+    pub(crate) nodes_reply: broadcast::Sender<NodesReply>,
+    /// Responses, towards the pending requests
+    pub(crate) pending_requests: PendingRequests,
+    /// Routing table changes, towards the events subscribers
+    pub(crate) events: EventSender,
+    /// Handling metrics, towards the metrics registry
+    pub(crate) metrics: Arc<Metrics>,
+}
+
+/// Channels connecting the [MessageHandler] to the wire network
+pub(crate) struct Channels {
+    /// Messages received from the other peers
+    pub(crate) inbound: Receiver<MessageBeanIn>,
+    /// Messages to send to the other peers
+    pub(crate) outbound: Sender<MessageBeanOut>,
 }
 
 pub(crate) struct MessageHandler {
     my_header: Header,
     ktable: RwLock<Tree<PeerInfo>>,
+    reputation: Reputation,
+    tokens: AddressTokens,
     outbound_sender: Sender<MessageBeanOut>,
     listener_sender: Sender<(Vec<u8>, MessageInfo)>,
+    nodes_reply_sender: broadcast::Sender<NodesReply>,
+    pending_requests: PendingRequests,
+    events: EventSender,
+    metrics: Arc<Metrics>,
+    validator: Option<Arc<dyn BroadcastValidator>>,
+    responder: Option<Arc<dyn Responder>>,
+    /// Requests being answered, aborted along with the handler
+    requests: Mutex<JoinSet<()>>,
+    request_permits: Arc<Semaphore>,
     nodes_reply_fn: fn(Header, BinaryKey, Version) -> Message,
+    reply_budget: Mutex<Budget>,
     auto_propagate: bool,
+    penalize_rejected: bool,
+    require_signatures: bool,
+    beta: usize,
     version_req: VersionReq,
     my_version: Version,
 }
@@ -58,14 +166,17 @@ pub(crate) struct MessageHandler {
 impl MessageHandler {
     async fn new(
         ktable: RwLock<Tree<PeerInfo>>,
+        reputation: Reputation,
+        tokens: AddressTokens,
         outbound_sender: Sender<MessageBeanOut>,
-        listener_sender: Sender<(Vec<u8>, MessageInfo)>,
+        notifiers: Notifiers,
+        hooks: Hooks,
         config: &Config,
     ) -> Self {
         let version_req = VersionReq::parse(&config.version_match)
-            .expect("Invalid version req");
-        let my_version =
-            Version::parse(&config.version).expect("Invalid version");
+            .expect("Version req validated by Peer::start");
+        let my_version = Version::parse(&config.version)
+            .expect("Version validated by Peer::start");
 
         let nodes_reply_fn = match config.recursive_discovery {
             true => |header: Header, target: BinaryKey, version: Version| {
@@ -76,15 +187,36 @@ impl MessageHandler {
             },
         };
         let auto_propagate = config.auto_propagate;
+        let penalize_rejected = config.penalize_rejected;
+        let require_signatures = config.require_signatures;
+        let beta = config.beta;
         let my_header = ktable.read().await.root().to_header();
+        let reply_budget =
+            Mutex::new(Budget::new(config.network.unverified_replies_per_sec));
 
         Self {
             my_header,
             auto_propagate,
+            penalize_rejected,
+            require_signatures,
+            beta,
             ktable,
-            listener_sender,
+            reputation,
+            tokens,
+            listener_sender: notifiers.listener,
             outbound_sender,
+            nodes_reply_sender: notifiers.nodes_reply,
+            pending_requests: notifiers.pending_requests,
+            events: notifiers.events,
+            metrics: notifiers.metrics,
+            validator: hooks.validator,
+            responder: hooks.responder,
+            requests: Mutex::new(JoinSet::new()),
+            request_permits: Arc::new(Semaphore::new(
+                config.max_concurrent_requests,
+            )),
             nodes_reply_fn,
+            reply_budget,
             version_req,
             my_version,
         }
@@ -92,23 +224,26 @@ impl MessageHandler {
 
     pub(crate) fn start(
         ktable: RwLock<Tree<PeerInfo>>,
-        mut inbound_receiver: Receiver<MessageBeanIn>,
-        outbound_sender: Sender<MessageBeanOut>,
-        listener_sender: Sender<(Vec<u8>, MessageInfo)>,
+        reputation: Reputation,
+        tokens: AddressTokens,
+        channels: Channels,
+        notifiers: Notifiers,
+        hooks: Hooks,
         config: &Config,
-    ) {
+    ) -> JoinHandle<()> {
         let config = config.clone();
+        let Channels {
+            mut inbound,
+            outbound,
+        } = channels;
         tokio::spawn(async move {
             let handler = MessageHandler::new(
-                ktable,
-                outbound_sender,
-                listener_sender,
-                &config,
+                ktable, reputation, tokens, outbound, notifiers, hooks, &config,
             )
             .await;
             debug!("MessageHandler started");
             while let Some((message, mut remote_peer_addr)) =
-                inbound_receiver.recv().await
+                inbound.recv().await
             {
                 trace!("Handler received message {}", message.type_byte());
                 remote_peer_addr.set_port(message.header().sender_port);
@@ -116,15 +251,17 @@ impl MessageHandler {
                 let header = message.header();
                 let src = remote_peer_addr.ip();
                 if !PeerNode::verify_header(header, &src) {
+                    handler.metrics.inc(Counter::InvalidHeaders);
                     error!("Invalid Id {header:?} - from {src}");
+                    handler
+                        .reputation
+                        .penalize(src, Misbehaviour::InvalidHeader)
+                        .await;
                     continue;
                 }
 
-                let remote_peer = PeerNode::from_socket(
-                    remote_peer_addr,
-                    *message.header().binary_id(),
-                    message.header().network_id,
-                );
+                let remote_peer =
+                    PeerNode::from_header(remote_peer_addr, header);
 
                 match handler.handle_peer(remote_peer, &message).await {
                     Ok(_) => {}
@@ -135,6 +272,7 @@ impl MessageHandler {
                         )
                     }
                     Err(NodeInsertError::Invalid(n)) => {
+                        handler.metrics.inc(Counter::NodesRejected);
                         error!(
                             "Unable to insert node - INVALID {}",
                             n.value().address()
@@ -142,6 +280,7 @@ impl MessageHandler {
                         continue;
                     }
                     Err(NodeInsertError::MismatchNetwork(n)) => {
+                        handler.metrics.inc(Counter::NodesRejected);
                         error!(
                             "Unable to insert node - NETWORK MISMATCH {} - {}",
                             n.value().address(),
@@ -150,17 +289,25 @@ impl MessageHandler {
                         continue;
                     }
                     Err(NodeInsertError::MismatchVersion(n, version)) => {
+                        handler.metrics.inc(Counter::NodesRejected);
                         error!(
                             "Unable to insert node - VERSION MISMATCH {} - {version}",
                             n.value().address(),
                         );
                         continue;
                     }
+                    Err(NodeInsertError::SubnetLimit(n)) => {
+                        handler.metrics.inc(Counter::NodesRejected);
+                        warn!(
+                            "Unable to insert node - SUBNET LIMIT {}",
+                            n.value().address()
+                        )
+                    }
                 };
 
                 handler.handle_message(message, remote_peer_addr).await;
             }
-        });
+        })
     }
 
     async fn handle_peer(
@@ -169,6 +316,12 @@ impl MessageHandler {
         msg: &Message,
     ) -> Result<(), NodeInsertError<PeerNode>> {
         let mut table = self.ktable.write().await;
+        let height = table.root().calculate_distance(&remote_node);
+        let peer_id = *remote_node.id().as_binary();
+        let address = *remote_node.value().address();
+        let known_address = table
+            .peer(&peer_id)
+            .map(|node| node.value().address() == &address);
 
         // If it's not a BROADCAST then we should handle the version and
         // insert/update the routing table accordingly
@@ -181,35 +334,62 @@ impl MessageHandler {
                 ));
             }
 
-            table.insert(remote_node)?
+            // A keyed node is only inserted, or moved to another address,
+            // once it proves to own its key at the address it sends from
+            if known_address != Some(true) {
+                if !PeerNode::verify_key_proof(msg.header(), &address) {
+                    return Err(NodeInsertError::Invalid(remote_node));
+                }
+                if known_address == Some(false) {
+                    table.remove_peer(&peer_id);
+                }
+            }
+            table.insert(remote_node)
         } else {
-            // If it's BROADCAST, and it's a new node, we should PING it in
-            // order to know the version
-            let peer_id = remote_node.id().as_binary();
-            if table.has_peer(peer_id).is_none() {
+            // If it's BROADCAST, and it's a new node or a node seen at
+            // another address, we should PING it in order to know the version
+            // and to have it prove its key
+            if known_address != Some(true) {
                 self.outbound_sender
                     .send((
                         Message::Ping(self.my_header, self.my_version.clone()),
                         vec![*remote_node.value().address()],
+                        Priority::Control,
                     ))
                     .await
                     .unwrap_or_else(|e| {
                         error!("Unable to send PING to new node {e}")
                     });
-                NodeInsertOk::NoAction
+                Ok(NodeInsertOk::NoAction)
             } else {
                 // If it's BROADCAST, and the node is already known, we just
                 // refresh the routing table
-                table.refresh(remote_node)?
+                table.refresh(remote_node)
+            }
+        };
+
+        let (event, pending) = match result {
+            Ok(result) => (
+                height.and_then(|height| insert_event(&result, height)),
+                result.pending_eviction().map(|p| *p.value().address()),
+            ),
+            Err(e) => {
+                self.events.emit_table_changes(&mut table);
+                return Err(e);
             }
         };
+        self.events.emit_table_changes(&mut table);
+        if let Some(event) = event {
+            self.events.emit(event);
+        }
 
         // Ping the pending node (if any)
-        if let Some(pending) = result.pending_eviction() {
+        if let Some(pending) = pending {
             self.outbound_sender
                 .send((
                     Message::Ping(self.my_header, self.my_version.clone()),
-                    vec![*pending.value().address()],
+                    vec![pending],
+                    Priority::Control,
                 ))
                 .await
                 .unwrap_or_else(|e| {
@@ -224,53 +404,199 @@ impl MessageHandler {
         message: Message,
         remote_node_addr: SocketAddr,
     ) {
+        let token = message.header().token;
         match message {
-            Message::Ping(..) => self.handle_ping(remote_node_addr).await,
-            Message::Pong(..) => {}
-            Message::FindNodes(_, _, target) => {
-                self.handle_find_nodes(remote_node_addr, &target).await
+            Message::Ping(..) => {
+                let verified = self.tokens.verify(&remote_node_addr, token);
+                self.handle_ping(remote_node_addr, verified).await
+            }
+            Message::Pong(..) => {
+                self.tokens.record(remote_node_addr, token);
+            }
+            Message::FindNodes(header, _, target) => {
+                let verified = self.tokens.verify(&remote_node_addr, token);
+                let requester = header.binary_id().as_binary();
+                self.handle_find_nodes(
+                    remote_node_addr,
+                    requester,
+                    &target,
+                    verified,
+                )
+                .await
+            }
+            Message::Nodes(header, _, nodes) => {
+                let first_token = self.tokens.record(remote_node_addr, token);
+                // Our request didn't carry any token, so the reply was
+                // limited: ask again, now that our address is proven
+                if first_token && !nodes.peers.is_empty() {
+                    self.find_self(remote_node_addr).await;
+                }
+                self.notify_lookups(&header, &nodes);
+                self.handle_nodes(nodes).await
             }
-            Message::Nodes(_, _, nodes) => self.handle_nodes(nodes).await,
             Message::Broadcast(_, payload, ray_id) => {
                 self.handle_broadcast(remote_node_addr, payload, ray_id)
                     .await
             }
+            Message::Request(_, payload) => {
+                self.handle_request(remote_node_addr, payload)
+            }
+            Message::Response(_, payload) => self.pending_requests.complete(
+                payload.request_id,
+                remote_node_addr,
+                payload.body,
+            ),
         }
     }
 
-    async fn handle_ping(&self, remote_node_addr: SocketAddr) {
+    /// Check if a reply to a request not carrying a valid token fits in the
+    /// reply budget
+    fn unverified_reply_allowed(&self, remote_node_addr: SocketAddr) -> bool {
+        let allowed = self.reply_budget.lock().expect("Unpoisoned lock").take();
+        if !allowed {
+            self.metrics.inc(Counter::RepliesThrottled);
+            debug!(
+                "Reply budget exhausted, not replying to {remote_node_addr}"
+            );
+        }
+        allowed
+    }
+
+    /// Header of a reply to `remote_node_addr`, carrying its address token
+    fn reply_header(&self, remote_node_addr: &SocketAddr) -> Header {
+        Header {
+            token: self.tokens.issue(remote_node_addr),
+            ..self.my_header
+        }
+    }
+
+    async fn handle_ping(&self, remote_node_addr: SocketAddr, verified: bool) {
+        if !verified && !self.unverified_reply_allowed(remote_node_addr) {
+            return;
+        }
+        let header = self.reply_header(&remote_node_addr);
         self.outbound_sender
             .send((
-                Message::Pong(self.my_header, self.my_version.clone()),
+                Message::Pong(header, self.my_version.clone()),
                 vec![remote_node_addr],
+                Priority::Control,
             ))
             .await
             .unwrap_or_else(|e| error!("Unable to send Pong {e}"));
     }
 
+    /// Look for the nodes closest to this peer through `remote_node_addr`
+    async fn find_self(&self, remote_node_addr: SocketAddr) {
+        let target = *self.my_header.binary_id().as_binary();
+        self.outbound_sender
+            .send((
+                Message::FindNodes(
+                    self.my_header,
+                    self.my_version.clone(),
+                    target,
+                ),
+                vec![remote_node_addr],
+                Priority::Control,
+            ))
+            .await
+            .unwrap_or_else(|e| error!("Unable to send FindNodes {e}"));
+    }
+
+    /// Let the [Responder] (if any) answer the request, without blocking the
+    /// incoming messages meanwhile.
+    ///
+    /// The request is dropped if [Config::max_concurrent_requests] are
+    /// already being answered.
+    fn handle_request(
+        &self,
+        remote_node_addr: SocketAddr,
+        req: RequestPayload,
+    ) {
+        self.metrics.inc(Counter::RequestsReceived);
+        let Some(responder) = self.responder.clone() else {
+            debug!("No responder for request from {remote_node_addr}");
+            return;
+        };
+        let Ok(permit) = self.request_permits.clone().try_acquire_owned()
+        else {
+            debug!("Dropping request from {remote_node_addr}");
+            self.metrics.inc(Counter::RequestsDropped);
+            return;
+        };
+        let header = self.my_header;
+        let outbound_sender = self.outbound_sender.clone();
+        let mut requests = self.requests.lock().expect("Unpoisoned lock");
+        while requests.try_join_next().is_some() {}
+        requests.spawn(async move {
+            let _permit = permit;
+            let Some(body) =
+                responder.respond(&req.body, remote_node_addr).await
+            else {
+                return;
+            };
+            let payload = RequestPayload {
+                request_id: req.request_id,
+                body,
+            };
+            outbound_sender
+                .send((
+                    Message::Response(header, payload),
+                    vec![remote_node_addr],
+                    Priority::Normal,
+                ))
+                .await
+                .unwrap_or_else(|e| error!("Unable to send Response {e}"));
+        });
+    }
+
     async fn handle_find_nodes(
         &self,
         remote_node_addr: SocketAddr,
+        requester: &BinaryKey,
         target: &BinaryKey,
+        verified: bool,
     ) {
-        let peers = self
-            .ktable
-            .read()
-            .await
-            .closest_peers::<K_K>(target)
-            .map(|p| p.as_peer_info())
-            .collect();
+        if !verified && !self.unverified_reply_allowed(remote_node_addr) {
+            return;
+        }
+        let peers = {
+            let table = self.ktable.read().await;
+            let k = table.bucket_k();
+            let limit = match verified {
+                true => k,
+                false => UNVERIFIED_MAX_NODES,
+            };
+            table
+                .closest_peers(target, k + 1)
+                .filter(|p| p.id().as_binary() != requester)
+                .take(limit)
+                .map(|p| p.as_peer_info())
+                .collect()
+        };
         let message = Message::Nodes(
-            self.my_header,
+            self.reply_header(&remote_node_addr),
             self.my_version.clone(),
             NodePayload { peers },
         );
         self.outbound_sender
-            .send((message, vec![remote_node_addr]))
+            .send((message, vec![remote_node_addr], Priority::Control))
             .await
             .unwrap_or_else(|e| error!("Unable to send Nodes {e}"));
     }
 
+    /// Forward a `Nodes` reply to the running lookups (if any)
+    fn notify_lookups(&self, header: &Header, nodes: &NodePayload) {
+        if self.nodes_reply_sender.receiver_count() == 0 {
+            return;
+        }
+        let reply = NodesReply {
+            from: *header.binary_id().as_binary(),
+            peers: nodes.peers.clone(),
+        };
+        // Sending fails only if the lookups completed in the meantime
+        let _ = self.nodes_reply_sender.send(reply);
+    }
+
     async fn handle_nodes(&self, nodes: NodePayload) {
         let peers = nodes.peers;
         if peers.is_empty() {
@@ -283,7 +609,7 @@ impl MessageHandler {
             .filter(|&n| &n.id != self.my_header.binary_id().as_binary())
             // Limit the number of peers accepted in a single Nodes message to a
             // reasonable bound
-            .take(K_K * 2)
+            .take(reader.bucket_k() * 2)
             .filter(|&n| {
                 let h = self.my_header.binary_id().calculate_distance(&n.id);
                 match h {
@@ -304,6 +630,7 @@ impl MessageHandler {
                         self.my_version.clone(),
                     ),
                     vec![n.to_socket_address()],
+                    Priority::Control,
                 )
             })
             .collect();
@@ -320,8 +647,10 @@ impl MessageHandler {
         payload: BroadcastPayload,
         ray_id: [u8; 32],
     ) {
+        self.metrics.inc(Counter::BroadcastsReceived);
         let height = payload.height;
         let gossip_frame = payload.gossip_frame;
+        let origin = payload.origin;
         debug!(
             event = "handle broadcast",
             height,
@@ -329,14 +658,63 @@ impl MessageHandler {
             ray = hex::encode(ray_id)
         );
 
+        // Authenticate the origin before notifying or propagating anything
+        let authentic = match &origin {
+            Some(origin) => {
+                origin.verify(self.my_header.network_id, &gossip_frame)
+            }
+            None => !self.require_signatures,
+        };
+        if !authentic {
+            self.metrics.inc(Counter::InvalidSignatures);
+            warn!(
+                event = "broadcast not authenticated",
+                src = %src,
+                signed = origin.is_some(),
+                ray = hex::encode(ray_id)
+            );
+            return;
+        }
+
         // Aggregate message + metadata for lib client
         let msg = gossip_frame.clone();
         let md = MessageInfo {
             src,
             height,
             ray_id,
+            origin,
         };
 
+        // Let the lib client validate the message before notifying it and
+        // propagating it any further
+        if let Some(validator) = &self.validator {
+            match validator.validate(&msg, &md).await {
+                Validation::Accept => {}
+                Validation::Ignore => {
+                    self.metrics.inc(Counter::BroadcastsDiscarded);
+                    debug!(
+                        event = "broadcast ignored",
+                        ray = hex::encode(ray_id)
+                    );
+                    return;
+                }
+                Validation::Reject => {
+                    self.metrics.inc(Counter::BroadcastsDiscarded);
+                    warn!(
+                        event = "broadcast rejected",
+                        src = %src,
+                        ray = hex::encode(ray_id)
+                    );
+                    if self.penalize_rejected {
+                        self.reputation
+                            .penalize(src.ip(), Misbehaviour::RejectedBroadcast)
+                            .await;
+                    }
+                    return;
+                }
+            }
+        }
+
         // Notify lib client
         self.listener_sender
             .send((msg, md))
@@ -347,24 +725,17 @@ impl MessageHandler {
             let new_height = height - 1;
             trace!("Extracting for height {new_height}");
 
-            let messages: Vec<_> = {
+            let messages = {
                 let table_read = self.ktable.read().await;
-                let target_nodes = table_read.extract(Some(new_height));
-
-                target_nodes
-                    .map(|(height, nodes)| {
-                        //FIX_ME: avoid clone
-                        let gossip_frame = gossip_frame.clone();
-                        let payload = BroadcastPayload {
-                            height,
-                            gossip_frame,
-                        };
-                        let msg = Message::broadcast(self.my_header, payload);
-                        let targets =
-                            nodes.map(|node| *node.value().address()).collect();
-                        (msg, targets)
-                    })
-                    .collect()
+                extract_broadcast(
+                    &table_read,
+                    self.my_header,
+                    &gossip_frame,
+                    origin,
+                    Some(new_height),
+                    self.beta,
+                    Priority::Normal,
+                )
             };
 
             for msg in messages {
@@ -376,3 +747,31 @@ impl MessageHandler {
         }
     }
 }
+
+/// Build the broadcast messages for at most `beta` nodes picked from each
+/// bucket up to `max_height` (inclusive). Each message carries the height of
+/// the bucket its targets belong to, along with the `origin` signature (if
+/// any), and is sent with `priority`.
+pub(crate) fn extract_broadcast(
+    ktable: &Tree<PeerInfo>,
+    header: Header,
+    gossip_frame: &[u8],
+    origin: Option<Origin>,
+    max_height: Option<BucketHeight>,
+    beta: usize,
+    priority: Priority,
+) -> Vec<MessageBeanOut> {
+    ktable
+        .extract(max_height, beta)
+        .map(|(height, nodes)| {
+            let payload = BroadcastPayload {
+                height,
+                gossip_frame: gossip_frame.to_vec(),
+                origin,
+            };
+            let msg = Message::broadcast(header, payload);
+            let targets = nodes.map(|node| *node.value().address()).collect();
+            (msg, targets, priority)
+        })
+        .collect()
+}
@@ -0,0 +1,222 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::encoding::payload::Origin;
+
+/// Length of an ed25519 public key
+pub const PUBLIC_KEY_LEN: usize = 32;
+
+/// Length of an ed25519 signature
+pub(crate) const SIGNATURE_LEN: usize = 64;
+
+/// Ed25519 public key of a [Peer](crate::Peer) running with an [Identity]
+pub type PublicKey = [u8; PUBLIC_KEY_LEN];
+
+// Domain separation of the broadcast signatures
+const BROADCAST_CONTEXT: &[u8] = b"kadcast-broadcast";
+
+// Domain separation of the session handshake signatures
+const HANDSHAKE_CONTEXT: &[u8] = b"kadcast-handshake";
+
+// Domain separation of the address signatures
+const ADDRESS_CONTEXT: &[u8] = b"kadcast-address";
+
+/// Ed25519 key pair identifying a [Peer](crate::Peer) in the network.
+///
+/// A peer built with an identity (see
+/// [PeerBuilder::identity](crate::PeerBuilder::identity)) derives its id from
+/// the public key instead of its address, announces the key in the header of
+/// every message and signs the messages it broadcasts, so that the receivers
+/// can authenticate their origin.
+///
+/// Its discovery messages also carry the signature of its public address,
+/// which the receivers check before adding the peer to their routing table
+/// or moving it to another address.
+#[derive(Clone)]
+pub struct Identity {
+    signing_key: SigningKey,
+}
+
+impl Identity {
+    /// Generate a new random identity
+    pub fn generate() -> Self {
+        let signing_key = SigningKey::generate(&mut rand::thread_rng());
+        Self { signing_key }
+    }
+
+    /// Restore the identity matching `secret`, as returned by
+    /// [Identity::secret]
+    pub fn from_secret(secret: [u8; 32]) -> Self {
+        let signing_key = SigningKey::from_bytes(&secret);
+        Self { signing_key }
+    }
+
+    /// Returns the secret key, to persist the identity
+    pub fn secret(&self) -> [u8; 32] {
+        self.signing_key.to_bytes()
+    }
+
+    /// Returns the public key announced to the other peers
+    pub fn public_key(&self) -> PublicKey {
+        self.signing_key.verifying_key().to_bytes()
+    }
+
+    /// Sign a broadcasted `frame` on behalf of its origin
+    pub(crate) fn sign(&self, network_id: u8, frame: &[u8]) -> Origin {
+        let bytes = signed_bytes(BROADCAST_CONTEXT, network_id, frame);
+        Origin {
+            public_key: self.public_key(),
+            signature: self.signing_key.sign(&bytes).to_bytes(),
+        }
+    }
+
+    /// Sign the ephemeral key offered to establish the encrypted sessions,
+    /// along with its creation time
+    pub(crate) fn sign_handshake(
+        &self,
+        network_id: u8,
+        ephemeral: &[u8; 32],
+        created: u64,
+    ) -> [u8; SIGNATURE_LEN] {
+        let data = handshake_bytes(ephemeral, created);
+        let bytes = signed_bytes(HANDSHAKE_CONTEXT, network_id, &data);
+        self.signing_key.sign(&bytes).to_bytes()
+    }
+
+    /// Sign the public `address` of the peer, to prove that it owns the key
+    /// at this address
+    pub(crate) fn sign_address(
+        &self,
+        network_id: u8,
+        address: &SocketAddr,
+    ) -> [u8; SIGNATURE_LEN] {
+        let bytes =
+            signed_bytes(ADDRESS_CONTEXT, network_id, &address_bytes(address));
+        self.signing_key.sign(&bytes).to_bytes()
+    }
+}
+
+impl fmt::Debug for Identity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Identity")
+            .field("public_key", &hex::encode(self.public_key()))
+            .finish_non_exhaustive()
+    }
+}
+
+impl Origin {
+    /// Check that the origin signed `frame`
+    pub(crate) fn verify(&self, network_id: u8, frame: &[u8]) -> bool {
+        let bytes = signed_bytes(BROADCAST_CONTEXT, network_id, frame);
+        verify(&self.public_key, &bytes, &self.signature)
+    }
+}
+
+/// Check that `public_key` signed the `ephemeral` key of a handshake, along
+/// with its creation time
+pub(crate) fn verify_handshake(
+    public_key: &PublicKey,
+    network_id: u8,
+    ephemeral: &[u8; 32],
+    created: u64,
+    signature: &[u8; SIGNATURE_LEN],
+) -> bool {
+    let data = handshake_bytes(ephemeral, created);
+    let bytes = signed_bytes(HANDSHAKE_CONTEXT, network_id, &data);
+    verify(public_key, &bytes, signature)
+}
+
+fn handshake_bytes(ephemeral: &[u8; 32], created: u64) -> Vec<u8> {
+    [&ephemeral[..], &created.to_le_bytes()].concat()
+}
+
+/// Check that `public_key` signed `address` as its public address
+pub(crate) fn verify_address(
+    public_key: &PublicKey,
+    network_id: u8,
+    address: &SocketAddr,
+    signature: &[u8; SIGNATURE_LEN],
+) -> bool {
+    let bytes =
+        signed_bytes(ADDRESS_CONTEXT, network_id, &address_bytes(address));
+    verify(public_key, &bytes, signature)
+}
+
+// IPv4-mapped addresses are signed as their IPv4 counterpart
+fn address_bytes(address: &SocketAddr) -> Vec<u8> {
+    let ip = match address.ip().to_canonical() {
+        IpAddr::V4(ip) => ip.octets().to_vec(),
+        IpAddr::V6(ip) => ip.octets().to_vec(),
+    };
+    [&ip[..], &address.port().to_le_bytes()].concat()
+}
+
+fn verify(
+    public_key: &PublicKey,
+    bytes: &[u8],
+    signature: &[u8; SIGNATURE_LEN],
+) -> bool {
+    let Ok(key) = VerifyingKey::from_bytes(public_key) else {
+        return false;
+    };
+    let signature = Signature::from_bytes(signature);
+    key.verify(bytes, &signature).is_ok()
+}
+
+/// Bytes covered by a signature in the given `context`.
+///
+/// The network id is included, so that a signature can't be replayed on
+/// another network.
+fn signed_bytes(context: &[u8], network_id: u8, data: &[u8]) -> Vec<u8> {
+    [context, &[network_id], data].concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_broadcast_signature() {
+        let identity = Identity::generate();
+        let restored = Identity::from_secret(identity.secret());
+        assert_eq!(identity.public_key(), restored.public_key());
+
+        let origin = identity.sign(0, b"message");
+        assert!(origin.verify(0, b"message"));
+        assert!(!origin.verify(1, b"message"));
+        assert!(!origin.verify(0, b"tampered"));
+
+        let impostor = Origin {
+            public_key: Identity::generate().public_key(),
+            ..origin
+        };
+        assert!(!impostor.verify(0, b"message"));
+    }
+
+    #[test]
+    fn test_address_signature() {
+        let identity = Identity::generate();
+        let address = "10.0.0.1:666".parse().unwrap();
+        let signature = identity.sign_address(0, &address);
+        let public_key = identity.public_key();
+        assert!(verify_address(&public_key, 0, &address, &signature));
+        let mapped = "[::ffff:10.0.0.1]:666".parse().unwrap();
+        assert!(verify_address(&public_key, 0, &mapped, &signature));
+
+        assert!(!verify_address(&public_key, 1, &address, &signature));
+        let moved = "10.0.0.2:666".parse().unwrap();
+        assert!(!verify_address(&public_key, 0, &moved, &signature));
+        let other_port = "10.0.0.1:667".parse().unwrap();
+        assert!(!verify_address(&public_key, 0, &other_port, &signature));
+        let impostor = Identity::generate().public_key();
+        assert!(!verify_address(&impostor, 0, &address, &signature));
+    }
+}
@@ -6,29 +6,59 @@
 
 use std::collections::HashMap;
 use std::collections::hash_map::Entry;
+use std::net::IpAddr;
 
 use bucket::Bucket;
+pub(crate) use bucket::BucketChange;
 pub use bucket::{InsertError, InsertOk, NodeInsertError, NodeInsertOk};
 use itertools::Itertools;
-pub use key::{BinaryID, BinaryKey, MAX_BUCKET_HEIGHT};
+pub use key::{BinaryID, BinaryKey, BinaryNonce, MAX_BUCKET_HEIGHT};
 pub use node::Node;
-use tracing::info;
+use tracing::{info, warn};
 
 mod bucket;
 mod key;
 mod node;
-use crate::K_BETA;
+use crate::blocklist::IpRange;
 use crate::config::BucketConfig;
 
 pub type BucketHeight = u8;
 
+/// Value of a node reachable at an IP address, subject to the subnet
+/// diversity limits of the routing table
+pub(crate) trait NodeAddress {
+    fn ip(&self) -> IpAddr;
+}
+
+/// Returns the subnet (/24 for IPv4, /48 for IPv6) the diversity limits
+/// apply to, or `None` for the loopback, private and link-local addresses,
+/// which are not limited
+fn subnet(ip: IpAddr) -> Option<IpRange> {
+    match ip.to_canonical() {
+        IpAddr::V4(ip)
+            if ip.is_loopback() || ip.is_private() || ip.is_link_local() =>
+        {
+            None
+        }
+        IpAddr::V6(ip)
+            if ip.is_loopback()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local() =>
+        {
+            None
+        }
+        ip @ IpAddr::V4(_) => IpRange::new(ip, 24),
+        ip @ IpAddr::V6(_) => IpRange::new(ip, 48),
+    }
+}
+
 pub(crate) struct Tree<V> {
     root: Node<V>,
     buckets: HashMap<BucketHeight, Bucket<V>>,
     config: BucketConfig,
 }
 
-impl<V> Tree<V> {
+impl<V: NodeAddress> Tree<V> {
     pub fn insert(
         &mut self,
         node: Node<V>,
@@ -38,10 +68,48 @@ impl<V> Tree<V> {
         }
         match self.root.calculate_distance(&node) {
             None => Err(NodeInsertError::Invalid(node)),
+            Some(height) if self.exceeds_subnet_limits(height, &node) => {
+                Err(NodeInsertError::SubnetLimit(node))
+            }
             Some(height) => self.get_or_create_bucket(height).insert(node),
         }
     }
 
+    /// Check if inserting `node` in the bucket at `height` would exceed the
+    /// amount of nodes allowed from the same subnet.
+    ///
+    /// Nodes already in the table are never limited.
+    fn exceeds_subnet_limits(
+        &self,
+        height: BucketHeight,
+        node: &Node<V>,
+    ) -> bool {
+        let Some(subnet_of_node) = subnet(node.value().ip()) else {
+            return false;
+        };
+        let bucket = self.buckets.get(&height);
+        if bucket.is_some_and(|b| b.has_node(node.id().as_binary())) {
+            return false;
+        }
+        let same_subnet =
+            |n: &&Node<V>| subnet(n.value().ip()) == Some(subnet_of_node);
+        let in_bucket = bucket
+            .map(|b| b.peers().filter(same_subnet).count())
+            .unwrap_or_default();
+        if in_bucket >= self.config.bucket_subnet_limit {
+            return true;
+        }
+        let in_table = self
+            .buckets
+            .values()
+            .flat_map(|b| b.peers())
+            .filter(same_subnet)
+            .count();
+        in_table >= self.config.table_subnet_limit
+    }
+}
+
+impl<V> Tree<V> {
     pub fn refresh(
         &mut self,
         node: Node<V>,
@@ -62,38 +130,39 @@ impl<V> Tree<V> {
         }
     }
 
-    // iter the buckets (up to max_height, inclusive) and pick at most Beta
+    // iter the buckets (up to max_height, inclusive) and pick at most `beta`
     // nodes for each bucket
     pub(crate) fn extract(
         &self,
         max_h: Option<BucketHeight>,
+        beta: usize,
     ) -> impl Iterator<Item = (BucketHeight, impl Iterator<Item = &Node<V>>)>
     {
         let max_h = max_h.unwrap_or(BucketHeight::MAX);
         self.buckets
             .iter()
             .filter(move |&(&height, _)| height <= max_h)
-            .map(|(&height, bucket)| (height, bucket.pick::<K_BETA>()))
+            .map(move |(&height, bucket)| (height, bucket.pick(beta)))
     }
 
     pub(crate) fn root(&self) -> &Node<V> {
         &self.root
     }
 
-    pub(crate) fn closest_peers<const ITEM_COUNT: usize>(
+    pub(crate) fn closest_peers(
         &self,
         other: &BinaryKey,
+        count: usize,
     ) -> impl Iterator<Item = &Node<V>> {
         self.buckets
             .values()
             .flat_map(|b| b.peers())
-            .filter(|p| p.id().as_binary() != other)
             .sorted_by(|a, b| {
                 let distance_a = a.id().calculate_distance(other);
                 let distance_b = b.id().calculate_distance(other);
                 distance_a.cmp(&distance_b)
             })
-            .take(ITEM_COUNT)
+            .take(count)
     }
 
     pub(crate) fn buckets(
@@ -132,6 +201,15 @@ impl<V> Tree<V> {
         })
     }
 
+    // Return the node identified by `peer`, if any
+    pub(crate) fn peer(&self, peer: &BinaryKey) -> Option<&Node<V>> {
+        self.root.id().calculate_distance(peer).and_then(|height| {
+            self.buckets
+                .get(&height)
+                .and_then(|bucket| bucket.node(peer))
+        })
+    }
+
     pub(crate) fn remove_peer(&mut self, peer: &BinaryKey) -> Option<Node<V>> {
         self.root.id().calculate_distance(peer).and_then(|height| {
             self.buckets
@@ -150,6 +228,19 @@ impl<V> Tree<V> {
             .for_each(|(_, b)| b.remove_idle_nodes())
     }
 
+    /// Drain the changes performed as a side effect of the operations on the
+    /// buckets (evictions, idle nodes removal, pending nodes promotion)
+    pub(crate) fn take_changes(
+        &mut self,
+    ) -> Vec<(BucketHeight, BucketChange<V>)> {
+        self.buckets
+            .iter_mut()
+            .flat_map(|(&height, bucket)| {
+                bucket.take_changes().into_iter().map(move |c| (height, c))
+            })
+            .collect()
+    }
+
     pub(crate) fn alive_nodes(&self) -> impl Iterator<Item = &Node<V>> {
         self.buckets
             .values()
@@ -169,12 +260,17 @@ impl<V> Tree<V> {
             .unwrap_or_default()
     }
 
-    pub(crate) fn new(root: Node<V>, config: BucketConfig) -> Tree<V> {
-        info!(
-            "Building table [K={}] with root: {:?}",
-            crate::K_K,
-            root.id()
-        );
+    /// Max amount of nodes a bucket can contain
+    pub(crate) fn bucket_k(&self) -> usize {
+        self.config.k
+    }
+
+    pub(crate) fn new(root: Node<V>, mut config: BucketConfig) -> Tree<V> {
+        if config.k == 0 {
+            warn!("Bucket K cannot be 0, using 1 instead");
+            config.k = 1;
+        }
+        info!("Building table [K={}] with root: {:?}", config.k, root.id());
         Tree {
             root,
             config,
@@ -193,9 +289,11 @@ mod tests {
     #[test]
     fn test_buckets() -> Result<()> {
         let root = PeerNode::generate("192.168.0.1:666", 0)?;
-        let mut config = BucketConfig::default();
-        config.node_evict_after = Duration::from_millis(5000);
-        config.node_ttl = Duration::from_secs(60);
+        let config = BucketConfig {
+            node_evict_after: Duration::from_millis(5000),
+            node_ttl: Duration::from_secs(60),
+            ..Default::default()
+        };
 
         let mut route_table = Tree::new(root, config);
         for i in 2..255 {
@@ -210,11 +308,53 @@ mod tests {
             .insert(PeerNode::generate("192.168.0.1:666", 0)?)
             .expect_err("this should be an error");
 
-        assert!(if let NodeInsertError::Invalid(_) = res {
-            true
-        } else {
-            false
-        });
+        assert!(matches!(res, NodeInsertError::Invalid(_)));
+
+        for beta in [1, 3, config.k] {
+            for (height, nodes) in route_table.extract(None, beta) {
+                let expected = route_table.bucket_size(height).min(beta);
+                assert_eq!(nodes.count(), expected);
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_subnet_limits() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let config = BucketConfig {
+            bucket_subnet_limit: 2,
+            table_subnet_limit: 3,
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+
+        let mut inserted = vec![];
+        for i in 1..=10 {
+            let node = PeerNode::generate(format!("1.2.3.{i}:666"), 0)?;
+            let id = *node.id().as_binary();
+            match route_table.insert(node) {
+                Ok(_) => inserted.push(id),
+                Err(NodeInsertError::SubnetLimit(_)) => {}
+                _ => panic!("Node must be valid"),
+            }
+        }
+        assert_eq!(inserted.len(), 3);
+        for (_, nodes) in route_table.buckets() {
+            assert!(nodes.count() <= 2);
+        }
+
+        // Known nodes are refreshed, other subnets and private addresses are
+        // not limited
+        let known = route_table.peer(&inserted[0]).unwrap().value().address();
+        let node = PeerNode::generate(known.to_string(), 0)?;
+        assert!(route_table.insert(node).is_ok());
+        let node = PeerNode::generate("1.2.4.1:666", 0)?;
+        assert!(route_table.insert(node).is_ok());
+        for i in 2..10 {
+            let node = PeerNode::generate(format!("192.168.0.{i}:666"), 0)?;
+            assert!(route_table.insert(node).is_ok());
+        }
         Ok(())
     }
 }
@@ -4,21 +4,32 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use arrayvec::ArrayVec;
 use rand::seq::SliceRandom;
 use rand::thread_rng;
 use semver::Version;
 
 use super::BinaryKey;
 use super::node::{Node, NodeEvictionStatus};
-use crate::K_K;
 use crate::config::BucketConfig;
 
 /// Represents a bucket for storing nodes in a Kademlia routing table.
 pub(super) struct Bucket<V> {
-    nodes: arrayvec::ArrayVec<Node<V>, K_K>,
+    nodes: Vec<Node<V>>,
     pending_node: Option<Node<V>>,
     bucket_config: BucketConfig,
+    changes: Vec<BucketChange<V>>,
+}
+
+/// Change of the bucket content performed as a side effect of another
+/// operation, which is not reported by the operation result.
+#[derive(Debug, PartialEq)]
+pub(crate) enum BucketChange<V> {
+    /// The least recently used node has been flagged for eviction
+    Flagged(BinaryKey),
+    /// The node has been removed from the bucket
+    Removed(Node<V>),
+    /// The pending node has been inserted into the bucket
+    Promoted(BinaryKey),
 }
 
 /// Enum representing the result of inserting a node into a bucket.
@@ -54,6 +65,9 @@ pub enum NodeInsertError<TNode> {
     MismatchNetwork(TNode),
     /// There is a mismatch with the version while inserting the node.
     MismatchVersion(TNode, Version),
+    /// Too many nodes of the bucket or of the routing table share the subnet
+    /// of the node.
+    SubnetLimit(TNode),
 }
 
 impl<TNode> NodeInsertOk<'_, TNode> {
@@ -84,9 +98,10 @@ impl<V> Bucket<V> {
     /// Creates a new `Bucket` with the given configuration.
     pub(super) fn new(bucket_config: BucketConfig) -> Self {
         Bucket {
-            nodes: ArrayVec::<Node<V>, K_K>::new(),
+            nodes: Vec::with_capacity(bucket_config.k),
             pending_node: None,
             bucket_config,
+            changes: vec![],
         }
     }
 
@@ -105,7 +120,7 @@ impl<V> Bucket<V> {
     ///
     /// If the pending node is no longer alive, it is removed.
     fn insert_pending(&mut self) {
-        if self.nodes.is_full() {
+        if self.is_full() {
             return;
         };
         if let Some(pending) = self.pending_node.take()
@@ -115,6 +130,8 @@ impl<V> Bucket<V> {
             // FIXME2: This may break the LRU policy, as other records may
             // have been updated in the meantime. However, it is mitigated
             // by the `is_alive` check.
+            self.changes
+                .push(BucketChange::Promoted(*pending.id().as_binary()));
             self.nodes.push(pending);
         }
     }
@@ -125,7 +142,7 @@ impl<V> Bucket<V> {
     ///
     /// The method returns the candidate for eviction (if any).
     fn try_perform_eviction(&mut self) -> Option<&Node<V>> {
-        if !self.nodes.is_full() {
+        if !self.is_full() {
             return None;
         }
         match self.nodes.first()?.eviction_status {
@@ -133,7 +150,8 @@ impl<V> Bucket<V> {
                 if instant.elapsed() < self.bucket_config.node_evict_after {
                     self.nodes.first()
                 } else {
-                    self.nodes.pop_at(0);
+                    let evicted = self.nodes.remove(0);
+                    self.changes.push(BucketChange::Removed(evicted));
                     self.insert_pending();
                     None
                 }
@@ -142,7 +160,10 @@ impl<V> Bucket<V> {
                 if self.nodes.first()?.is_alive(self.bucket_config.node_ttl) {
                     None
                 } else {
-                    self.nodes.first_mut()?.flag_for_check();
+                    let node = self.nodes.first_mut()?;
+                    node.flag_for_check();
+                    let id = *node.id().as_binary();
+                    self.changes.push(BucketChange::Flagged(id));
                     self.nodes.first()
                 }
             }
@@ -167,31 +188,30 @@ impl<V> Bucket<V> {
             });
         }
         self.try_perform_eviction();
-        match self.nodes.try_push(node) {
-            Ok(_) => Ok(NodeInsertOk::Inserted {
+        if !self.is_full() {
+            self.nodes.push(node);
+            return Ok(NodeInsertOk::Inserted {
                 inserted: self.nodes.last().expect(
                     "last node to exist because it's been just inserted",
                 ),
-            }),
-            Err(err) => {
-                if self
-                    .nodes
-                    .first()
-                    .expect("Bucket full but no node as .first()")
-                    .is_alive(self.bucket_config.node_ttl)
-                {
-                    Err(NodeInsertError::Full(err.element()))
-                } else {
-                    self.pending_node = Some(err.element());
-                    Ok(NodeInsertOk::Pending {
-                        pending_insert: self
-                            .pending_node
-                            .as_ref()
-                            .expect("Unable to get the pending node back"),
-                        pending_eviction: self.pending_eviction_node(),
-                    })
-                }
-            }
+            });
+        }
+        if self
+            .nodes
+            .first()
+            .expect("Bucket full but no node as .first()")
+            .is_alive(self.bucket_config.node_ttl)
+        {
+            Err(NodeInsertError::Full(node))
+        } else {
+            self.pending_node = Some(node);
+            Ok(NodeInsertOk::Pending {
+                pending_insert: self
+                    .pending_node
+                    .as_ref()
+                    .expect("Unable to get the pending node back"),
+                pending_eviction: self.pending_eviction_node(),
+            })
         }
     }
 
@@ -218,14 +238,12 @@ impl<V> Bucket<V> {
         })
     }
 
-    /// Picks at most `ITEM_COUNT` random nodes from this bucket.
-    pub fn pick<const ITEM_COUNT: usize>(
-        &self,
-    ) -> impl Iterator<Item = &Node<V>> {
+    /// Picks at most `count` random nodes from this bucket.
+    pub fn pick(&self, count: usize) -> impl Iterator<Item = &Node<V>> {
         let mut idxs: Vec<_> = (0..self.nodes.len()).collect();
         idxs.shuffle(&mut thread_rng());
         idxs.into_iter()
-            .take(ITEM_COUNT)
+            .take(count)
             .filter_map(move |idx| self.nodes.get(idx))
     }
 
@@ -251,10 +269,21 @@ impl<V> Bucket<V> {
     /// Removes idle nodes from the bucket.
     pub(crate) fn remove_idle_nodes(&mut self) {
         let ttl = self.bucket_config.node_ttl;
-        self.nodes.retain(|n| n.is_alive(ttl));
+        let removed = self.nodes.extract_if(.., |n| !n.is_alive(ttl));
+        self.changes.extend(removed.map(BucketChange::Removed));
         self.insert_pending();
     }
 
+    /// Drains the changes performed since the last call.
+    pub(crate) fn take_changes(&mut self) -> Vec<BucketChange<V>> {
+        std::mem::take(&mut self.changes)
+    }
+
+    /// Returns the node with the given peer key, if any.
+    pub(crate) fn node(&self, peer: &BinaryKey) -> Option<&Node<V>> {
+        self.nodes.iter().find(|n| n.id().as_binary() == peer)
+    }
+
     /// Returns an iterator over the alive nodes in the bucket.
     pub(crate) fn alive_nodes(&self) -> impl Iterator<Item = &Node<V>> {
         let ttl = self.bucket_config.node_ttl;
@@ -274,7 +303,7 @@ impl<V> Bucket<V> {
 
     /// Checks if the bucket is full.
     pub(crate) fn is_full(&self) -> bool {
-        self.nodes.is_full()
+        self.nodes.len() >= self.bucket_config.k
     }
 
     /// Removes a node from the bucket by its ID.
@@ -286,23 +315,20 @@ impl<V> Bucket<V> {
         let node_idx =
             self.nodes.iter().position(|s| s.id().as_binary() == id)?;
 
-        self.nodes.pop_at(node_idx).inspect(|_| {
-            if let Some(pending) = self.pending_node.take()
-                && pending.is_alive(self.bucket_config.node_ttl)
-            {
-                self.nodes.push(pending);
-            }
-        })
+        let removed = self.nodes.remove(node_idx);
+        self.insert_pending();
+        Some(removed)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
     use std::time::Duration;
 
+    use tokio::time;
+
     use super::*;
-    use crate::K_BETA;
+    use crate::config::DEFAULT_BETA;
     use crate::kbucket::Tree;
     use crate::peer::PeerNode;
     use crate::tests::Result;
@@ -323,8 +349,8 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_lru_base_5secs() -> Result<()> {
+    #[tokio::test(start_paused = true)]
+    async fn test_lru_base_5secs() -> Result<()> {
         // Create all the nodes at the beginning to ensure that PoW is not a
         // factor in the test timing.
         let root = PeerNode::generate("127.0.0.1:666", 0)?;
@@ -344,19 +370,21 @@ mod tests {
         let pending = PeerNode::generate("192.168.1.21:8080", 0)?;
         let pending_2 = PeerNode::generate("192.168.1.21:8080", 0)?;
 
-        let mut config = BucketConfig::default();
-        config.node_evict_after = Duration::from_millis(1000);
-        config.node_ttl = Duration::from_secs(5);
+        let config = BucketConfig {
+            node_evict_after: Duration::from_millis(1000),
+            node_ttl: Duration::from_secs(5),
+            ..Default::default()
+        };
 
         let mut route_table = Tree::new(root, config);
 
         let bucket = route_table.bucket_for_test();
-        let id_node1 = node1.id().as_binary().clone();
+        let id_node1 = *node1.id().as_binary();
         match bucket.insert(node1).expect("This should return an ok()") {
             NodeInsertOk::Inserted { .. } => {}
-            _ => assert!(false),
+            _ => panic!("Unexpected insert result"),
         }
-        let a = bucket.pick::<K_BETA>();
+        let a = bucket.pick(DEFAULT_BETA);
         assert_eq!(a.count(), 1);
 
         match bucket
@@ -364,16 +392,16 @@ mod tests {
             .expect("This should return an ok()")
         {
             NodeInsertOk::Updated { .. } => {}
-            _ => assert!(false),
+            _ => panic!("Unexpected insert result"),
         }
         assert_eq!(Some(&id_node1), bucket.last_id());
-        let id_node2 = node2.id().as_binary().clone();
+        let id_node2 = *node2.id().as_binary();
 
         match bucket.insert(node2).expect("This should return an ok()") {
             NodeInsertOk::Inserted { inserted: _ } => {}
-            _ => assert!(false),
+            _ => panic!("Unexpected insert result"),
         }
-        let a = bucket.pick::<K_BETA>();
+        let a = bucket.pick(DEFAULT_BETA);
         assert_eq!(a.count(), 2);
         assert_eq!(Some(&id_node2), bucket.last_id());
         assert_eq!(Some(&id_node1), bucket.least_used_id());
@@ -383,9 +411,9 @@ mod tests {
             .expect("This should return an ok()")
         {
             NodeInsertOk::Updated { .. } => {}
-            _ => assert!(false),
+            _ => panic!("Unexpected insert result"),
         }
-        let a = bucket.pick::<K_BETA>();
+        let a = bucket.pick(DEFAULT_BETA);
         assert_eq!(a.count(), 2);
         assert_eq!(Some(&id_node1), bucket.last_id());
         assert_eq!(Some(&id_node2), bucket.least_used_id());
@@ -396,17 +424,17 @@ mod tests {
         for n in additionals {
             match bucket.insert(n).expect("This should return an ok()") {
                 NodeInsertOk::Inserted { .. } => {
-                    assert!(bucket.pick::<K_BETA>().count() <= K_BETA);
+                    assert!(bucket.pick(DEFAULT_BETA).count() <= DEFAULT_BETA);
                 }
-                _ => assert!(false),
+                _ => panic!("Unexpected insert result"),
             }
         }
-        assert_eq!(bucket.pick::<K_BETA>().count(), K_BETA);
-        let pending_id = pending.id().as_binary().clone();
+        assert_eq!(bucket.pick(DEFAULT_BETA).count(), DEFAULT_BETA);
+        let pending_id = *pending.id().as_binary();
         match bucket.insert(pending).expect_err("this should be error") {
             NodeInsertError::Full(pending) => {
                 assert_eq!(pending.id().as_binary(), &pending_id);
-                thread::sleep(Duration::from_secs(5));
+                time::advance(Duration::from_secs(5)).await;
                 match bucket.insert(pending).expect("this should be ok") {
                     NodeInsertOk::Pending {
                         pending_insert,
@@ -416,26 +444,50 @@ mod tests {
                             pending_insert.id().as_binary(),
                             &pending_id
                         );
-                        thread::sleep(Duration::from_secs(1));
+                        time::advance(Duration::from_secs(1)).await;
                         match bucket
                             .insert(pending_2)
                             .expect("this should be ok")
                         {
                             NodeInsertOk::Inserted { inserted: _ } => {}
                             v => {
-                                println!("{:?}", v);
-                                assert!(false)
+                                panic!("Unexpected insert result {v:?}")
                             }
                         }
                     }
                     b => {
-                        println!("{:?}", b);
-                        assert!(false)
+                        panic!("Unexpected insert result {b:?}")
                     }
                 }
             }
-            _ => assert!(false),
+            _ => panic!("Unexpected insert result"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_custom_k() -> Result<()> {
+        let root = PeerNode::generate("127.0.0.1:666", 0)?;
+        let nodes = (1..4)
+            .map(|i| PeerNode::generate(format!("192.168.1.{i}:8080"), 0))
+            .collect::<std::result::Result<Vec<_>, _>>()?;
+        let config = BucketConfig {
+            k: 2,
+            ..Default::default()
+        };
+        let mut route_table = Tree::new(root, config);
+        let bucket = route_table.bucket_for_test();
+        let mut nodes = nodes.into_iter();
+        for n in nodes.by_ref().take(2) {
+            assert!(!bucket.is_full());
+            bucket.insert(n).expect("This should return an ok()");
+        }
+        assert!(bucket.is_full());
+        match bucket.insert(nodes.next().expect("a third node")) {
+            Err(NodeInsertError::Full(_)) => {}
+            _ => panic!("Unexpected insert result"),
         }
+        assert_eq!(bucket.peers().count(), 2);
         Ok(())
     }
 }
@@ -4,13 +4,18 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::time::{Duration, Instant};
+use std::time::Duration;
+
+use tokio::time::Instant;
 
 use super::BucketHeight;
 use super::key::BinaryID;
 
 /// A struct representing a node in the network with an associated ID, value,
 /// and eviction status.
+///
+/// Times are measured through the tokio clock, so that the node ages
+/// according to the runtime time, even when it is paused.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Node<TValue> {
     id: BinaryID,
@@ -4,67 +4,116 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::collections::{BTreeMap, HashSet};
-use std::net::{AddrParseError, SocketAddr};
-use std::time::Instant;
-
-use config::Config;
-use encoding::message::{Header, Message};
-use encoding::payload::BroadcastPayload;
-use handling::MessageHandler;
-pub use handling::MessageInfo;
+use std::collections::BTreeMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use amplification::AddressTokens;
+use blocklist::Blocklist;
+pub use blocklist::{Ban, BlockedSource, IpRange};
+pub use builder::PeerBuilder;
+use config::{Config, Encryption};
+use encoding::message::{Header, Message, Origin};
+use encoding::payload::{BroadcastPayload, RequestPayload};
+pub use error::KadcastError;
+use events::EventSender;
+pub use events::{EventReceiver, PeerEvent};
+pub use handling::{
+    BroadcastValidator, MessageInfo, Validation, ValidationFuture,
+};
+use handling::{Channels, MessageHandler, Notifiers};
+pub use identity::{Identity, PublicKey};
 use itertools::Itertools;
+pub use kbucket::BinaryKey;
 use kbucket::{BucketHeight, MAX_BUCKET_HEIGHT, Tree};
+use lookup::NodeLookup;
 use maintainer::TableMaintainer;
+use metrics::Metrics;
+pub use metrics::{ChannelUsage, Counter, MetricsSnapshot};
 use peer::{PeerInfo, PeerNode};
 use rand::prelude::IteratorRandom;
+use reputation::Reputation;
+use request::PendingRequests;
+pub use request::{Responder, ResponseFuture};
 pub(crate) use rwlock::RwLock;
+use semver::{Version, VersionReq};
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tokio::task;
-use tracing::{error, info, warn};
-use transport::{MessageBeanOut, WireNetwork};
+use tokio::sync::{Notify, broadcast};
+use tokio::task::JoinHandle;
+use tracing::{debug, error, info, warn};
+pub use transport::scheduler::Priority;
+use transport::{
+    MessageBeanOut, SecureTransport, Transport, UdpTransport, WireNetwork,
+};
 
+mod amplification;
+mod blocklist;
+mod builder;
 pub mod config;
 mod encoding;
+mod error;
+mod events;
 mod handling;
+mod identity;
 mod kbucket;
+mod lookup;
 mod maintainer;
+mod metrics;
 mod peer;
+mod reputation;
+mod request;
 mod rwlock;
+pub mod simulation;
+mod snapshot;
 pub mod transport;
 
-// Max amount of nodes a bucket should contain
-const DEFAULT_K_K: usize = 20;
-const K_K: usize = get_k_k();
-
 const K_ID_LEN_BYTES: usize = 16;
 const K_NONCE_LEN: usize = 4;
 const K_DIFF_MIN_BIT: usize = 8;
 const K_DIFF_PRODUCED_BIT: usize = 20;
 
-const fn get_k_k() -> usize {
-    match option_env!("KADCAST_K") {
-        Some(v) => match usize::from_str_radix(v, 10) {
-            Ok(e) => e,
-            Err(_) => DEFAULT_K_K,
-        },
-        None => DEFAULT_K_K,
-    }
-}
-
-// Redundacy factor for lookup
-const K_ALPHA: usize = 3;
-// Redundacy factor for broadcast
-const K_BETA: usize = 3;
-
 /// Struct representing the Kadcast Network Peer
 pub struct Peer {
     outbound_sender: Sender<MessageBeanOut>,
     ktable: RwLock<Tree<PeerInfo>>,
     header: Header,
-    blocklist: RwLock<HashSet<SocketAddr>>,
+    blocklist: RwLock<Blocklist>,
+    reputation: Reputation,
+    tasks: Vec<JoinHandle<()>>,
+    outgoing_task: Option<JoinHandle<()>>,
+    outgoing_shutdown: Arc<Notify>,
+    snapshot_path: Option<PathBuf>,
+    beta: usize,
+    lookup: NodeLookup,
+    pending_requests: PendingRequests,
+    events: EventSender,
+    metrics: Arc<Metrics>,
+    identity: Option<Identity>,
+}
+
+/// Per-message options of [Peer::broadcast_with]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct BroadcastOptions {
+    /// Overrides default Kadcast broadcast height
+    pub height: Option<BucketHeight>,
+
+    /// Overrides [Config::beta] for this message
+    pub beta: Option<usize>,
+
+    /// [Priority] of the message over the other messages sent by this peer.
+    ///
+    /// The priority is not carried by the message: the receivers propagate
+    /// it with [Priority::Normal].
+    pub priority: Priority,
 }
 
+/// Receiver of the broadcasted messages, as returned by
+/// [Peer::new_with_receiver]
+pub type MessageReceiver = Receiver<(Vec<u8>, MessageInfo)>;
+
 /// The [NetworkListen] trait receives notifications whenever a broadcasted
 /// message is received from the network.
 pub trait NetworkListen: Send {
@@ -74,18 +123,87 @@ pub trait NetworkListen: Send {
 impl Peer {
     /// Create a [Peer].
     ///
+    /// The [Config] is checked with [Config::validate] and the listen socket
+    /// is bound before returning, so that an invalid configuration or an
+    /// address already in use is reported as a [KadcastError]. Resolving the
+    /// bootstrapping nodes may block on DNS queries.
+    ///
     /// * `config` - The [Config] used to create the Peer
     /// * `listener` - The [NetworkListen] impl notified each time a broadcasted
     ///   message is received from the network
     pub fn new<L: NetworkListen + 'static>(
         config: Config,
         listener: L,
-    ) -> Result<Self, AddrParseError> {
+    ) -> Result<Self, KadcastError> {
+        Self::builder(config).build(listener)
+    }
+
+    /// Create a [Peer] along with the [Receiver] of the broadcasted messages.
+    ///
+    /// This is an alternative to [Peer::new] for applications which prefer to
+    /// consume the incoming messages asynchronously instead of implementing
+    /// [NetworkListen].
+    ///
+    /// The receiver is bounded by [Config::channel_size]: while it is full,
+    /// the processing of the incoming messages is suspended, so the
+    /// application can apply its own flow control.
+    ///
+    /// * `config` - The [Config] used to create the Peer
+    pub fn new_with_receiver(
+        config: Config,
+    ) -> Result<(Self, MessageReceiver), KadcastError> {
+        Self::builder(config).build_with_receiver()
+    }
+
+    /// Create a [PeerBuilder], used to register application hooks on the
+    /// [Peer] being created.
+    ///
+    /// * `config` - The [Config] used to create the Peer
+    pub fn builder(config: Config) -> PeerBuilder {
+        PeerBuilder::new(config)
+    }
+
+    fn start(
+        builder: PeerBuilder,
+    ) -> Result<(Self, MessageReceiver), KadcastError> {
+        let PeerBuilder {
+            config,
+            hooks,
+            transport,
+            mut identity,
+        } = builder;
+        Version::parse(&config.version).map_err(|e| {
+            KadcastError::InvalidVersion(config.version.clone(), e)
+        })?;
+        VersionReq::parse(&config.version_match).map_err(|e| {
+            KadcastError::InvalidVersionMatch(config.version_match.clone(), e)
+        })?;
+        config.public_address.parse::<SocketAddr>().map_err(|e| {
+            KadcastError::InvalidAddress(config.public_address.clone(), e)
+        })?;
+        config.validate().map_err(KadcastError::InvalidConfig)?;
         let network_id = config.kadcast_id.unwrap_or_default();
-        let tree = Tree::new(
-            PeerNode::generate(&config.public_address[..], network_id)?,
-            config.bucket,
-        );
+        let encryption = config.network.encryption;
+        if encryption != Encryption::Disabled && identity.is_none() {
+            info!("Encryption enabled without identity, generating one");
+            identity = Some(Identity::generate());
+        }
+        let root = match &identity {
+            Some(identity) => PeerNode::generate_with_identity(
+                &config.public_address[..],
+                identity,
+                network_id,
+            ),
+            None => PeerNode::generate(&config.public_address[..], network_id),
+        }
+        .map_err(|e| {
+            KadcastError::InvalidAddress(config.public_address.clone(), e)
+        })?;
+        let mut transport: Arc<dyn Transport> = match transport {
+            Some(transport) => transport,
+            None => Arc::new(UdpTransport::bind(&config)?),
+        };
+        let tree = Tree::new(root, config.bucket);
 
         let (inbound_channel_tx, inbound_channel_rx) =
             mpsc::channel(config.channel_size);
@@ -93,49 +211,162 @@ impl Peer {
             mpsc::channel(config.channel_size);
         let (notification_channel_tx, listener_channel_rx) =
             mpsc::channel(config.channel_size);
+        let (nodes_reply_tx, _) = broadcast::channel(config.channel_size);
+
+        let restored_nodes = config
+            .snapshot
+            .path
+            .as_ref()
+            .map(|path| snapshot::restore(path, tree.root()))
+            .unwrap_or_default();
 
         let header = tree.root().to_header();
         let table = rwlock::new(tree);
-        let blocklist = rwlock::new(HashSet::new());
-        let peer = Peer {
-            outbound_sender: outbound_channel_tx.clone(),
-            ktable: table.clone(),
-            header,
-            blocklist: blocklist.clone(),
-        };
-        let nodes = config.bootstrapping_nodes.clone();
-        let idle_time = config.bucket.bucket_ttl;
-        let min_peers = config.bucket.min_peers;
-        let version =
-            semver::Version::parse(&config.version).expect("Invalid version");
+        let blocklist = rwlock::new(Blocklist::default());
+        let beta = config.beta;
+        let snapshot_path = config.snapshot.path.clone();
+        let snapshot_interval = config.snapshot.interval;
+        let pending_requests = PendingRequests::default();
+        let events = EventSender::new(config.channel_size);
+        let metrics = Arc::new(Metrics::default());
+        metrics.register_channel("inbound", &inbound_channel_tx);
+        metrics.register_channel("outbound", &outbound_channel_tx);
+        metrics.register_channel("listener", &notification_channel_tx);
+        let reputation = Reputation::new(
+            &config.reputation,
+            blocklist.clone(),
+            table.clone(),
+            events.clone(),
+            metrics.clone(),
+        );
+        let tokens = AddressTokens::default();
+
+        if let (Some(identity), Encryption::Optional | Encryption::Required) =
+            (&identity, encryption)
+        {
+            transport = Arc::new(SecureTransport::new(
+                transport,
+                encryption,
+                identity,
+                network_id,
+                config.network.handshakes_per_sec,
+                metrics.clone(),
+            ));
+        }
 
-        MessageHandler::start(
+        let handler = MessageHandler::start(
             table.clone(),
-            inbound_channel_rx,
-            outbound_channel_tx.clone(),
-            notification_channel_tx,
+            reputation.clone(),
+            tokens.clone(),
+            Channels {
+                inbound: inbound_channel_rx,
+                outbound: outbound_channel_tx.clone(),
+            },
+            Notifiers {
+                listener: notification_channel_tx,
+                nodes_reply: nodes_reply_tx.clone(),
+                pending_requests: pending_requests.clone(),
+                events: events.clone(),
+                metrics: metrics.clone(),
+            },
+            hooks,
             &config,
         );
-        WireNetwork::start(
+        let wire = WireNetwork::start(
             inbound_channel_tx,
             outbound_channel_rx,
-            config,
-            blocklist,
+            config.clone(),
+            reputation.clone(),
+            tokens,
+            metrics.clone(),
+            transport,
         );
-        TableMaintainer::start(
-            nodes,
-            table,
-            outbound_channel_tx,
-            idle_time,
-            min_peers,
-            version,
+        let maintainer = TableMaintainer::start(
+            restored_nodes,
+            table.clone(),
+            outbound_channel_tx.clone(),
+            events.clone(),
+            metrics.clone(),
+            &config,
         );
-        task::spawn(Peer::notifier(listener_channel_rx, listener));
-        Ok(peer)
+        let lookup = NodeLookup::new(
+            table.clone(),
+            outbound_channel_tx.clone(),
+            nodes_reply_tx,
+            header,
+            &config,
+        );
+        let mut tasks = vec![handler, maintainer, wire.decoder, wire.incoming];
+        if let Some(path) = &snapshot_path {
+            let persist = snapshot::persist(
+                table.clone(),
+                path.clone(),
+                snapshot_interval,
+            );
+            tasks.push(tokio::spawn(persist));
+        }
+        let peer = Peer {
+            outbound_sender: outbound_channel_tx,
+            ktable: table,
+            header,
+            blocklist,
+            reputation,
+            tasks,
+            outgoing_task: Some(wire.outgoing),
+            outgoing_shutdown: wire.outgoing_shutdown,
+            snapshot_path,
+            beta,
+            lookup,
+            pending_requests,
+            events,
+            metrics,
+            identity,
+        };
+        Ok((peer, listener_channel_rx))
+    }
+
+    /// Gracefully shutdown the [Peer].
+    ///
+    /// Every task spawned by the peer is cancelled, the messages already queued
+    /// for sending are flushed and the listen socket is released before this
+    /// method returns. If [SnapshotConfig::path](config::SnapshotConfig::path)
+    /// is set, the routing table is saved as well.
+    ///
+    /// Dropping a [Peer] performs the same cancellation without waiting for
+    /// its completion and without saving the routing table.
+    pub async fn shutdown(mut self) {
+        self.stop_tasks();
+        for task in std::mem::take(&mut self.tasks) {
+            // Aborted tasks always return a `JoinError::Cancelled`
+            let _ = task.await;
+        }
+        if let Some(outgoing) = self.outgoing_task.take() {
+            outgoing.await.unwrap_or_else(|e| {
+                error!("Unable to flush outgoing messages {e}")
+            });
+        }
+        if let Some(path) = &self.snapshot_path {
+            snapshot::save(&self.ktable, path)
+                .await
+                .unwrap_or_else(|e| {
+                    error!(
+                        "Unable to save routing table to {} {e}",
+                        path.display()
+                    )
+                });
+        }
+        debug!("Peer shutdown completed");
+    }
+
+    /// Cancel the spawned tasks and ask the outgoing loop to flush the
+    /// queued messages.
+    fn stop_tasks(&self) {
+        self.tasks.iter().for_each(JoinHandle::abort);
+        self.outgoing_shutdown.notify_one();
     }
 
-    async fn notifier(
-        mut listener_channel_rx: Receiver<(Vec<u8>, MessageInfo)>,
+    pub(crate) async fn notifier(
+        mut listener_channel_rx: MessageReceiver,
         listener: impl NetworkListen,
     ) {
         while let Some(notif) = listener_channel_rx.recv().await {
@@ -143,6 +374,44 @@ impl Peer {
         }
     }
 
+    /// Return the [BinaryKey] identifying this peer in the network
+    pub fn id(&self) -> &BinaryKey {
+        self.header.binary_id().as_binary()
+    }
+
+    /// Subscribe to the [PeerEvent]s emitted whenever the routing table or the
+    /// blocklist change.
+    ///
+    /// Only the events emitted after the subscription are received. A receiver
+    /// lagging behind more than [Config::channel_size] events misses the
+    /// oldest ones.
+    pub fn events(&self) -> EventReceiver {
+        self.events.subscribe()
+    }
+
+    /// Returns a snapshot of the metrics collected since the [Peer] started,
+    /// along with the current size of the routing table buckets and the usage
+    /// of the internal channels.
+    ///
+    /// See [MetricsSnapshot::to_prometheus] to expose them to Prometheus.
+    pub async fn metrics(&self) -> MetricsSnapshot {
+        self.metrics.snapshot(&*self.ktable.read().await)
+    }
+
+    /// Search the network for the nodes closest to `target`.
+    ///
+    /// This performs an iterative Kademlia lookup: the [Config::alpha]
+    /// closest known nodes are queried and the closer nodes they return are
+    /// queried in turn, until the `K` closest nodes found have all replied.
+    /// Nodes not replying within [Config::lookup_timeout] are discarded.
+    ///
+    /// Returns at most [BucketConfig::k](config::BucketConfig::k) addresses,
+    /// sorted by their distance from `target`. If a node identified by
+    /// `target` is found, it comes first.
+    pub async fn find_closest(&self, target: BinaryKey) -> Vec<SocketAddr> {
+        self.lookup.find_closest(target).await
+    }
+
     /// Return the [SocketAddr] of a set of random active nodes.
     ///
     /// * `amount` - The max amount of nodes to return
@@ -196,7 +465,7 @@ impl Peer {
         let table_read = self.ktable.read().await;
         table_read.buckets().for_each(|(h, nodes)| {
             let nodes = nodes
-                .map(|p| (*p.value().address(), *p.seen_at()))
+                .map(|p| (*p.value().address(), p.seen_at().into_std()))
                 .collect::<Vec<_>>();
             route_table.insert(h, nodes);
         });
@@ -211,6 +480,9 @@ impl Peer {
     /// * `message` - Byte array containing the message to be broadcasted
     /// * `height` - (Optional) Overrides default Kadcast broadcast height
     ///
+    /// The message is sent with [Priority::Normal], see
+    /// [BroadcastOptions::priority] to override it.
+    ///
     /// Note:
     /// The function returns just after the message is put on the internal queue
     /// system. It **does not guarantee** the message will be broadcasted
@@ -218,13 +490,40 @@ impl Peer {
         &self,
         message: &[u8],
         height: Option<BucketHeight>,
+    ) {
+        let options = BroadcastOptions {
+            height,
+            ..Default::default()
+        };
+        self.broadcast_with(message, options).await
+    }
+
+    /// Broadcast a message to the network, overriding the default behaviour
+    /// with the provided [BroadcastOptions]
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - Byte array containing the message to be broadcasted
+    /// * `options` - The [BroadcastOptions] applied to this message only
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be broadcasted
+    pub async fn broadcast_with(
+        &self,
+        message: &[u8],
+        options: BroadcastOptions,
     ) {
         if message.is_empty() {
             error!("Message empty");
             return;
         }
 
-        for i in self.extract(message, height).await {
+        let beta = options.beta.unwrap_or(self.beta);
+        for i in self
+            .extract(message, options.height, beta, options.priority)
+            .await
+        {
             self.outbound_sender.send(i).await.unwrap_or_else(|e| {
                 error!("Unable to send from broadcast {e}")
             });
@@ -235,7 +534,9 @@ impl Peer {
         &self,
         message: &[u8],
         height: Option<BucketHeight>,
-    ) -> Vec<(Message, Vec<SocketAddr>)> {
+        beta: usize,
+        priority: Priority,
+    ) -> Vec<MessageBeanOut> {
         const LAST_BUCKET_IDX: u8 = MAX_BUCKET_HEIGHT as u8 - 1;
         let ktable = self.ktable.read().await;
         if height.is_none() && ktable.bucket_size(LAST_BUCKET_IDX) == 0 {
@@ -243,21 +544,69 @@ impl Peer {
                 "Broadcasting a new message with empty bucket height {LAST_BUCKET_IDX}"
             )
         }
-        ktable
-            .extract(height)
-            .map(|(height, nodes)| {
-                let msg = Message::broadcast(
-                    self.header,
-                    BroadcastPayload {
-                        height,
-                        gossip_frame: message.to_vec(),
-                    },
-                );
-                let targets =
-                    nodes.map(|node| *node.value().address()).collect();
-                (msg, targets)
-            })
-            .collect()
+        handling::extract_broadcast(
+            &ktable,
+            self.header,
+            message,
+            self.sign(message),
+            height,
+            beta,
+            priority,
+        )
+    }
+
+    /// Sign a message originated by this peer, if it runs with an [Identity]
+    fn sign(&self, message: &[u8]) -> Option<Origin> {
+        let identity = self.identity.as_ref()?;
+        Some(identity.sign(self.header.network_id, message))
+    }
+
+    /// Propagate a message previously received from the network
+    ///
+    /// The message is forwarded exactly as it would have been if
+    /// [Config::auto_propagate] was enabled: it is sent to the buckets below
+    /// the received broadcast height and, since the payload is unchanged, it
+    /// keeps the same ray-id.
+    ///
+    /// This is meant to propagate messages only after the application
+    /// validated them, while `auto_propagate` is disabled. The origin
+    /// signature of the message (if any) is propagated as well.
+    ///
+    /// # Arguments
+    ///
+    /// * `metadata` - The [MessageInfo] received along with the message
+    /// * `message` - Byte array containing the received message
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be broadcasted
+    pub async fn propagate(&self, metadata: &MessageInfo, message: &[u8]) {
+        if message.is_empty() {
+            error!("Message empty");
+            return;
+        }
+        // Messages received at height 0 are not propagated any further
+        let Some(height) = metadata.height().checked_sub(1) else {
+            return;
+        };
+
+        let messages = {
+            let ktable = self.ktable.read().await;
+            handling::extract_broadcast(
+                &ktable,
+                self.header,
+                message,
+                metadata.origin,
+                Some(height),
+                self.beta,
+                Priority::Normal,
+            )
+        };
+        for i in messages {
+            self.outbound_sender.send(i).await.unwrap_or_else(|e| {
+                error!("Unable to send from propagate {e}")
+            });
+        }
     }
 
     /// Send a message to a peer in the network
@@ -281,6 +630,9 @@ impl Peer {
     /// * `message` - Byte array containing the message to be sent
     /// * `targets` - Vector of receiver addresses (`Vec<SocketAddr>`)
     ///
+    /// The message is sent with [Priority::Normal], see
+    /// [Peer::send_to_peers_with] to override it.
+    ///
     /// Note:
     /// The function returns just after the message is put on the internal queue
     /// system. It **does not guarantee** the message will be broadcasted to
@@ -289,6 +641,29 @@ impl Peer {
         &self,
         message: &[u8],
         targets: Vec<SocketAddr>,
+    ) {
+        self.send_to_peers_with(message, targets, Priority::Normal)
+            .await
+    }
+
+    /// Send a message to multiple peers in the network, with the provided
+    /// [Priority] over the other outbound messages
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - Byte array containing the message to be sent
+    /// * `targets` - Vector of receiver addresses (`Vec<SocketAddr>`)
+    /// * `priority` - The [Priority] of this message
+    ///
+    /// Note:
+    /// The function returns just after the message is put on the internal queue
+    /// system. It **does not guarantee** the message will be broadcasted to
+    /// all.
+    pub async fn send_to_peers_with(
+        &self,
+        message: &[u8],
+        targets: Vec<SocketAddr>,
+        priority: Priority,
     ) {
         if message.is_empty() {
             return;
@@ -300,28 +675,169 @@ impl Peer {
             BroadcastPayload {
                 height: 0,
                 gossip_frame: message.to_vec(),
+                origin: self.sign(message),
             },
         );
         self.outbound_sender
-            .send((msg, targets))
+            .send((msg, targets, priority))
             .await
             .unwrap_or_else(|e| error!("Unable to send from send method {e}"));
     }
 
-    /// Blocks a network source and removes it from the routing table.
+    /// Send a request to a peer in the network and wait for its response.
+    ///
+    /// # Arguments
+    ///
+    /// * `message` - Byte array containing the request
+    /// * `target` - Receiver address
+    /// * `timeout` - Maximum time to wait for the response
+    ///
+    /// The request is answered by the [Responder] registered through
+    /// [PeerBuilder::responder] on the target peer. Only a response sent by
+    /// `target` completes the request.
+    ///
+    /// Returns an error of kind [io::ErrorKind::TimedOut] if no response is
+    /// received in time.
+    pub async fn request(
+        &self,
+        message: &[u8],
+        target: SocketAddr,
+        timeout: Duration,
+    ) -> io::Result<Vec<u8>> {
+        let (request_id, _guard, response) = loop {
+            let request_id = rand::random();
+            if let Some((guard, response)) =
+                self.pending_requests.register(request_id, target)
+            {
+                break (request_id, guard, response);
+            }
+        };
+        let payload = RequestPayload {
+            request_id,
+            body: message.to_vec(),
+        };
+        self.outbound_sender
+            .send((
+                Message::Request(self.header, payload),
+                vec![target],
+                Priority::Normal,
+            ))
+            .await
+            .map_err(io::Error::other)?;
+        match tokio::time::timeout(timeout, response).await {
+            Ok(Ok(response)) => Ok(response),
+            Ok(Err(e)) => Err(io::Error::other(e)),
+            Err(_) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("No response from {target}"),
+            )),
+        }
+    }
+
+    /// Blocks a network source permanently and removes it from the routing
+    /// table.
     ///
     /// # Arguments
     ///
-    /// * `source` - The address of the network source to be blocked.
+    /// * `source` - The network source to be blocked: a [SocketAddr], every
+    ///   port of an [IpAddr] or every address of an [IpRange].
     ///
-    /// This method blocks a network source by adding its address to the
-    /// blocklist and subsequently removes the corresponding peer from the
-    /// routing table. This action prevents further communication with the
-    /// blocked source.
-    pub async fn block_source(&self, source: SocketAddr) {
-        self.blocklist.write().await.insert(source);
-        let binary_key = PeerNode::compute_id(&source.ip(), source.port());
-        self.ktable.write().await.remove_peer(&binary_key);
+    /// This method blocks a network source by adding it to the blocklist and
+    /// subsequently removes the corresponding peers from the routing table.
+    /// This action prevents further communication with the blocked source.
+    ///
+    /// The incoming datagrams are checked against a copy of the blocklist,
+    /// refreshed every
+    /// [blocklist_refresh_interval](config::NetworkConfig::blocklist_refresh_interval).
+    pub async fn block_source(&self, source: impl Into<BlockedSource>) {
+        self.block_source_for(source, None).await
+    }
+
+    /// Blocks a network source like [Peer::block_source], until `duration`
+    /// expires. A `None` duration blocks the source permanently.
+    ///
+    /// Blocking a source already blocked replaces the previous ban.
+    pub async fn block_source_for(
+        &self,
+        source: impl Into<BlockedSource>,
+        duration: Option<Duration>,
+    ) {
+        let source = source.into();
+        block_source(
+            &self.blocklist,
+            &self.ktable,
+            &self.events,
+            source,
+            duration,
+        )
+        .await
+    }
+
+    /// Removes the ban of a network source, as passed to
+    /// [Peer::block_source].
+    ///
+    /// Returns `false` if the source wasn't blocked. Addresses covered by a
+    /// wider ban (e.g. an address belonging to a blocked [IpRange]) stay
+    /// blocked.
+    pub async fn unblock_source(
+        &self,
+        source: impl Into<BlockedSource>,
+    ) -> bool {
+        let source = source.into();
+        let unblocked = self.blocklist.write().await.unblock(&source);
+        if unblocked {
+            info!("Source {source} unblocked");
+        }
+        unblocked
+    }
+
+    /// Returns the bans currently in place
+    pub async fn blocked_sources(&self) -> Vec<Ban> {
+        self.blocklist.read().await.bans()
+    }
+
+    /// Returns the misbehaviour score of the source IPs penalized recently.
+    ///
+    /// Sources sending invalid headers, undecodable datagrams or bogus
+    /// RaptorQ chunks are penalized, and banned once their score reaches
+    /// [ReputationConfig::ban_threshold](config::ReputationConfig::ban_threshold).
+    /// Scores decay over time and are reset when the source gets banned.
+    pub fn source_scores(&self) -> BTreeMap<IpAddr, f64> {
+        self.reputation.scores()
+    }
+}
+
+/// Add `source` to the blocklist and remove the corresponding peers from the
+/// routing table.
+pub(crate) async fn block_source(
+    blocklist: &RwLock<Blocklist>,
+    ktable: &RwLock<Tree<PeerInfo>>,
+    events: &EventSender,
+    source: BlockedSource,
+    duration: Option<Duration>,
+) {
+    blocklist.write().await.block(source, duration);
+    let mut table = ktable.write().await;
+    // Nodes running with an identity have an id not derived from their
+    // address
+    let blocked: Vec<_> = table
+        .buckets()
+        .flat_map(|(height, nodes)| nodes.map(move |node| (height, node)))
+        .filter(|(_, node)| source.contains(node.value().address()))
+        .map(|(height, node)| (height, *node.id().as_binary()))
+        .collect();
+    for (_, key) in &blocked {
+        table.remove_peer(key);
+    }
+    events.emit(PeerEvent::PeerBlocked { source });
+    events.emit_table_changes(&mut table);
+    let heights = blocked.into_iter().map(|(height, _)| height).dedup();
+    events.emit_empty_buckets(&table, heights);
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        self.stop_tasks();
     }
 }
 
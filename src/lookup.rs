@@ -0,0 +1,226 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use semver::Version;
+use tokio::sync::broadcast;
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::mpsc::Sender;
+use tokio::time::{Instant, timeout_at};
+use tracing::{debug, error, warn};
+
+use crate::config::Config;
+use crate::encoding::message::{Header, Message};
+use crate::encoding::payload::PeerEncodedInfo;
+use crate::kbucket::{BinaryKey, Tree};
+use crate::peer::{PeerInfo, PeerNode};
+use crate::transport::MessageBeanOut;
+use crate::transport::scheduler::Priority;
+use crate::{K_ID_LEN_BYTES, RwLock};
+
+/// `Nodes` reply forwarded by the
+/// [MessageHandler](crate::handling::MessageHandler) to the running lookups
+#[derive(Clone, Debug)]
+pub(crate) struct NodesReply {
+    pub(crate) from: BinaryKey,
+    pub(crate) peers: Vec<PeerEncodedInfo>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum QueryState {
+    NotQueried,
+    Pending,
+    Responded,
+    Failed,
+}
+
+struct Candidate {
+    id: BinaryKey,
+    address: SocketAddr,
+    distance: [u8; K_ID_LEN_BYTES],
+    state: QueryState,
+}
+
+/// Iterative Kademlia node lookup
+pub(crate) struct NodeLookup {
+    ktable: RwLock<Tree<PeerInfo>>,
+    outbound_sender: Sender<MessageBeanOut>,
+    replies: broadcast::Sender<NodesReply>,
+    header: Header,
+    version: Version,
+    alpha: usize,
+    timeout: Duration,
+}
+
+impl NodeLookup {
+    pub(crate) fn new(
+        ktable: RwLock<Tree<PeerInfo>>,
+        outbound_sender: Sender<MessageBeanOut>,
+        replies: broadcast::Sender<NodesReply>,
+        header: Header,
+        config: &Config,
+    ) -> Self {
+        Self {
+            ktable,
+            outbound_sender,
+            replies,
+            header,
+            version: Version::parse(&config.version)
+                .expect("Version validated by Peer::start"),
+            alpha: config.alpha,
+            timeout: config.lookup_timeout,
+        }
+    }
+
+    /// Iteratively query the network for the nodes closest to `target`.
+    ///
+    /// Every round, `FindNodes` is sent to the `alpha` closest nodes not
+    /// queried yet among the `K` closest known ones. The nodes which don't
+    /// reply within the lookup timeout are discarded. The lookup terminates
+    /// once the `K` closest known nodes have all replied.
+    ///
+    /// Returns the addresses of the `K` closest nodes which replied, the
+    /// closest first.
+    pub(crate) async fn find_closest(
+        &self,
+        target: BinaryKey,
+    ) -> Vec<SocketAddr> {
+        // Subscribe before sending any query, so no reply can be missed
+        let mut replies = self.replies.subscribe();
+        let my_id = *self.header.binary_id().as_binary();
+
+        let (k, mut candidates) = {
+            let table = self.ktable.read().await;
+            let k = table.bucket_k();
+            let candidates: Vec<_> = table
+                .closest_peers(&target, k)
+                .map(|n| {
+                    let id = *n.id().as_binary();
+                    Candidate::new(&target, id, *n.value().address())
+                })
+                .collect();
+            (k, candidates)
+        };
+        candidates.sort_by_key(|c| c.distance);
+
+        loop {
+            let to_query: Vec<_> = candidates
+                .iter_mut()
+                .filter(|c| c.state != QueryState::Failed)
+                .take(k)
+                .filter(|c| c.state == QueryState::NotQueried)
+                .take(self.alpha)
+                .map(|c| {
+                    c.state = QueryState::Pending;
+                    c.address
+                })
+                .collect();
+            if to_query.is_empty() {
+                break;
+            }
+            debug!("Lookup querying {} nodes", to_query.len());
+            let find_nodes =
+                Message::FindNodes(self.header, self.version.clone(), target);
+            self.outbound_sender
+                .send((find_nodes, to_query, Priority::Control))
+                .await
+                .unwrap_or_else(|e| error!("Unable to send FindNodes {e}"));
+
+            let deadline = Instant::now() + self.timeout;
+            while candidates.iter().any(|c| c.state == QueryState::Pending) {
+                let reply = match timeout_at(deadline, replies.recv()).await {
+                    Ok(Ok(reply)) => reply,
+                    Ok(Err(RecvError::Lagged(n))) => {
+                        warn!("Lookup missed {n} Nodes replies");
+                        continue;
+                    }
+                    // The handler is gone or the round expired
+                    Ok(Err(RecvError::Closed)) | Err(_) => break,
+                };
+                let Some(sender) = candidates.iter_mut().find(|c| {
+                    c.id == reply.from && c.state == QueryState::Pending
+                }) else {
+                    continue;
+                };
+                sender.state = QueryState::Responded;
+
+                // Peers whose id doesn't derive from their key or address
+                // could point the queries at any address
+                for peer in reply.peers {
+                    if peer.id == my_id
+                        || !PeerNode::verify_peer_info(&peer)
+                        || candidates.iter().any(|c| c.id == peer.id)
+                    {
+                        continue;
+                    }
+                    let address = peer.to_socket_address();
+                    candidates.push(Candidate::new(&target, peer.id, address));
+                }
+                candidates.sort_by_key(|c| c.distance);
+            }
+            candidates
+                .iter_mut()
+                .filter(|c| c.state == QueryState::Pending)
+                .for_each(|c| c.state = QueryState::Failed);
+        }
+
+        candidates
+            .into_iter()
+            .filter(|c| c.state == QueryState::Responded)
+            .take(k)
+            .map(|c| c.address)
+            .collect()
+    }
+}
+
+impl Candidate {
+    fn new(target: &BinaryKey, id: BinaryKey, address: SocketAddr) -> Self {
+        Self {
+            id,
+            address,
+            distance: xor_distance(target, &id),
+            state: QueryState::NotQueried,
+        }
+    }
+}
+
+/// XOR distance between two keys, most significant byte first, so that
+/// distances can be compared lexicographically
+fn xor_distance(a: &BinaryKey, b: &BinaryKey) -> [u8; K_ID_LEN_BYTES] {
+    let mut distance = [0; K_ID_LEN_BYTES];
+    // Keys are little endian, as in `BinaryID::calculate_distance`
+    for (d, (a, b)) in distance.iter_mut().zip(a.iter().zip(b).rev()) {
+        *d = a ^ b;
+    }
+    distance
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::peer::PeerNode;
+    use crate::tests::Result;
+
+    #[test]
+    fn test_xor_distance_order() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let target = root.id().as_binary();
+        assert_eq!(xor_distance(target, target), [0; K_ID_LEN_BYTES]);
+
+        let mut keys: Vec<_> = (0..K_ID_LEN_BYTES * 8)
+            .map(|h| root.id().get_at_distance(h as u8))
+            .collect();
+        keys.reverse();
+        keys.sort_by_key(|k| xor_distance(target, k));
+        for (h, key) in keys.iter().enumerate() {
+            assert_eq!(root.id().calculate_distance(key), Some(h as u8));
+        }
+        Ok(())
+    }
+}
@@ -5,50 +5,71 @@
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
 use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::Arc;
 use std::time::Duration;
 
+use itertools::Itertools;
 use semver::Version;
 use tokio::sync::mpsc::Sender;
+use tokio::task::JoinHandle;
 use tracing::{error, info};
 
+use crate::RwLock;
+use crate::config::Config;
 use crate::encoding::message::{Header, Message};
+use crate::events::EventSender;
 use crate::kbucket::Tree;
+use crate::metrics::{Counter, Metrics};
 use crate::peer::PeerInfo;
 use crate::transport::MessageBeanOut;
-use crate::{K_ALPHA, RwLock};
+use crate::transport::scheduler::Priority;
 
 pub(crate) struct TableMaintainer {
     bootstrapping_nodes: Vec<String>,
+    restored_nodes: Vec<SocketAddr>,
     ktable: RwLock<Tree<PeerInfo>>,
     outbound_sender: Sender<MessageBeanOut>,
+    events: EventSender,
+    metrics: Arc<Metrics>,
     my_ip: SocketAddr,
     header: Header,
     version: Version,
+    alpha: usize,
 }
 
 impl TableMaintainer {
     pub fn start(
-        bootstrapping_nodes: Vec<String>,
+        restored_nodes: Vec<SocketAddr>,
         ktable: RwLock<Tree<PeerInfo>>,
         outbound_sender: Sender<MessageBeanOut>,
-        idle_time: Duration,
-        min_peers: usize,
-        version: Version,
-    ) {
+        events: EventSender,
+        metrics: Arc<Metrics>,
+        config: &Config,
+    ) -> JoinHandle<()> {
+        let bootstrapping_nodes = config.bootstrapping_nodes.clone();
+        let idle_time = config.bucket.bucket_ttl;
+        let min_peers = config.bucket.min_peers;
+        let alpha = config.alpha;
+        let version = Version::parse(&config.version)
+            .expect("Version validated by Peer::start");
         tokio::spawn(async move {
             let my_ip = *ktable.read().await.root().value().address();
             let header = ktable.read().await.root().to_header();
 
             let maintainer = Self {
                 bootstrapping_nodes,
+                restored_nodes,
                 ktable,
                 outbound_sender,
+                events,
+                metrics,
                 my_ip,
                 header,
                 version,
+                alpha,
             };
             maintainer.monitor_buckets(idle_time, min_peers).await;
-        });
+        })
     }
 
     /// Check if the peer need to contact the bootstrappers in order to join the
@@ -73,24 +94,36 @@ impl TableMaintainer {
     }
 
     /// Try to contact the bootstrappers node until no needed anymore
+    ///
+    /// The nodes restored from the routing table snapshot are contacted along
+    /// with the bootstrappers, so the peer can join the network even if none
+    /// of them is reachable. They are inserted in the routing table only once
+    /// they reply.
     async fn contact_bootstrappers(&self, min_peers: usize) {
         while self.need_bootstrappers(min_peers).await {
             info!("TableMaintainer::contact_bootstrappers");
-            let bootstrapping_nodes_addr = self.bootstrapping_nodes_addr();
+            let bootstrapping_nodes_addr = self
+                .bootstrapping_nodes_addr()
+                .into_iter()
+                .chain(self.restored_nodes.iter().copied())
+                .unique()
+                .collect();
             let binary_key = self.header.binary_id().as_binary();
             let find_nodes = Message::FindNodes(
                 self.header,
                 self.version.clone(),
                 *binary_key,
             );
-            self.send((find_nodes, bootstrapping_nodes_addr)).await;
+            self.metrics.inc(Counter::BootstrapRounds);
+            self.send(find_nodes, bootstrapping_nodes_addr).await;
             tokio::time::sleep(Duration::from_secs(30)).await;
         }
     }
 
-    async fn send(&self, message: MessageBeanOut) {
+    /// Send a protocol message, with [Priority::Control]
+    async fn send(&self, message: Message, targets: Vec<SocketAddr>) {
         self.outbound_sender
-            .send(message)
+            .send((message, targets, Priority::Control))
             .await
             .unwrap_or_else(|e| {
                 error!("Unable to send message from maintainer {e}")
@@ -127,9 +160,11 @@ impl TableMaintainer {
             .idle_nodes()
             .map(|n| *n.value().address())
             .collect();
-        self.send((Message::Ping(self.header, self.version.clone()), idles))
+        self.send(Message::Ping(self.header, self.version.clone()), idles)
             .await;
-        self.ktable.write().await.remove_idle_nodes();
+        let mut table = self.ktable.write().await;
+        table.remove_idle_nodes();
+        self.events.emit_table_changes(&mut table);
     }
 
     /// Searches for idle or empty buckets (those without received messages) in
@@ -142,9 +177,9 @@ impl TableMaintainer {
     /// generates a new target key that is used to search for additional
     /// nodes.
     ///
-    /// A set of active peers, up to `K_ALPHA`, is gathered from the current
-    /// routing table and combined with the bootstrapping nodes to form the
-    /// list of peers to contact.
+    /// A set of active peers, up to [Config::alpha], is gathered from the
+    /// current routing table and combined with the bootstrapping nodes to
+    /// form the list of peers to contact.
     ///
     /// The purpose of this method is to keep the routing table active and up to
     /// date by finding new peers whenever buckets are empty or nodes become
@@ -156,7 +191,7 @@ impl TableMaintainer {
         let alive_peers = table_lock_read
             .alive_nodes()
             .map(|n| n.as_peer_info().to_socket_address())
-            .take(K_ALPHA)
+            .take(self.alpha)
             .chain(self.bootstrapping_nodes_addr())
             .collect::<Vec<_>>();
 
@@ -164,7 +199,67 @@ impl TableMaintainer {
             let target = self.header.binary_id().get_at_distance(bucket_h);
             let msg =
                 Message::FindNodes(self.header, self.version.clone(), target);
-            self.send((msg, alive_peers.clone())).await;
+            self.metrics.inc(Counter::BucketRefreshes);
+            self.send(msg, alive_peers.clone()).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+    use tokio::time::Instant;
+
+    use super::*;
+    use crate::config::BucketConfig;
+    use crate::events::PeerEvent;
+    use crate::peer::PeerNode;
+    use crate::tests::Result;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_nodes_removal() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let node = PeerNode::generate("192.168.0.2:666", 0)?;
+        let address = *node.value().address();
+        let config = Config {
+            bucket: BucketConfig {
+                min_peers: 1,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut tree = Tree::new(root, config.bucket);
+        assert!(tree.insert(node).is_ok());
+
+        let events = EventSender::new(10);
+        let mut receiver = events.subscribe();
+        let (outbound_tx, mut outbound_rx) = mpsc::channel(1000);
+        let start = Instant::now();
+        let maintainer = TableMaintainer::start(
+            vec![],
+            crate::rwlock::new(tree),
+            outbound_tx,
+            events,
+            Arc::default(),
+            &config,
+        );
+
+        // The node is evicted once the bucket is idle, without waiting for
+        // its actual ttl
+        match receiver.recv().await? {
+            PeerEvent::PeerEvicted { address: a, .. } => assert_eq!(a, address),
+            e => panic!("Unexpected event {e:?}"),
+        }
+        assert!(start.elapsed() >= config.bucket.bucket_ttl);
+
+        let mut pinged = false;
+        while let Ok((message, targets, priority)) = outbound_rx.try_recv() {
+            pinged |= matches!(message, Message::Ping(..))
+                && targets == vec![address]
+                && priority == Priority::Control;
         }
+        assert!(pinged);
+        maintainer.abort();
+        Ok(())
     }
 }
@@ -0,0 +1,447 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tokio::sync::mpsc;
+
+use crate::kbucket::{BucketHeight, Tree};
+use crate::peer::PeerInfo;
+
+const COUNTERS: usize = Counter::ALL.len();
+
+/// Counters collected by a running [Peer](crate::Peer)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum Counter {
+    /// Datagrams received from the network
+    DatagramsIn,
+    /// Bytes received from the network
+    BytesIn,
+    /// Datagrams discarded because their source is blocked
+    DatagramsBlocked,
+    /// Datagrams dropped because their source exceeded
+    /// [source_packets_per_sec](crate::config::NetworkConfig::source_packets_per_sec)
+    PacketRateLimited,
+    /// Datagrams dropped because their source exceeded
+    /// [source_bytes_per_sec](crate::config::NetworkConfig::source_bytes_per_sec)
+    ByteRateLimited,
+    /// Sources blocked because their misbehaviour score reached the ban
+    /// threshold
+    SourcesBanned,
+    /// Datagrams sent to the network
+    DatagramsOut,
+    /// Bytes sent to the network
+    BytesOut,
+    /// Datagrams dropped because the socket failed to send them
+    SendErrors,
+    /// Datagrams or chunks which could not be decoded
+    DecodeErrors,
+    /// Encrypted sessions established with other peers
+    SessionsEstablished,
+    /// Encrypted datagrams which could not be authenticated and decrypted
+    UndecryptableDatagrams,
+    /// Datagrams neither sent nor accepted in plaintext, as encryption is
+    /// required
+    PlaintextRejected,
+    /// Handshakes discarded without being authenticated, as the
+    /// [handshake budget](crate::config::NetworkConfig::handshakes_per_sec)
+    /// was exhausted
+    HandshakesThrottled,
+    /// RaptorQ chunks received
+    ChunksReceived,
+    /// RaptorQ chunks received for an already decoded message
+    ChunkCacheHits,
+    /// Messages decoded from RaptorQ chunks
+    RaysDecoded,
+    /// Messages discarded before receiving enough RaptorQ chunks
+    RaysExpired,
+    /// Messages forwarded to the message handler
+    MessagesIn,
+    /// Messages discarded because of an header not matching their source
+    InvalidHeaders,
+    /// Nodes not inserted in the routing table because invalid, belonging
+    /// to another network or version, or exceeding the subnet limits
+    NodesRejected,
+    /// Broadcasted messages received
+    BroadcastsReceived,
+    /// Broadcasted messages ignored or rejected by the validator
+    BroadcastsDiscarded,
+    /// Broadcasted messages with an invalid or, if required, missing origin
+    /// signature
+    InvalidSignatures,
+    /// Requests received
+    RequestsReceived,
+    /// Requests dropped, as the
+    /// [max concurrent
+    /// requests](crate::config::Config::max_concurrent_requests) were being
+    /// answered
+    RequestsDropped,
+    /// Replies not sent to sources without a valid address token, as the
+    /// [reply budget](crate::config::NetworkConfig::unverified_replies_per_sec)
+    /// was exhausted
+    RepliesThrottled,
+    /// Messages which found an internal channel full
+    ChannelSaturated,
+    /// Attempts to contact the bootstrapping nodes
+    BootstrapRounds,
+    /// `FindNodes` sent to refresh idle or empty buckets
+    BucketRefreshes,
+}
+
+impl Counter {
+    /// Every counter, in the order they are rendered
+    pub const ALL: [Counter; 30] = [
+        Counter::DatagramsIn,
+        Counter::BytesIn,
+        Counter::DatagramsBlocked,
+        Counter::PacketRateLimited,
+        Counter::ByteRateLimited,
+        Counter::SourcesBanned,
+        Counter::DatagramsOut,
+        Counter::BytesOut,
+        Counter::SendErrors,
+        Counter::DecodeErrors,
+        Counter::SessionsEstablished,
+        Counter::UndecryptableDatagrams,
+        Counter::PlaintextRejected,
+        Counter::HandshakesThrottled,
+        Counter::ChunksReceived,
+        Counter::ChunkCacheHits,
+        Counter::RaysDecoded,
+        Counter::RaysExpired,
+        Counter::MessagesIn,
+        Counter::InvalidHeaders,
+        Counter::NodesRejected,
+        Counter::BroadcastsReceived,
+        Counter::BroadcastsDiscarded,
+        Counter::InvalidSignatures,
+        Counter::RequestsReceived,
+        Counter::RequestsDropped,
+        Counter::RepliesThrottled,
+        Counter::ChannelSaturated,
+        Counter::BootstrapRounds,
+        Counter::BucketRefreshes,
+    ];
+
+    /// Name of the counter in the Prometheus exposition
+    pub fn name(self) -> &'static str {
+        match self {
+            Counter::DatagramsIn => "kadcast_datagrams_in_total",
+            Counter::BytesIn => "kadcast_bytes_in_total",
+            Counter::DatagramsBlocked => "kadcast_datagrams_blocked_total",
+            Counter::PacketRateLimited => {
+                "kadcast_datagrams_packet_rate_limited_total"
+            }
+            Counter::ByteRateLimited => {
+                "kadcast_datagrams_byte_rate_limited_total"
+            }
+            Counter::SourcesBanned => "kadcast_sources_banned_total",
+            Counter::DatagramsOut => "kadcast_datagrams_out_total",
+            Counter::BytesOut => "kadcast_bytes_out_total",
+            Counter::SendErrors => "kadcast_send_errors_total",
+            Counter::DecodeErrors => "kadcast_decode_errors_total",
+            Counter::SessionsEstablished => {
+                "kadcast_sessions_established_total"
+            }
+            Counter::UndecryptableDatagrams => {
+                "kadcast_datagrams_undecryptable_total"
+            }
+            Counter::PlaintextRejected => "kadcast_plaintext_rejected_total",
+            Counter::HandshakesThrottled => {
+                "kadcast_handshakes_throttled_total"
+            }
+            Counter::ChunksReceived => "kadcast_chunks_received_total",
+            Counter::ChunkCacheHits => "kadcast_chunk_cache_hits_total",
+            Counter::RaysDecoded => "kadcast_rays_decoded_total",
+            Counter::RaysExpired => "kadcast_rays_expired_total",
+            Counter::MessagesIn => "kadcast_messages_in_total",
+            Counter::InvalidHeaders => "kadcast_invalid_headers_total",
+            Counter::NodesRejected => "kadcast_nodes_rejected_total",
+            Counter::BroadcastsReceived => "kadcast_broadcasts_received_total",
+            Counter::BroadcastsDiscarded => {
+                "kadcast_broadcasts_discarded_total"
+            }
+            Counter::InvalidSignatures => "kadcast_invalid_signatures_total",
+            Counter::RequestsReceived => "kadcast_requests_received_total",
+            Counter::RequestsDropped => "kadcast_requests_dropped_total",
+            Counter::RepliesThrottled => "kadcast_replies_throttled_total",
+            Counter::ChannelSaturated => "kadcast_channel_saturated_total",
+            Counter::BootstrapRounds => "kadcast_bootstrap_rounds_total",
+            Counter::BucketRefreshes => "kadcast_bucket_refreshes_total",
+        }
+    }
+
+    fn help(self) -> &'static str {
+        match self {
+            Counter::DatagramsIn => "Datagrams received from the network",
+            Counter::BytesIn => "Bytes received from the network",
+            Counter::DatagramsBlocked => {
+                "Datagrams discarded from blocked sources"
+            }
+            Counter::PacketRateLimited => {
+                "Datagrams dropped exceeding the per-source packet rate"
+            }
+            Counter::ByteRateLimited => {
+                "Datagrams dropped exceeding the per-source byte rate"
+            }
+            Counter::SourcesBanned => "Misbehaving sources banned",
+            Counter::DatagramsOut => "Datagrams sent to the network",
+            Counter::BytesOut => "Bytes sent to the network",
+            Counter::SendErrors => "Datagrams dropped by the socket",
+            Counter::DecodeErrors => "Datagrams or chunks not decoded",
+            Counter::SessionsEstablished => "Encrypted sessions established",
+            Counter::UndecryptableDatagrams => {
+                "Encrypted datagrams not decrypted"
+            }
+            Counter::PlaintextRejected => {
+                "Plaintext datagrams dropped as encryption is required"
+            }
+            Counter::HandshakesThrottled => "Handshakes exceeding the budget",
+            Counter::ChunksReceived => "RaptorQ chunks received",
+            Counter::ChunkCacheHits => {
+                "RaptorQ chunks received for already decoded messages"
+            }
+            Counter::RaysDecoded => "Messages decoded from RaptorQ chunks",
+            Counter::RaysExpired => "Messages expired before being decoded",
+            Counter::MessagesIn => "Messages forwarded to the handler",
+            Counter::InvalidHeaders => "Messages with an invalid header",
+            Counter::NodesRejected => "Nodes rejected by the routing table",
+            Counter::BroadcastsReceived => "Broadcasted messages received",
+            Counter::BroadcastsDiscarded => {
+                "Broadcasted messages discarded by the validator"
+            }
+            Counter::InvalidSignatures => {
+                "Broadcasted messages not authenticated by their origin"
+            }
+            Counter::RequestsReceived => "Requests received",
+            Counter::RequestsDropped => {
+                "Requests dropped while answering the max concurrent ones"
+            }
+            Counter::RepliesThrottled => {
+                "Replies to unverified sources exceeding the budget"
+            }
+            Counter::ChannelSaturated => "Messages finding a channel full",
+            Counter::BootstrapRounds => "Attempts to contact the bootstrappers",
+            Counter::BucketRefreshes => "Lookups sent to refresh buckets",
+        }
+    }
+}
+
+/// Usage of an internal channel at the time of a [MetricsSnapshot]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChannelUsage {
+    /// Name of the channel
+    pub name: &'static str,
+    /// Messages waiting in the channel
+    pub queued: usize,
+    /// Max amount of messages the channel can hold
+    pub capacity: usize,
+}
+
+type ChannelProbe = Box<dyn Fn() -> Option<(usize, usize)> + Send + Sync>;
+
+/// Metrics registry shared by the tasks of a [Peer](crate::Peer)
+#[derive(Default)]
+pub(crate) struct Metrics {
+    counters: [AtomicU64; COUNTERS],
+    channels: Mutex<Vec<(&'static str, ChannelProbe)>>,
+}
+
+impl Metrics {
+    pub(crate) fn inc(&self, counter: Counter) {
+        self.add(counter, 1)
+    }
+
+    pub(crate) fn add(&self, counter: Counter, value: u64) {
+        self.counters[counter as usize].fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Track the usage of the channel fed by `sender`, without keeping it
+    /// open
+    pub(crate) fn register_channel<T: Send + 'static>(
+        &self,
+        name: &'static str,
+        sender: &mpsc::Sender<T>,
+    ) {
+        let sender = sender.downgrade();
+        let probe = Box::new(move || {
+            let sender = sender.upgrade()?;
+            let capacity = sender.max_capacity();
+            Some((capacity - sender.capacity(), capacity))
+        });
+        self.channels
+            .lock()
+            .expect("Unpoisoned lock")
+            .push((name, probe));
+    }
+
+    /// Send `message` through `sender`, counting whether the channel is full
+    pub(crate) async fn send<T>(
+        &self,
+        sender: &mpsc::Sender<T>,
+        message: T,
+    ) -> Result<(), mpsc::error::SendError<T>> {
+        if sender.capacity() == 0 {
+            self.inc(Counter::ChannelSaturated);
+        }
+        sender.send(message).await
+    }
+
+    pub(crate) fn snapshot(&self, table: &Tree<PeerInfo>) -> MetricsSnapshot {
+        let counters =
+            std::array::from_fn(|i| self.counters[i].load(Ordering::Relaxed));
+        let buckets = table
+            .buckets()
+            .map(|(height, nodes)| (height, nodes.count()))
+            .collect();
+        let channels = self
+            .channels
+            .lock()
+            .expect("Unpoisoned lock")
+            .iter()
+            .filter_map(|(name, probe)| {
+                let (queued, capacity) = probe()?;
+                Some(ChannelUsage {
+                    name,
+                    queued,
+                    capacity,
+                })
+            })
+            .collect();
+        MetricsSnapshot {
+            counters,
+            buckets,
+            channels,
+        }
+    }
+}
+
+/// Snapshot of the metrics of a [Peer](crate::Peer), as returned by
+/// [Peer::metrics](crate::Peer::metrics)
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    counters: [u64; COUNTERS],
+    buckets: BTreeMap<BucketHeight, usize>,
+    channels: Vec<ChannelUsage>,
+}
+
+impl MetricsSnapshot {
+    /// Returns the value of `counter`
+    pub fn counter(&self, counter: Counter) -> u64 {
+        self.counters[counter as usize]
+    }
+
+    /// Returns the amount of nodes of each non-empty bucket of the routing
+    /// table
+    pub fn buckets(&self) -> &BTreeMap<BucketHeight, usize> {
+        &self.buckets
+    }
+
+    /// Returns the usage of the internal channels
+    pub fn channels(&self) -> &[ChannelUsage] {
+        &self.channels
+    }
+
+    /// Render the snapshot in the Prometheus text exposition format
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        for counter in Counter::ALL {
+            let name = counter.name();
+            let _ = writeln!(out, "# HELP {name} {}", counter.help());
+            let _ = writeln!(out, "# TYPE {name} counter");
+            let _ = writeln!(out, "{name} {}", self.counter(counter));
+        }
+
+        let name = "kadcast_bucket_nodes";
+        let _ =
+            writeln!(out, "# HELP {name} Nodes in each routing table bucket");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for (height, nodes) in &self.buckets {
+            let _ = writeln!(out, "{name}{{height=\"{height}\"}} {nodes}");
+        }
+
+        let name = "kadcast_channel_queued";
+        let _ = writeln!(out, "# HELP {name} Messages waiting in each channel");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for channel in &self.channels {
+            let channel_name = channel.name;
+            let queued = channel.queued;
+            let _ =
+                writeln!(out, "{name}{{channel=\"{channel_name}\"}} {queued}");
+        }
+
+        let name = "kadcast_channel_capacity";
+        let _ = writeln!(out, "# HELP {name} Capacity of each channel");
+        let _ = writeln!(out, "# TYPE {name} gauge");
+        for channel in &self.channels {
+            let channel_name = channel.name;
+            let capacity = channel.capacity;
+            let _ = writeln!(
+                out,
+                "{name}{{channel=\"{channel_name}\"}} {capacity}"
+            );
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::config::BucketConfig;
+    use crate::peer::PeerNode;
+    use crate::tests::Result;
+
+    #[test]
+    fn test_counter_index() {
+        for (i, counter) in Counter::ALL.into_iter().enumerate() {
+            assert_eq!(counter as usize, i);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prometheus_rendering() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let mut table = Tree::new(root, BucketConfig::default());
+        let node = PeerNode::generate("192.168.0.2:666", 0)?;
+        let height = table.root().calculate_distance(&node);
+        assert!(table.insert(node).is_ok());
+
+        let metrics = Metrics::default();
+        let (tx, _rx) = mpsc::channel(10);
+        metrics.register_channel("test", &tx);
+        metrics.send(&tx, ()).await?;
+        metrics.add(Counter::BytesIn, 42);
+        metrics.inc(Counter::DatagramsIn);
+
+        let snapshot = metrics.snapshot(&table);
+        assert_eq!(snapshot.counter(Counter::BytesIn), 42);
+        assert_eq!(snapshot.counter(Counter::ChannelSaturated), 0);
+        assert_eq!(snapshot.channels()[0].queued, 1);
+
+        let text = snapshot.to_prometheus();
+        assert!(text.contains("\nkadcast_bytes_in_total 42\n"));
+        assert!(text.contains("# TYPE kadcast_datagrams_in_total counter\n"));
+        let bucket = format!(
+            "kadcast_bucket_nodes{{height=\"{}\"}} 1\n",
+            height.unwrap()
+        );
+        assert!(text.contains(&bucket));
+        assert!(text.contains("kadcast_channel_queued{channel=\"test\"} 1\n"));
+        assert!(
+            text.contains("kadcast_channel_capacity{channel=\"test\"} 10\n")
+        );
+
+        drop(tx);
+        assert!(metrics.snapshot(&table).channels().is_empty());
+        Ok(())
+    }
+}
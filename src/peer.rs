@@ -12,18 +12,36 @@ use blake2::{Blake2s256, Digest};
 use crate::kbucket::{BinaryID, BinaryKey};
 pub type PeerNode = Node<PeerInfo>;
 use crate::K_ID_LEN_BYTES;
+use crate::amplification::NO_TOKEN;
 use crate::encoding::message::Header;
 use crate::encoding::payload::{IpInfo, PeerEncodedInfo};
-use crate::kbucket::Node;
+use crate::identity::{self, Identity, PublicKey, SIGNATURE_LEN};
+use crate::kbucket::{Node, NodeAddress};
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct PeerInfo {
     address: SocketAddr,
+    public_key: Option<PublicKey>,
+    // Signature of the address by the public key, only known for the local
+    // node
+    key_proof: Option<[u8; SIGNATURE_LEN]>,
 }
 
 impl PeerInfo {
     pub fn address(&self) -> &SocketAddr {
         &self.address
     }
+
+    /// Returns the public key the node id derives from, if the node runs with
+    /// an [Identity](crate::Identity)
+    pub fn public_key(&self) -> Option<&PublicKey> {
+        self.public_key.as_ref()
+    }
+}
+
+impl NodeAddress for PeerInfo {
+    fn ip(&self) -> IpAddr {
+        self.address.ip()
+    }
 }
 
 impl PeerNode {
@@ -32,25 +50,88 @@ impl PeerNode {
         network_id: u8,
     ) -> Result<Self, AddrParseError> {
         let address: SocketAddr = address.as_ref().parse()?;
-        let info = PeerInfo { address };
+        let info = PeerInfo {
+            address,
+            public_key: None,
+            key_proof: None,
+        };
         let binary =
             PeerNode::compute_id(&info.address.ip(), info.address.port());
         let id = BinaryID::generate(binary);
         Ok(Node::new(id, info, network_id))
     }
 
-    pub fn from_socket(
-        address: SocketAddr,
-        id: BinaryID,
+    /// Generate a node whose id derives from the public key of `identity`
+    /// instead of its address.
+    ///
+    /// Its headers carry the signature of `address`, to prove that it owns the
+    /// key at this address.
+    pub fn generate_with_identity(
+        address: impl AsRef<str>,
+        identity: &Identity,
         network_id: u8,
-    ) -> Self {
-        let info = PeerInfo { address };
-        Node::new(id, info, network_id)
+    ) -> Result<Self, AddrParseError> {
+        let address: SocketAddr = address.as_ref().parse()?;
+        let public_key = identity.public_key();
+        let info = PeerInfo {
+            address,
+            public_key: Some(public_key),
+            key_proof: Some(identity.sign_address(network_id, &address)),
+        };
+        let id = BinaryID::generate(PeerNode::compute_key_id(&public_key));
+        Ok(Node::new(id, info, network_id))
     }
 
+    /// Create the node which sent a message with `header` from `address`
+    pub(crate) fn from_header(address: SocketAddr, header: &Header) -> Self {
+        let info = PeerInfo {
+            address,
+            public_key: header.public_key,
+            key_proof: None,
+        };
+        Node::new(*header.binary_id(), info, header.network_id)
+    }
+
+    /// Check that the id of the header derives from the public key it carries
+    /// or, without a key, from the address of the sender
     pub(crate) fn verify_header(header: &Header, ip: &IpAddr) -> bool {
-        header.binary_id().as_binary()
-            == &PeerNode::compute_id(ip, header.sender_port)
+        let id = header.binary_id().as_binary();
+        match &header.public_key {
+            Some(public_key) => id == &PeerNode::compute_key_id(public_key),
+            None => id == &PeerNode::compute_id(ip, header.sender_port),
+        }
+    }
+
+    /// Check that the id of a peer advertised by another node derives from
+    /// its public key or, without a key, from its address
+    pub(crate) fn verify_peer_info(peer: &PeerEncodedInfo) -> bool {
+        match &peer.public_key {
+            Some(public_key) => peer.id == PeerNode::compute_key_id(public_key),
+            None => {
+                let address = peer.to_socket_address();
+                peer.id == PeerNode::compute_id(&address.ip(), address.port())
+            }
+        }
+    }
+
+    /// Check that the header proves that its public key is owned by the
+    /// sender at `address`.
+    ///
+    /// A header without key is bound to the address of its sender by its id.
+    pub(crate) fn verify_key_proof(
+        header: &Header,
+        address: &SocketAddr,
+    ) -> bool {
+        match (&header.public_key, &header.key_proof) {
+            (Some(public_key), Some(key_proof)) => identity::verify_address(
+                public_key,
+                header.network_id,
+                address,
+                key_proof,
+            ),
+            (Some(_), None) => false,
+            (None, _) => true,
+        }
     }
 
     pub(crate) fn compute_id(ip: &IpAddr, port: u16) -> BinaryKey {
@@ -66,12 +147,21 @@ impl PeerNode {
             .expect("compute_id length = K_ID_LEN_BYTES")
     }
 
+    pub(crate) fn compute_key_id(public_key: &PublicKey) -> BinaryKey {
+        let hash = Blake2s256::digest(public_key);
+        hash[..K_ID_LEN_BYTES]
+            .try_into()
+            .expect("compute_key_id length = K_ID_LEN_BYTES")
+    }
+
     pub(crate) fn to_header(&self) -> Header {
         Header {
             binary_id: *self.id(),
             sender_port: self.value().address.port(),
-            reserved: [0; 2],
+            token: NO_TOKEN,
             network_id: self.network_id,
+            public_key: self.value().public_key,
+            key_proof: self.value().key_proof,
         }
     }
 
@@ -83,6 +173,7 @@ impl PeerNode {
                 IpAddr::V6(ip) => IpInfo::IPv6(ip.octets()),
             },
             port: self.value().address.port(),
+            public_key: self.value().public_key().copied(),
         }
     }
 }
@@ -90,6 +181,7 @@ impl PeerNode {
 #[cfg(test)]
 mod tests {
 
+    use crate::Identity;
     use crate::peer::PeerNode;
     use crate::tests::Result;
     #[test]
@@ -97,7 +189,7 @@ mod tests {
         let wrong_header = PeerNode::generate("10.0.0.1:333", 0)?.to_header();
         let wrong_header_sameport =
             PeerNode::generate("10.0.0.1:666", 0)?.to_header();
-        vec![
+        [
             PeerNode::generate("192.168.1.1:666", 0)?,
             PeerNode::generate(
                 "[2001:0db8:85a3:0000:0000:8a2e:0370:7334]:666",
@@ -114,4 +206,87 @@ mod tests {
         });
         Ok(())
     }
+
+    #[test]
+    fn test_verify_key_header() -> Result<()> {
+        let identity = Identity::generate();
+        let node =
+            PeerNode::generate_with_identity("10.0.0.1:666", &identity, 0)?;
+        let header = node.to_header();
+        // The id derives from the key, wherever the node is
+        assert!(PeerNode::verify_header(&header, &"10.0.0.1".parse()?));
+        assert!(PeerNode::verify_header(&header, &"10.0.0.2".parse()?));
+
+        let mut impostor = header;
+        impostor.public_key = Some(Identity::generate().public_key());
+        assert!(!PeerNode::verify_header(&impostor, &"10.0.0.1".parse()?));
+        let mut unkeyed = header;
+        unkeyed.public_key = None;
+        assert!(!PeerNode::verify_header(&unkeyed, &"10.0.0.1".parse()?));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_key_proof() -> Result<()> {
+        let node = PeerNode::generate_with_identity(
+            "10.0.0.1:666",
+            &Identity::generate(),
+            0,
+        )?;
+        let header = node.to_header();
+        assert!(PeerNode::verify_key_proof(
+            &header,
+            &"10.0.0.1:666".parse()?
+        ));
+
+        // The key can't be claimed from another address
+        assert!(!PeerNode::verify_key_proof(
+            &header,
+            &"10.0.0.2:666".parse()?
+        ));
+        assert!(!PeerNode::verify_key_proof(
+            &header,
+            &"10.0.0.1:667".parse()?
+        ));
+        let mut unproven = header;
+        unproven.key_proof = None;
+        assert!(!PeerNode::verify_key_proof(
+            &unproven,
+            &"10.0.0.1:666".parse()?
+        ));
+
+        // Unkeyed nodes are bound to their address by their id
+        let unkeyed = PeerNode::generate("10.0.0.1:666", 0)?.to_header();
+        assert!(PeerNode::verify_key_proof(
+            &unkeyed,
+            &"10.0.0.1:666".parse()?
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_verify_peer_info() -> Result<()> {
+        let node = PeerNode::generate("10.0.0.1:666", 0)?;
+        assert!(PeerNode::verify_peer_info(&node.as_peer_info()));
+        let mut forged = node.as_peer_info();
+        forged.port = 667;
+        assert!(!PeerNode::verify_peer_info(&forged));
+
+        // Keyed ids are checked against the key, whatever the address
+        let keyed = PeerNode::generate_with_identity(
+            "10.0.0.2:666",
+            &Identity::generate(),
+            0,
+        )?;
+        let mut moved = keyed.as_peer_info();
+        moved.port = 667;
+        assert!(PeerNode::verify_peer_info(&moved));
+        let mut impostor = keyed.as_peer_info();
+        impostor.public_key = Some(Identity::generate().public_key());
+        assert!(!PeerNode::verify_peer_info(&impostor));
+        let mut unkeyed = keyed.as_peer_info();
+        unkeyed.public_key = None;
+        assert!(!PeerNode::verify_peer_info(&unkeyed));
+        Ok(())
+    }
 }
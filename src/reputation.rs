@@ -0,0 +1,260 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::blocklist::{BlockedSource, Blocklist};
+use crate::config::ReputationConfig;
+use crate::events::EventSender;
+use crate::kbucket::Tree;
+use crate::metrics::{Counter, Metrics};
+use crate::peer::PeerInfo;
+use crate::{RwLock, block_source};
+
+// Scores decayed below this value are forgotten
+const MIN_SCORE: f64 = 1.0;
+
+/// Protocol violation penalizing the score of its source
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Misbehaviour {
+    /// Header not matching the source of the message
+    InvalidHeader,
+    /// Datagram which could not be deserialized
+    UndecodableDatagram,
+    /// RaptorQ chunk too short or with bogus transmission info
+    InvalidChunk,
+    /// Message decoded from RaptorQ chunks failing the ray-id integrity
+    /// check.
+    ///
+    /// The source is the one of the last chunk, which is not necessarily
+    /// the one of the bogus chunks: the penalty is lower accordingly.
+    CorruptedMessage,
+    /// Broadcast rejected by the
+    /// [BroadcastValidator](crate::BroadcastValidator), with
+    /// [Config::penalize_rejected](crate::config::Config::penalize_rejected)
+    /// enabled.
+    ///
+    /// The source is the peer forwarding the message, usually an honest
+    /// relay rather than its author: the penalty is lower accordingly.
+    RejectedBroadcast,
+}
+
+impl Misbehaviour {
+    fn penalty(&self) -> f64 {
+        match self {
+            Misbehaviour::InvalidHeader => 25.0,
+            Misbehaviour::UndecodableDatagram => 10.0,
+            Misbehaviour::InvalidChunk => 25.0,
+            Misbehaviour::CorruptedMessage => 10.0,
+            Misbehaviour::RejectedBroadcast => 10.0,
+        }
+    }
+}
+
+struct Score {
+    value: f64,
+    updated: Instant,
+}
+
+/// Misbehaviour scores of the source IPs, decaying over time
+struct Scores {
+    scores: HashMap<IpAddr, Score>,
+    config: ReputationConfig,
+    last_pruned: Instant,
+}
+
+impl Scores {
+    fn new(config: ReputationConfig) -> Self {
+        Self {
+            scores: HashMap::new(),
+            config,
+            last_pruned: Instant::now(),
+        }
+    }
+
+    fn decayed(&self, score: &Score, now: Instant) -> f64 {
+        let elapsed = now.duration_since(score.updated).as_secs_f64();
+        let half_lives = elapsed / self.config.score_half_life.as_secs_f64();
+        score.value * 0.5f64.powf(half_lives)
+    }
+
+    /// Add the penalty of `misbehaviour` to the score of `ip`, returning
+    /// `true` if the source must be banned
+    fn penalize(&mut self, ip: IpAddr, misbehaviour: Misbehaviour) -> bool {
+        let now = Instant::now();
+        if now.duration_since(self.last_pruned) > self.config.score_half_life {
+            let scores = std::mem::take(&mut self.scores);
+            self.scores = scores
+                .into_iter()
+                .filter(|(_, score)| self.decayed(score, now) >= MIN_SCORE)
+                .collect();
+            self.last_pruned = now;
+        }
+
+        let value = self
+            .scores
+            .get(&ip)
+            .map(|score| self.decayed(score, now))
+            .unwrap_or_default()
+            + misbehaviour.penalty();
+        let ban = self.config.auto_ban && value >= self.config.ban_threshold;
+        match ban {
+            true => self.scores.remove(&ip),
+            false => self.scores.insert(
+                ip,
+                Score {
+                    value,
+                    updated: now,
+                },
+            ),
+        };
+        ban
+    }
+
+    fn all(&self) -> BTreeMap<IpAddr, f64> {
+        let now = Instant::now();
+        self.scores
+            .iter()
+            .map(|(ip, score)| (*ip, self.decayed(score, now)))
+            .filter(|(_, value)| *value >= MIN_SCORE)
+            .collect()
+    }
+}
+
+/// Tracks the misbehaviour of the sources, blocking the ones exceeding the
+/// configured threshold
+#[derive(Clone)]
+pub(crate) struct Reputation {
+    scores: Arc<Mutex<Scores>>,
+    ban_duration: Option<Duration>,
+    blocklist: RwLock<Blocklist>,
+    ktable: RwLock<Tree<PeerInfo>>,
+    events: EventSender,
+    metrics: Arc<Metrics>,
+}
+
+impl Reputation {
+    pub(crate) fn new(
+        config: &ReputationConfig,
+        blocklist: RwLock<Blocklist>,
+        ktable: RwLock<Tree<PeerInfo>>,
+        events: EventSender,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        Self {
+            scores: Arc::new(Mutex::new(Scores::new(config.clone()))),
+            ban_duration: config.ban_duration,
+            blocklist,
+            ktable,
+            events,
+            metrics,
+        }
+    }
+
+    /// The blocklist of the sources banned, either manually or
+    /// automatically
+    pub(crate) fn blocklist(&self) -> &RwLock<Blocklist> {
+        &self.blocklist
+    }
+
+    /// Penalize `ip` for `misbehaviour`, banning it if its score reaches the
+    /// threshold
+    pub(crate) async fn penalize(
+        &self,
+        ip: IpAddr,
+        misbehaviour: Misbehaviour,
+    ) {
+        let ban = self
+            .scores
+            .lock()
+            .expect("Unpoisoned lock")
+            .penalize(ip, misbehaviour);
+        debug!(event = "source penalized", src = %ip, ?misbehaviour);
+        if ban {
+            warn!(event = "source banned", src = %ip, duration = ?self.ban_duration);
+            self.metrics.inc(Counter::SourcesBanned);
+            self.block(ip.into(), self.ban_duration).await;
+        }
+    }
+
+    /// Block `source`, for `duration` if any, or permanently
+    pub(crate) async fn block(
+        &self,
+        source: BlockedSource,
+        duration: Option<Duration>,
+    ) {
+        block_source(
+            &self.blocklist,
+            &self.ktable,
+            &self.events,
+            source,
+            duration,
+        )
+        .await
+    }
+
+    /// Returns the current score of every source penalized recently
+    pub(crate) fn scores(&self) -> BTreeMap<IpAddr, f64> {
+        self.scores.lock().expect("Unpoisoned lock").all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scores() {
+        let config = ReputationConfig {
+            auto_ban: true,
+            ban_threshold: 50.0,
+            score_half_life: Duration::from_secs(10),
+            ..Default::default()
+        };
+        let mut scores = Scores::new(config);
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+
+        assert!(!scores.penalize(ip, Misbehaviour::InvalidHeader));
+        assert_eq!(scores.all().get(&ip), Some(&25.0));
+
+        // Decays by half
+        tokio::time::advance(Duration::from_secs(10)).await;
+        assert_eq!(scores.all().get(&ip), Some(&12.5));
+        assert!(!scores.penalize(ip, Misbehaviour::InvalidHeader));
+
+        // Reaches the threshold, the score is reset
+        assert!(scores.penalize(ip, Misbehaviour::InvalidChunk));
+        assert!(scores.all().is_empty());
+
+        // Forgotten once decayed
+        scores.penalize(ip, Misbehaviour::UndecodableDatagram);
+        tokio::time::advance(Duration::from_secs(40)).await;
+        assert!(scores.all().is_empty());
+        scores
+            .penalize("10.1.2.4".parse().unwrap(), Misbehaviour::InvalidChunk);
+        assert_eq!(scores.scores.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_scores_without_auto_ban() {
+        let config = ReputationConfig {
+            auto_ban: false,
+            ..Default::default()
+        };
+        let mut scores = Scores::new(config);
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        for _ in 0..10 {
+            assert!(!scores.penalize(ip, Misbehaviour::InvalidHeader));
+        }
+        assert_eq!(scores.all().get(&ip), Some(&250.0));
+    }
+}
@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::oneshot;
+use tracing::debug;
+
+/// Future returned by [Responder::respond]
+pub type ResponseFuture<'a> =
+    Pin<Box<dyn Future<Output = Option<Vec<u8>>> + Send + 'a>>;
+
+/// The [Responder] trait is consulted whenever a request sent through
+/// [Peer::request](crate::Peer::request) is received from the network.
+///
+/// Returning `None` leaves the request unanswered, so the requester will
+/// eventually time out.
+pub trait Responder: Send + Sync {
+    fn respond<'a>(
+        &'a self,
+        request: &'a [u8],
+        source: SocketAddr,
+    ) -> ResponseFuture<'a>;
+}
+
+struct Pending {
+    target: SocketAddr,
+    sender: oneshot::Sender<Vec<u8>>,
+}
+
+/// Requests sent by the local peer which are still waiting for a response
+#[derive(Clone, Default)]
+pub(crate) struct PendingRequests {
+    inner: Arc<Mutex<HashMap<u64, Pending>>>,
+}
+
+/// Registration of a pending request, removed once dropped
+pub(crate) struct PendingGuard {
+    pending: PendingRequests,
+    request_id: u64,
+}
+
+impl PendingRequests {
+    /// Register a request sent to `target`, returning `None` if `request_id`
+    /// is already pending.
+    pub(crate) fn register(
+        &self,
+        request_id: u64,
+        target: SocketAddr,
+    ) -> Option<(PendingGuard, oneshot::Receiver<Vec<u8>>)> {
+        let mut inner = self.inner.lock().expect("Unpoisoned lock");
+        if inner.contains_key(&request_id) {
+            return None;
+        }
+        let (sender, receiver) = oneshot::channel();
+        inner.insert(request_id, Pending { target, sender });
+        let guard = PendingGuard {
+            pending: self.clone(),
+            request_id,
+        };
+        Some((guard, receiver))
+    }
+
+    /// Complete the pending request with the response received from `source`.
+    ///
+    /// Responses coming from any address other than the request target are
+    /// discarded.
+    pub(crate) fn complete(
+        &self,
+        request_id: u64,
+        source: SocketAddr,
+        response: Vec<u8>,
+    ) {
+        let mut inner = self.inner.lock().expect("Unpoisoned lock");
+        match inner.get(&request_id) {
+            Some(pending) if pending.target == source => {
+                if let Some(pending) = inner.remove(&request_id) {
+                    // The requester may have given up in the meantime
+                    let _ = pending.sender.send(response);
+                }
+            }
+            _ => debug!("Discarding unexpected response from {source}"),
+        }
+    }
+}
+
+impl Drop for PendingGuard {
+    fn drop(&mut self) {
+        self.pending
+            .inner
+            .lock()
+            .expect("Unpoisoned lock")
+            .remove(&self.request_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_pending_requests() {
+        let pending = PendingRequests::default();
+        let target = "192.168.0.1:666".parse().unwrap();
+        let other = "192.168.0.2:666".parse().unwrap();
+
+        let (guard, mut rx) = pending.register(1, target).unwrap();
+        assert!(pending.register(1, target).is_none());
+
+        pending.complete(1, other, vec![1]);
+        assert!(rx.try_recv().is_err());
+        pending.complete(1, target, vec![2]);
+        assert_eq!(rx.try_recv().unwrap(), vec![2]);
+
+        drop(guard);
+        let (guard, _rx) = pending.register(2, target).unwrap();
+        drop(guard);
+        assert!(pending.inner.lock().unwrap().is_empty());
+    }
+}
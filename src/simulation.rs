@@ -0,0 +1,210 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+//! Harness running many [Peer]s over a [MemoryNetwork], in order to evaluate
+//! the broadcast coverage and latency under simulated network conditions.
+
+use std::io::{self, ErrorKind};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, timeout_at};
+
+use crate::Peer;
+use crate::config::Config;
+use crate::transport::MemoryNetwork;
+
+const SIMULATION_PORT: u16 = 9000;
+
+type Delivery = (usize, Vec<u8>, Instant);
+
+/// A set of [Peer]s connected through a [MemoryNetwork]
+pub struct Simulation {
+    network: MemoryNetwork,
+    peers: Vec<Peer>,
+    addresses: Vec<SocketAddr>,
+    deliveries: mpsc::UnboundedReceiver<Delivery>,
+    collectors: Vec<JoinHandle<()>>,
+}
+
+/// Outcome of a [Simulation::broadcast]
+#[derive(Debug, Clone)]
+pub struct BroadcastReport {
+    expected: usize,
+    latencies: Vec<(usize, Duration)>,
+}
+
+impl Simulation {
+    /// Start `nodes` peers configured after `config`.
+    ///
+    /// Each peer is assigned its own address, while the first peer is used as
+    /// the bootstrapper of all the others. The peers are not given any time to
+    /// discover each other, see [Simulation::settle].
+    pub fn start(nodes: usize, config: Config) -> io::Result<Self> {
+        let network = MemoryNetwork::new();
+        let addresses: Vec<_> = (0..nodes).map(Self::address_of).collect();
+        let bootstrap: Vec<_> =
+            addresses.iter().take(1).map(|a| a.to_string()).collect();
+        let (deliveries_tx, deliveries) = mpsc::unbounded_channel();
+
+        let mut peers = Vec::with_capacity(nodes);
+        let mut collectors = Vec::with_capacity(nodes);
+        for (index, address) in addresses.iter().enumerate() {
+            let config = Config {
+                public_address: address.to_string(),
+                listen_address: None,
+                bootstrapping_nodes: bootstrap.clone(),
+                ..config.clone()
+            };
+            let (peer, mut receiver) = Peer::builder(config)
+                .transport(network.transport(*address)?)
+                .build_with_receiver()
+                .map_err(|e| io::Error::new(ErrorKind::InvalidInput, e))?;
+            let deliveries_tx = deliveries_tx.clone();
+            collectors.push(tokio::spawn(async move {
+                while let Some((message, _)) = receiver.recv().await {
+                    let delivery = (index, message, Instant::now());
+                    if deliveries_tx.send(delivery).is_err() {
+                        break;
+                    }
+                }
+            }));
+            peers.push(peer);
+        }
+        Ok(Self {
+            network,
+            peers,
+            addresses,
+            deliveries,
+            collectors,
+        })
+    }
+
+    fn address_of(index: usize) -> SocketAddr {
+        // Skip the network address 10.0.0.0
+        let ip = Ipv4Addr::from(
+            u32::from(Ipv4Addr::new(10, 0, 0, 1)) + index as u32,
+        );
+        SocketAddr::new(ip.into(), SIMULATION_PORT)
+    }
+
+    /// Returns the network connecting the peers, to script its conditions
+    pub fn network(&self) -> &MemoryNetwork {
+        &self.network
+    }
+
+    /// Returns the amount of peers
+    pub fn len(&self) -> usize {
+        self.peers.len()
+    }
+
+    /// Returns `true` if the simulation has no peer
+    pub fn is_empty(&self) -> bool {
+        self.peers.is_empty()
+    }
+
+    /// Returns the peer at `index`
+    pub fn peer(&self, index: usize) -> &Peer {
+        &self.peers[index]
+    }
+
+    /// Returns the address of the peer at `index`
+    pub fn address(&self, index: usize) -> SocketAddr {
+        self.addresses[index]
+    }
+
+    /// Let the peers exchange messages for `duration`, so that they can fill
+    /// their routing tables
+    pub async fn settle(&self, duration: Duration) {
+        tokio::time::sleep(duration).await;
+    }
+
+    /// Broadcast `message` from the peer at `from` and wait until every other
+    /// peer receives it, or `timeout` expires.
+    ///
+    /// Messages received in the meantime which differ from `message` are
+    /// discarded.
+    pub async fn broadcast(
+        &mut self,
+        from: usize,
+        message: &[u8],
+        timeout: Duration,
+    ) -> BroadcastReport {
+        // Discard the deliveries of previous broadcasts
+        while self.deliveries.try_recv().is_ok() {}
+
+        let start = Instant::now();
+        let deadline = start + timeout;
+        self.peers[from].broadcast(message, None).await;
+
+        let mut received = vec![false; self.peers.len()];
+        received[from] = true;
+        let mut latencies = vec![];
+        while latencies.len() < self.peers.len() - 1 {
+            let delivery = timeout_at(deadline, self.deliveries.recv()).await;
+            let Ok(Some((index, delivered, at))) = delivery else {
+                break;
+            };
+            if delivered == message && !received[index] {
+                received[index] = true;
+                latencies.push((index, at - start));
+            }
+        }
+        BroadcastReport {
+            expected: self.peers.len() - 1,
+            latencies,
+        }
+    }
+
+    /// Gracefully shutdown every peer
+    pub async fn shutdown(mut self) {
+        self.collectors.iter().for_each(JoinHandle::abort);
+        for peer in std::mem::take(&mut self.peers) {
+            peer.shutdown().await;
+        }
+    }
+}
+
+impl Drop for Simulation {
+    fn drop(&mut self) {
+        self.collectors.iter().for_each(JoinHandle::abort);
+    }
+}
+
+impl BroadcastReport {
+    /// Returns the amount of peers which received the message
+    pub fn received(&self) -> usize {
+        self.latencies.len()
+    }
+
+    /// Returns the fraction (between 0 and 1) of the peers, besides the
+    /// sender, which received the message
+    pub fn coverage(&self) -> f64 {
+        match self.expected {
+            0 => 1.0,
+            expected => self.received() as f64 / expected as f64,
+        }
+    }
+
+    /// Returns the indexes of the peers which received the message, along
+    /// with the time elapsed since the broadcast, the fastest first
+    pub fn latencies(&self) -> &[(usize, Duration)] {
+        &self.latencies
+    }
+
+    /// Returns the time needed by the slowest peer to receive the message
+    pub fn max_latency(&self) -> Option<Duration> {
+        self.latencies.last().map(|(_, latency)| *latency)
+    }
+
+    /// Returns the time needed by half of the peers to receive the message
+    pub fn median_latency(&self) -> Option<Duration> {
+        let median = self.latencies.len().checked_sub(1)? / 2;
+        self.latencies.get(median).map(|(_, latency)| *latency)
+    }
+}
@@ -0,0 +1,331 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::fs;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use tokio::task;
+use tracing::{debug, info, warn};
+
+use crate::RwLock;
+use crate::encoding::Marshallable;
+use crate::encoding::payload::PeerEncodedInfo;
+use crate::kbucket::{BinaryID, BinaryNonce, BucketHeight, Tree};
+use crate::peer::{PeerInfo, PeerNode};
+
+// Snapshot format version, bumped on every breaking change of the encoding
+const SNAPSHOT_VERSION: u8 = 2;
+
+/// Binary snapshot of a routing table
+#[derive(Debug, PartialEq)]
+pub(crate) struct TableSnapshot {
+    network_id: u8,
+    // Unix time (in seconds) the snapshot has been taken at
+    saved_at: u64,
+    entries: Vec<SnapshotEntry>,
+}
+
+#[derive(Debug, PartialEq)]
+struct SnapshotEntry {
+    peer: PeerEncodedInfo,
+    nonce: BinaryNonce,
+    height: BucketHeight,
+    // Seconds elapsed since the node has been seen, at `saved_at`
+    age: u64,
+}
+
+impl TableSnapshot {
+    pub(crate) fn from_tree(tree: &Tree<PeerInfo>) -> Self {
+        let entries = tree
+            .buckets()
+            .flat_map(|(height, nodes)| {
+                nodes.map(move |node| SnapshotEntry {
+                    peer: node.as_peer_info(),
+                    nonce: *node.id().nonce(),
+                    height,
+                    age: node.seen_at().elapsed().as_secs(),
+                })
+            })
+            .collect();
+        Self {
+            network_id: tree.root().network_id,
+            saved_at: unix_now(),
+            entries,
+        }
+    }
+
+    /// Return the addresses of the nodes which can be contacted by `root`,
+    /// the most recently seen first.
+    ///
+    /// Nodes belonging to another network, with an invalid nonce or with an
+    /// id not matching their public key (or their address, for the nodes
+    /// running without identity) are discarded.
+    pub(crate) fn restorable_nodes(&self, root: &PeerNode) -> Vec<SocketAddr> {
+        if self.network_id != root.network_id {
+            warn!(
+                "Discarding routing table snapshot of network {}",
+                self.network_id
+            );
+            return vec![];
+        }
+        let mut entries: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|e| BinaryID::from_nonce(e.peer.id, e.nonce).is_ok())
+            .filter(|e| PeerNode::verify_peer_info(&e.peer))
+            .filter(|e| &e.peer.id != root.id().as_binary())
+            .collect();
+        entries.sort_by_key(|e| e.age);
+        entries.iter().map(|e| e.peer.to_socket_address()).collect()
+    }
+
+    /// Time elapsed since the snapshot has been taken
+    pub(crate) fn elapsed(&self) -> Duration {
+        Duration::from_secs(unix_now().saturating_sub(self.saved_at))
+    }
+
+    fn bytes(&self) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        self.marshal_binary(&mut bytes)?;
+        Ok(bytes)
+    }
+}
+
+impl Marshallable for TableSnapshot {
+    fn marshal_binary<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&[SNAPSHOT_VERSION, self.network_id])?;
+        writer.write_all(&self.saved_at.to_le_bytes())?;
+        let len = self.entries.len() as u32;
+        writer.write_all(&len.to_le_bytes())?;
+        for entry in &self.entries {
+            entry.peer.marshal_binary(writer)?;
+            entry.peer.marshal_public_key(writer)?;
+            writer.write_all(&entry.nonce)?;
+            writer.write_all(&[entry.height])?;
+            writer.write_all(&entry.age.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn unmarshal_binary<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut head = [0; 2];
+        reader.read_exact(&mut head)?;
+        let [version, network_id] = head;
+        if version != SNAPSHOT_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("Unsupported snapshot version {version}"),
+            ));
+        }
+        let mut saved_at = [0; 8];
+        reader.read_exact(&mut saved_at)?;
+        let mut len = [0; 4];
+        reader.read_exact(&mut len)?;
+        let mut entries = vec![];
+        for _ in 0..u32::from_le_bytes(len) {
+            let mut peer = PeerEncodedInfo::unmarshal_binary(reader)?;
+            peer.unmarshal_public_key(reader)?;
+            let mut nonce = [0; 4];
+            reader.read_exact(&mut nonce)?;
+            let mut height = [0; 1];
+            reader.read_exact(&mut height)?;
+            let mut age = [0; 8];
+            reader.read_exact(&mut age)?;
+            entries.push(SnapshotEntry {
+                peer,
+                nonce,
+                height: height[0],
+                age: u64::from_le_bytes(age),
+            });
+        }
+        Ok(TableSnapshot {
+            network_id,
+            saved_at: u64::from_le_bytes(saved_at),
+            entries,
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Save a snapshot of the routing table to `path`.
+///
+/// The snapshot is written to a temporary file first, so an interrupted save
+/// never corrupts the previous one.
+pub(crate) async fn save(
+    ktable: &RwLock<Tree<PeerInfo>>,
+    path: &Path,
+) -> io::Result<()> {
+    let bytes = TableSnapshot::from_tree(&*ktable.read().await).bytes()?;
+    let path = path.to_path_buf();
+    task::spawn_blocking(move || {
+        let tmp_path = path.with_extension("tmp");
+        fs::write(&tmp_path, bytes)?;
+        fs::rename(&tmp_path, &path)?;
+        debug!("Routing table saved to {}", path.display());
+        Ok(())
+    })
+    .await
+    .map_err(io::Error::other)?
+}
+
+/// Save a snapshot of the routing table to `path` every `interval`
+pub(crate) async fn persist(
+    ktable: RwLock<Tree<PeerInfo>>,
+    path: PathBuf,
+    interval: Duration,
+) {
+    loop {
+        tokio::time::sleep(interval).await;
+        save(&ktable, &path).await.unwrap_or_else(|e| {
+            warn!("Unable to save routing table to {}: {e}", path.display())
+        });
+    }
+}
+
+/// Load the snapshot saved at `path` and return the addresses of the nodes
+/// `root` should contact in order to restore its routing table.
+pub(crate) fn restore(path: &Path, root: &PeerNode) -> Vec<SocketAddr> {
+    let snapshot = fs::read(path)
+        .and_then(|bytes| TableSnapshot::unmarshal_binary(&mut &bytes[..]));
+    match snapshot {
+        Ok(snapshot) => {
+            let nodes = snapshot.restorable_nodes(root);
+            info!(
+                "Restoring {} nodes from routing table snapshot ({:?} old)",
+                nodes.len(),
+                snapshot.elapsed()
+            );
+            nodes
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            debug!("No routing table snapshot at {}", path.display());
+            vec![]
+        }
+        Err(e) => {
+            warn!("Unable to load routing table from {}: {e}", path.display());
+            vec![]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::Identity;
+    use crate::config::BucketConfig;
+    use crate::tests::Result;
+
+    #[test]
+    fn test_snapshot_roundtrip() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let mut tree = Tree::new(root, BucketConfig::default());
+        for i in 2..12 {
+            let node = PeerNode::generate(format!("192.168.0.{i}:666"), 0)?;
+            assert!(tree.insert(node).is_ok());
+        }
+        let other_network = PeerNode::generate("192.168.0.12:666", 1)?;
+        assert!(tree.insert(other_network).is_err());
+        // Restorable whether the root runs with an identity or not
+        let keyed = PeerNode::generate_with_identity(
+            "10.1.0.1:666",
+            &Identity::generate(),
+            0,
+        )?;
+        assert!(tree.insert(keyed).is_ok());
+
+        let snapshot = TableSnapshot::from_tree(&tree);
+        let bytes = snapshot.bytes()?;
+        let decoded = TableSnapshot::unmarshal_binary(&mut &bytes[..])?;
+        assert_eq!(decoded, snapshot);
+
+        let mut restored = decoded.restorable_nodes(tree.root());
+        let mut expected: Vec<_> =
+            tree.alive_nodes().map(|n| *n.value().address()).collect();
+        restored.sort();
+        expected.sort();
+        assert_eq!(restored, expected);
+        assert_eq!(restored.len(), 11);
+
+        let other_root = PeerNode::generate("192.168.0.1:666", 1)?;
+        assert!(decoded.restorable_nodes(&other_root).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_snapshot_discard_invalid() -> Result<()> {
+        let root = PeerNode::generate("192.168.0.1:666", 0)?;
+        let node = PeerNode::generate("192.168.0.2:666", 0)?;
+        let invalid_nonce = (0u32..)
+            .map(u32::to_le_bytes)
+            .find(|n| BinaryID::from_nonce(*node.id().as_binary(), *n).is_err())
+            .expect("an invalid nonce");
+        let mut forged = node.as_peer_info();
+        forged.port = 667;
+        let keyed = PeerNode::generate_with_identity(
+            "192.168.0.3:666",
+            &Identity::generate(),
+            0,
+        )?;
+        let mut impostor = keyed.as_peer_info();
+        impostor.public_key = Some(Identity::generate().public_key());
+        let mut unkeyed = keyed.as_peer_info();
+        unkeyed.public_key = None;
+        let snapshot = TableSnapshot {
+            network_id: 0,
+            saved_at: unix_now(),
+            entries: vec![
+                SnapshotEntry {
+                    peer: node.as_peer_info(),
+                    nonce: invalid_nonce,
+                    height: 0,
+                    age: 0,
+                },
+                SnapshotEntry {
+                    peer: forged,
+                    nonce: *node.id().nonce(),
+                    height: 0,
+                    age: 0,
+                },
+                SnapshotEntry {
+                    peer: impostor,
+                    nonce: *keyed.id().nonce(),
+                    height: 0,
+                    age: 0,
+                },
+                SnapshotEntry {
+                    peer: unkeyed,
+                    nonce: *keyed.id().nonce(),
+                    height: 0,
+                    age: 0,
+                },
+                SnapshotEntry {
+                    peer: root.as_peer_info(),
+                    nonce: *root.id().nonce(),
+                    height: 0,
+                    age: 0,
+                },
+            ],
+        };
+        assert!(snapshot.restorable_nodes(&root).is_empty());
+
+        let bytes = snapshot.bytes()?;
+        assert!(
+            TableSnapshot::unmarshal_binary(&mut &bytes[..bytes.len() - 1])
+                .is_err()
+        );
+        Ok(())
+    }
+}
@@ -4,102 +4,187 @@
 //
 // Copyright (c) DUSK NETWORK. All rights reserved.
 
-use std::collections::HashSet;
+use std::future::Future;
 use std::net::SocketAddr;
-use std::time::Instant;
+use std::pin::Pin;
+use std::sync::Arc;
 
-use socket2::SockRef;
 use tokio::io;
-use tokio::net::UdpSocket;
+use tokio::sync::Notify;
 use tokio::sync::mpsc::{self, Receiver, Sender};
-use tracing::{debug, error, info, trace, warn};
+use tokio::task::JoinHandle;
+use tokio::time::{self, Instant};
+use tracing::{debug, error, trace};
 
+use crate::amplification::AddressTokens;
+use crate::blocklist::Blocklist;
 use crate::config::Config;
 use crate::encoding::Marshallable;
 use crate::encoding::message::Message;
+use crate::metrics::{Counter, Metrics};
+use crate::reputation::{Misbehaviour, Reputation};
 use crate::rwlock::RwLock;
 use crate::transport::encoding::{
     Configurable, Decoder, Encoder, TransportDecoder, TransportEncoder,
 };
-use crate::transport::sockets::MultipleOutSocket;
+use crate::transport::limiter::{Exceeded, RateLimiter};
+pub use crate::transport::memory::{
+    LinkConditions, MemoryNetwork, MemoryTransport,
+};
+use crate::transport::scheduler::{Priority, Scheduler};
+pub(crate) use crate::transport::secure::SecureTransport;
+pub(crate) use crate::transport::udp::UdpTransport;
 
-pub(crate) type MessageBeanOut = (Message, Vec<SocketAddr>);
+pub(crate) type MessageBeanOut = (Message, Vec<SocketAddr>, Priority);
 pub(crate) type MessageBeanIn = (Message, SocketAddr);
 type UDPChunk = (Vec<u8>, SocketAddr);
 
 const MAX_DATAGRAM_SIZE: usize = 65_507;
 pub(crate) struct WireNetwork {}
 
+/// Handles of the tasks spawned by [WireNetwork::start]
+pub(crate) struct WireNetworkTasks {
+    pub(crate) outgoing: JoinHandle<()>,
+    /// Notified to flush the queued messages and stop the outgoing task
+    pub(crate) outgoing_shutdown: Arc<Notify>,
+    pub(crate) decoder: JoinHandle<()>,
+    pub(crate) incoming: JoinHandle<()>,
+}
+
 pub(crate) mod encoding;
+mod limiter;
+mod memory;
+mod pacing;
+pub(crate) mod scheduler;
+mod secure;
 pub(crate) mod sockets;
+mod udp;
+
+/// Future returned by the [Transport] methods
+pub type TransportFuture<'a, T> =
+    Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// The [Transport] trait abstracts the exchange of datagrams with the other
+/// peers of the network.
+///
+/// By default a [Peer](crate::Peer) uses UDP sockets. A different transport
+/// can be set through [PeerBuilder::transport](crate::PeerBuilder::transport),
+/// e.g. a [MemoryTransport] to run many peers in a single test.
+pub trait Transport: Send + Sync {
+    /// Send a datagram to `target`.
+    ///
+    /// As with UDP, a successful send doesn't guarantee the delivery.
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> TransportFuture<'a, ()>;
+
+    /// Receive a datagram, returning its length and its source address.
+    ///
+    /// Errors are considered fatal: the peer stops receiving messages.
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> TransportFuture<'a, (usize, SocketAddr)>;
+}
 
 impl WireNetwork {
     pub fn start(
         in_channel_tx: Sender<MessageBeanIn>,
         out_channel_rx: Receiver<MessageBeanOut>,
         conf: Config,
-        blocklist: RwLock<HashSet<SocketAddr>>,
-    ) {
-        let decoder = TransportDecoder::configure(&conf.fec.decoder);
+        reputation: Reputation,
+        tokens: AddressTokens,
+        metrics: Arc<Metrics>,
+        transport: Arc<dyn Transport>,
+    ) -> WireNetworkTasks {
+        let decoder = TransportDecoder::configure(&conf.fec.decoder)
+            .with_metrics(metrics.clone());
         let encoder = TransportEncoder::configure(&conf.fec.encoder);
-        let out_socket = MultipleOutSocket::configure(&conf.network);
         let (dec_chan_tx, dec_chan_rx) = mpsc::channel(conf.channel_size);
+        metrics.register_channel("decoder", &dec_chan_tx);
+        let outgoing_shutdown = Arc::new(Notify::new());
 
-        let outgoing = Self::outgoing(out_channel_rx, out_socket, encoder);
-        let decoder = Self::decoder(in_channel_tx, dec_chan_rx, decoder);
+        let outgoing = Self::outgoing(
+            out_channel_rx,
+            transport.clone(),
+            encoder,
+            Scheduler::new(conf.channel_size, &conf.network),
+            tokens,
+            outgoing_shutdown.clone(),
+            metrics.clone(),
+        );
+        let blocklist = reputation.blocklist().clone();
+        let decoder = Self::decoder(
+            in_channel_tx,
+            dec_chan_rx,
+            decoder,
+            reputation,
+            metrics.clone(),
+        );
         let incoming = async {
-            Self::incoming(dec_chan_tx, conf, blocklist)
+            Self::incoming(dec_chan_tx, transport, conf, blocklist, metrics)
                 .await
                 .unwrap_or_else(|e| error!("Error in incoming_loop {e}"));
         };
 
-        tokio::spawn(outgoing);
-        tokio::spawn(decoder);
-        tokio::spawn(incoming);
+        WireNetworkTasks {
+            outgoing: tokio::spawn(outgoing),
+            outgoing_shutdown,
+            decoder: tokio::spawn(decoder),
+            incoming: tokio::spawn(incoming),
+        }
     }
 
     async fn incoming(
         dec_chan_tx: Sender<UDPChunk>,
+        transport: Arc<dyn Transport>,
         conf: Config,
-        blocklist: RwLock<HashSet<SocketAddr>>,
+        blocklist: RwLock<Blocklist>,
+        metrics: Arc<Metrics>,
     ) -> io::Result<()> {
         debug!("WireNetwork::incoming loop started");
 
-        let listen_address =
-            conf.listen_address.as_ref().unwrap_or(&conf.public_address);
-        let socket = UdpSocket::bind(listen_address).await?;
-
-        info!("Listening on: {}", socket.local_addr()?);
-
         // Using a local blocklist prevent the library to constantly requests a
         // read access to the RwLock
-        let last_blocklist_refresh = Instant::now();
+        let mut last_blocklist_refresh = Instant::now();
         let blocklist_refresh = conf.network.blocklist_refresh_interval;
         let mut local_blocklist = blocklist.read().await.clone();
+        let mut limiter = RateLimiter::new(&conf.network);
 
-        // Try to extend socket recv buffer size
-        Self::configure_socket(&socket, &conf)?;
-
-        // Read UDP socket recv buffer and delegate the processing to decode
+        // Read the transport and delegate the processing to decode
         // task
         loop {
             if last_blocklist_refresh.elapsed() > blocklist_refresh {
                 local_blocklist = blocklist.read().await.clone();
+                last_blocklist_refresh = Instant::now();
             }
 
             let mut bytes = [0; MAX_DATAGRAM_SIZE];
             let (len, remote_address) =
-                socket.recv_from(&mut bytes).await.map_err(|e| {
-                    error!("Error receiving from socket {e}");
+                transport.recv_from(&mut bytes).await.map_err(|e| {
+                    error!("Error receiving from transport {e}");
                     e
                 })?;
 
-            if local_blocklist.contains(&remote_address) {
+            if local_blocklist.is_blocked(&remote_address) {
+                metrics.inc(Counter::DatagramsBlocked);
+                continue;
+            }
+            if let Err(exceeded) = limiter.check(remote_address.ip(), len) {
+                metrics.inc(match exceeded {
+                    Exceeded::Packets => Counter::PacketRateLimited,
+                    Exceeded::Bytes => Counter::ByteRateLimited,
+                });
+                trace!("Rate limit exceeded by {remote_address}");
                 continue;
             }
+            metrics.inc(Counter::DatagramsIn);
+            metrics.add(Counter::BytesIn, len as u64);
 
-            dec_chan_tx
-                .send((bytes[0..len].to_vec(), remote_address))
+            metrics
+                .send(&dec_chan_tx, (bytes[0..len].to_vec(), remote_address))
                 .await
                 .unwrap_or_else(|e| {
                     error!("Unable to send to dec_chan_tx channel {e}")
@@ -111,24 +196,30 @@ impl WireNetwork {
         in_channel_tx: Sender<MessageBeanIn>,
         mut dec_chan_rx: Receiver<UDPChunk>,
         mut decoder: TransportDecoder,
+        reputation: Reputation,
+        metrics: Arc<Metrics>,
     ) {
         debug!("WireNetwork::decoder loop started");
-        loop {
-            if let Some((data, src)) = dec_chan_rx.recv().await {
-                match Message::unmarshal_binary(&mut &data[..]) {
-                    Ok(deser) => {
-                        trace!("> Received raw message {}", deser.type_byte());
-                        Self::handle_raw_message(
-                            &mut decoder,
-                            deser,
-                            src,
-                            &in_channel_tx,
-                        )
+        while let Some((data, src)) = dec_chan_rx.recv().await {
+            match Message::unmarshal_binary(&mut &data[..]) {
+                Ok(deser) => {
+                    trace!("> Received raw message {}", deser.type_byte());
+                    Self::handle_raw_message(
+                        &mut decoder,
+                        deser,
+                        src,
+                        &in_channel_tx,
+                        &reputation,
+                        &metrics,
+                    )
+                    .await;
+                }
+                Err(e) => {
+                    metrics.inc(Counter::DecodeErrors);
+                    error!("Error deser from {data:?} - {src} - {e}");
+                    reputation
+                        .penalize(src.ip(), Misbehaviour::UndecodableDatagram)
                         .await;
-                    }
-                    Err(e) => {
-                        error!("Error deser from {data:?} - {src} - {e}")
-                    }
                 }
             }
         }
@@ -139,14 +230,27 @@ impl WireNetwork {
         deser: Message,
         src: SocketAddr,
         in_channel_tx: &Sender<MessageBeanIn>,
+        reputation: &Reputation,
+        metrics: &Metrics,
     ) {
         match decoder.decode(deser) {
             Err(e) => {
-                error!("Unable to process the message through the decoder: {e}")
+                metrics.inc(Counter::DecodeErrors);
+                error!(
+                    "Unable to process the message through the decoder: {e}"
+                );
+                let misbehaviour = match e.kind() {
+                    io::ErrorKind::InvalidData => {
+                        Misbehaviour::CorruptedMessage
+                    }
+                    _ => Misbehaviour::InvalidChunk,
+                };
+                reputation.penalize(src.ip(), misbehaviour).await;
             }
             Ok(Some(message)) => {
-                in_channel_tx
-                    .send((message, src))
+                metrics.inc(Counter::MessagesIn);
+                metrics
+                    .send(in_channel_tx, (message, src))
                     .await
                     .unwrap_or_else(|e| {
                         error!("Unable to send to inbound channel {e}")
@@ -156,59 +260,119 @@ impl WireNetwork {
         }
     }
 
+    /// Send the outbound messages until `shutdown` is notified.
+    ///
+    /// The messages received are queued in the `scheduler` according to
+    /// their [Priority], as long as it is not full, and their datagrams are
+    /// sent in the order it decides. The datagrams delayed by the pacing
+    /// never block the loop: it waits for the next one ready only if no other
+    /// datagram can be sent.
+    ///
+    /// Once notified, the outbound channel is closed and the messages already
+    /// queued are flushed before returning.
     async fn outgoing(
         mut out_channel_rx: Receiver<MessageBeanOut>,
-        mut out_socket: MultipleOutSocket,
+        transport: Arc<dyn Transport>,
         encoder: TransportEncoder,
+        mut scheduler: Scheduler,
+        tokens: AddressTokens,
+        shutdown: Arc<Notify>,
+        metrics: Arc<Metrics>,
     ) {
         debug!("WireNetwork::outgoing loop started");
+        let mut closed = false;
         loop {
-            if let Some((message, targets)) = out_channel_rx.recv().await {
-                trace!(
-                    "< Message to send to ({targets:?}) - {:?} ",
-                    message.type_byte()
-                );
-
-                match encoder.encode(message) {
-                    Ok(chunks) => {
-                        let chunks: Vec<_> = chunks
-                            .iter()
-                            .filter_map(|m| m.bytes().ok())
-                            .collect();
-                        for remote_addr in targets.iter() {
-                            for chunk in &chunks {
-                                out_socket
-                                    .send(chunk, remote_addr)
-                                    .await
-                                    .unwrap_or_else(|e| {
-                                        error!("Unable to send msg {e}")
-                                    });
-                            }
-                        }
+            while !scheduler.is_full() {
+                match out_channel_rx.try_recv() {
+                    Ok(bean) => {
+                        Self::schedule(&mut scheduler, bean, &encoder, &tokens)
                     }
-                    Err(e) => error!("Unable to encode msg {e}"),
+                    Err(_) => break,
                 }
             }
+            if let Some((chunk, remote_addr)) = scheduler.pop() {
+                Self::send(&*transport, &chunk, &remote_addr, &metrics).await;
+                continue;
+            }
+
+            let ready_at = scheduler.ready_at();
+            if closed && ready_at.is_none() {
+                break;
+            }
+            let receive = !closed && !scheduler.is_full();
+            tokio::select! {
+                bean = out_channel_rx.recv(), if receive => match bean {
+                    Some(bean) => Self::schedule(
+                        &mut scheduler,
+                        bean,
+                        &encoder,
+                        &tokens,
+                    ),
+                    None => closed = true,
+                },
+                _ = shutdown.notified(), if !closed => {
+                    debug!("WireNetwork::outgoing flushing queued messages");
+                    out_channel_rx.close();
+                }
+                _ = time::sleep_until(ready_at.unwrap_or_else(Instant::now)),
+                    if ready_at.is_some() => {}
+            }
         }
+        debug!("WireNetwork::outgoing loop stopped");
     }
 
-    pub fn configure_socket(
-        socket: &UdpSocket,
-        conf: &Config,
-    ) -> io::Result<()> {
-        if let Some(size) = conf.network.udp_recv_buffer_size {
-            let sock = SockRef::from(socket);
-            match sock.set_recv_buffer_size(size) {
-                Ok(_) => info!("udp_recv_buffer is now {size}"),
-                Err(e) => {
-                    error!("Error setting udp_recv_buffer to {size} - {e}",);
-                    warn!(
-                        "udp_recv_buffer is still {}",
-                        sock.recv_buffer_size().unwrap_or(0)
-                    );
+    /// Encode a message and queue its chunks in the `scheduler`
+    fn schedule(
+        scheduler: &mut Scheduler,
+        (message, targets, priority): MessageBeanOut,
+        encoder: &TransportEncoder,
+        tokens: &AddressTokens,
+    ) {
+        trace!(
+            "< Message to send to ({targets:?}) - {:?} - {priority:?}",
+            message.type_byte()
+        );
+
+        // Requests carry the address token received from each target
+        let messages = match message {
+            Message::Ping(..) | Message::FindNodes(..) => targets
+                .iter()
+                .filter_map(|target| {
+                    let token = tokens.request(*target);
+                    let request = message.request_with_token(token)?;
+                    Some((request, vec![*target]))
+                })
+                .collect(),
+            _ => vec![(message, targets)],
+        };
+
+        for (message, targets) in messages {
+            match encoder.encode(message) {
+                Ok(chunks) => {
+                    let chunks =
+                        chunks.iter().filter_map(|m| m.bytes().ok()).collect();
+                    scheduler.push(priority, chunks, targets);
                 }
+                Err(e) => error!("Unable to encode msg {e}"),
+            }
+        }
+    }
+
+    async fn send(
+        transport: &dyn Transport,
+        chunk: &[u8],
+        remote_addr: &SocketAddr,
+        metrics: &Metrics,
+    ) {
+        match transport.send_to(chunk, *remote_addr).await {
+            Ok(_) => {
+                metrics.inc(Counter::DatagramsOut);
+                metrics.add(Counter::BytesOut, chunk.len() as u64);
+            }
+            Err(e) => {
+                metrics.inc(Counter::SendErrors);
+                error!("Unable to send msg {e}")
             }
         }
-        Ok(())
     }
 }
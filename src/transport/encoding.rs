@@ -16,12 +16,15 @@ pub(crate) use plain_encoder::PlainEncoder as TransportEncoder;
 mod raptorq;
 
 use std::io;
+use std::sync::Arc;
 
 #[cfg(feature = "raptorq")]
 pub(crate) use self::raptorq::RaptorQDecoder as TransportDecoder;
 #[cfg(feature = "raptorq")]
 pub(crate) use self::raptorq::RaptorQEncoder as TransportEncoder;
+use crate::config::ConfigError;
 use crate::encoding::message::Message;
+use crate::metrics::Metrics;
 
 pub type TransportEncoderConfig =
     <self::TransportEncoder as Configurable>::TConf;
@@ -32,6 +35,12 @@ pub trait Configurable {
     type TConf;
     fn default_configuration() -> Self::TConf;
     fn configure(conf: &Self::TConf) -> Self;
+
+    /// Report the values of `conf` which are invalid, or which
+    /// [Configurable::configure] would replace
+    fn validate(_conf: &Self::TConf) -> Vec<ConfigError> {
+        vec![]
+    }
 }
 
 pub(crate) trait Encoder: Configurable {
@@ -39,5 +48,18 @@ pub(crate) trait Encoder: Configurable {
 }
 
 pub(crate) trait Decoder: Configurable {
+    /// Decode a chunk, returning the message once complete.
+    ///
+    /// An error of kind [InvalidData](io::ErrorKind::InvalidData) reports a
+    /// message failing its integrity check, the other errors report an
+    /// invalid chunk.
     fn decode(&mut self, chunk: Message) -> io::Result<Option<Message>>;
+
+    /// Record the decoding metrics (if any) in `metrics`
+    fn with_metrics(self, _metrics: Arc<Metrics>) -> Self
+    where
+        Self: Sized,
+    {
+        self
+    }
 }
@@ -11,7 +11,8 @@ use blake2::{Blake2s256, Digest};
 use safe::{SafeObjectTransmissionInformation, TransmissionInformationError};
 
 use crate::encoding::Marshallable;
-use crate::encoding::payload::BroadcastPayload;
+use crate::encoding::message::Message;
+use crate::encoding::payload::{BroadcastPayload, RequestPayload};
 
 mod decoder;
 mod encoder;
@@ -20,7 +21,7 @@ mod safe;
 pub(crate) use decoder::RaptorQDecoder;
 pub(crate) use encoder::RaptorQEncoder;
 
-struct ChunkedPayload<'a>(&'a BroadcastPayload);
+struct ChunkedPayload<'a>(&'a [u8]);
 
 // ObjectTransmissionInformation Size (Raptorq header)
 const TRANSMISSION_INFO_SIZE: usize = 12;
@@ -36,11 +37,11 @@ const MIN_ENCODING_PACKET_SIZE: usize = 5;
 
 const MIN_CHUNKED_SIZE: usize = CHUNKED_HEADER_SIZE + MIN_ENCODING_PACKET_SIZE;
 
-impl<'a> TryFrom<&'a BroadcastPayload> for ChunkedPayload<'a> {
+impl<'a> TryFrom<&'a [u8]> for ChunkedPayload<'a> {
     type Error = io::Error;
 
-    fn try_from(value: &'a BroadcastPayload) -> Result<Self, Self::Error> {
-        if value.gossip_frame.len() < MIN_CHUNKED_SIZE {
+    fn try_from(value: &'a [u8]) -> Result<Self, Self::Error> {
+        if value.len() < MIN_CHUNKED_SIZE {
             Err(io::Error::new(
                 ErrorKind::UnexpectedEof,
                 "Chunked payload too short",
@@ -62,12 +63,94 @@ impl BroadcastPayload {
         let mut hasher = Blake2s256::new();
         // Remove the kadcast `height` field from the hash
         hasher.update(&self.bytes()?[1..]);
+        // Include the origin, so that a message signed by somebody else is
+        // never mistaken for an already processed one
+        if let Some(origin) = &self.origin {
+            let mut bytes = vec![];
+            origin.marshal_binary(&mut bytes)?;
+            hasher.update(bytes);
+        }
         Ok(hasher.finalize().into())
     }
 }
+
+impl Message {
+    /// Return the frame split in chunks by the encoder, if the message
+    /// carries one
+    fn chunked_frame(&self) -> Option<&[u8]> {
+        match self {
+            Message::Broadcast(_, payload, ..) => Some(&payload.gossip_frame),
+            Message::Request(_, payload) | Message::Response(_, payload) => {
+                Some(&payload.body)
+            }
+            _ => None,
+        }
+    }
+
+    /// Return the kadcast height of a chunked message
+    fn chunked_height(&self) -> u8 {
+        match self {
+            Message::Broadcast(_, payload, ..) => payload.height,
+            _ => 0,
+        }
+    }
+
+    /// Return a copy of a chunked message carrying the provided frame
+    fn with_frame(
+        &self,
+        frame: Vec<u8>,
+        height: u8,
+        ray_id: [u8; RAY_ID_SIZE],
+    ) -> Message {
+        match self {
+            Message::Broadcast(header, payload, _) => Message::Broadcast(
+                *header,
+                BroadcastPayload {
+                    height,
+                    gossip_frame: frame,
+                    origin: payload.origin,
+                },
+                ray_id,
+            ),
+            Message::Request(header, payload) => Message::Request(
+                *header,
+                RequestPayload {
+                    request_id: payload.request_id,
+                    body: frame,
+                },
+            ),
+            Message::Response(header, payload) => Message::Response(
+                *header,
+                RequestPayload {
+                    request_id: payload.request_id,
+                    body: frame,
+                },
+            ),
+            _ => unreachable!("Only chunked messages carry a frame"),
+        }
+    }
+
+    fn generate_ray_id(&self) -> io::Result<[u8; RAY_ID_SIZE]> {
+        match self {
+            Message::Broadcast(_, payload, ..) => payload.generate_ray_id(),
+            Message::Request(_, payload) | Message::Response(_, payload) => {
+                let mut hasher = Blake2s256::new();
+                // Include the message type, so a response never collides with
+                // its request
+                hasher.update([self.type_byte()]);
+                let mut bytes = vec![];
+                payload.marshal_binary(&mut bytes)?;
+                hasher.update(bytes);
+                Ok(hasher.finalize().into())
+            }
+            _ => Err(io::Error::other("Message not chunked")),
+        }
+    }
+}
+
 impl ChunkedPayload<'_> {
     fn ray_id(&self) -> [u8; RAY_ID_SIZE] {
-        self.0.gossip_frame[0..RAY_ID_SIZE]
+        self.0[0..RAY_ID_SIZE]
             .try_into()
             .expect("slice to be length 32")
     }
@@ -86,13 +169,13 @@ impl ChunkedPayload<'_> {
     }
 
     fn transmission_info_bytes(&self) -> [u8; TRANSMISSION_INFO_SIZE] {
-        self.0.gossip_frame[RAY_ID_SIZE..(CHUNKED_HEADER_SIZE)]
+        self.0[RAY_ID_SIZE..(CHUNKED_HEADER_SIZE)]
             .try_into()
             .expect("slice to be length 12")
     }
 
     fn encoded_chunk(&self) -> &[u8] {
-        &self.0.gossip_frame[(CHUNKED_HEADER_SIZE)..]
+        &self.0[(CHUNKED_HEADER_SIZE)..]
     }
 }
 
@@ -102,8 +185,10 @@ mod tests {
     use std::time::Instant;
 
     use io::{BufWriter, Cursor};
+    use rand::RngCore;
 
     use super::*;
+    use crate::Identity;
     use crate::encoding::message::Message;
     use crate::peer::PeerNode;
     use crate::tests::Result;
@@ -118,14 +203,13 @@ mod tests {
         #[cfg(debug_assertions)]
         let mut data = vec![0; 100_000];
 
-        for i in 0..data.len() {
-            data[i] = rand::Rng::r#gen(&mut rand::thread_rng());
-        }
+        rand::thread_rng().fill_bytes(&mut data);
         let peer = PeerNode::generate("192.168.0.1:666", 0)?;
         let header = peer.to_header();
         let payload = BroadcastPayload {
             height: 255,
             gossip_frame: data,
+            origin: None,
         };
         println!("orig payload len {}", payload.bytes()?.len());
         let message = Message::broadcast(header, payload);
@@ -147,7 +231,7 @@ mod tests {
         let mut sizetotal = 0;
         for chunk in chunks {
             // println!("chunk {:?}", chunk);
-            i = i + 1;
+            i += 1;
             sizetotal += chunk.bytes()?.len();
             if let Some(d) = decoder.decode(chunk).unwrap() {
                 decoded = Some(d);
@@ -165,24 +249,87 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_encode_raptorq_request() -> Result<()> {
+        let mut body = vec![0; 100_000];
+        rand::thread_rng().fill_bytes(&mut body);
+        let peer = PeerNode::generate("192.168.0.1:666", 0)?;
+        let payload = RequestPayload {
+            request_id: 42,
+            body,
+        };
+        let message = Message::Response(peer.to_header(), payload);
+        let message_bytes = message.bytes()?;
+        let encoder = TransportEncoder::configure(
+            &TransportEncoder::default_configuration(),
+        );
+        let chunks = encoder.encode(message)?;
+        assert!(chunks.len() > 1);
+        let mut decoder = TransportDecoder::configure(
+            &TransportDecoder::default_configuration(),
+        );
+        let decoded = chunks
+            .into_iter()
+            .find_map(|chunk| decoder.decode(chunk).unwrap())
+            .expect("The response to be decoded");
+        assert!(matches!(decoded, Message::Response(..)));
+        assert_eq!(decoded.bytes()?, message_bytes, "Unable to decode");
+        Ok(())
+    }
+
+    #[test]
+    fn test_encode_raptorq_signed() -> Result<()> {
+        let mut data = vec![0; 100_000];
+        rand::thread_rng().fill_bytes(&mut data);
+        let identity = Identity::generate();
+        let peer =
+            PeerNode::generate_with_identity("192.168.0.1:666", &identity, 0)?;
+        let payload = BroadcastPayload {
+            height: 255,
+            origin: Some(identity.sign(0, &data)),
+            gossip_frame: data,
+        };
+        let ray_id = payload.generate_ray_id()?;
+        let resigned = BroadcastPayload {
+            origin: Some(Identity::generate().sign(0, &payload.gossip_frame)),
+            ..payload.clone()
+        };
+        assert_ne!(ray_id, resigned.generate_ray_id()?);
+
+        let message = Message::broadcast(peer.to_header(), payload);
+        let message_bytes = message.bytes()?;
+        let encoder = TransportEncoder::configure(
+            &TransportEncoder::default_configuration(),
+        );
+        let chunks = encoder.encode(message)?;
+        assert!(chunks.len() > 1);
+        let mut decoder = TransportDecoder::configure(
+            &TransportDecoder::default_configuration(),
+        );
+        let decoded = chunks
+            .into_iter()
+            .find_map(|chunk| decoder.decode(chunk).unwrap())
+            .expect("The broadcast to be decoded");
+        assert_eq!(decoded.bytes()?, message_bytes, "Unable to decode");
+        Ok(())
+    }
+
     #[test]
     fn test_encode_raptorq_junk() -> Result<()> {
         #[cfg(not(debug_assertions))]
         const DATA_LEN: usize = 3_000_000;
 
         #[cfg(debug_assertions)]
-        const DATA_LEN: usize = 100_00;
+        const DATA_LEN: usize = 10_000;
 
         let mut data = vec![0; DATA_LEN];
-
-        for i in 0..DATA_LEN {
-            data[i] = rand::Rng::r#gen(&mut rand::thread_rng());
-        }
+        rand::thread_rng().fill_bytes(&mut data);
         let peer = PeerNode::generate("192.168.0.1:666", 0)?;
         let header = peer.to_header();
         let payload = BroadcastPayload {
             height: 255,
             gossip_frame: data,
+            origin: None,
         };
         println!("orig payload len {}", payload.bytes()?.len());
         let message = Message::broadcast(header, payload);
@@ -211,6 +358,7 @@ mod tests {
                 BroadcastPayload {
                     height: 255,
                     gossip_frame,
+                    origin: None,
                 },
             );
             if let Ok(Some(_)) = decoder.decode(msg) {
@@ -223,7 +371,7 @@ mod tests {
         let start = Instant::now();
         let mut junk = 0;
         for chunk in chunks {
-            i = i + 1;
+            i += 1;
             sizetotal += chunk.bytes()?.len();
             let cloned_chunk = clone_and_corrupt_msg(&chunk)?;
             for _ in 0..1000 {
@@ -258,9 +406,7 @@ mod tests {
         let mut bytes = vec![];
         c.seek(std::io::SeekFrom::Start(0))?;
         c.read_to_end(&mut bytes)?;
-        for i in 44..bytes.len() {
-            bytes[i] = rand::Rng::r#gen(&mut rand::thread_rng());
-        }
+        rand::thread_rng().fill_bytes(&mut bytes[44..]);
         let c = Cursor::new(bytes);
         let mut reader = BufReader::new(c);
         let msg = Message::unmarshal_binary(&mut reader)?;
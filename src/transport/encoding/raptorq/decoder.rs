@@ -7,15 +7,18 @@
 use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::io;
-use std::time::{Duration, Instant};
+use std::sync::Arc;
+use std::time::Duration;
 
 use raptorq::{Decoder as ExtDecoder, EncodingPacket};
 use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
 use tracing::{debug, trace, warn};
 
 use super::{ChunkedPayload, RAY_ID_SIZE, TRANSMISSION_INFO_SIZE};
+use crate::config::ConfigError;
 use crate::encoding::message::Message;
-use crate::encoding::payload::BroadcastPayload;
+use crate::metrics::{Counter, Metrics};
 use crate::transport::Decoder;
 use crate::transport::encoding::Configurable;
 
@@ -28,6 +31,7 @@ pub struct RaptorQDecoder {
     cache: BTreeMap<[u8; RAY_ID_SIZE], CacheStatus>,
     last_pruned: Instant,
     conf: RaptorQDecoderConf,
+    metrics: Arc<Metrics>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Copy)]
@@ -61,8 +65,21 @@ impl Configurable for RaptorQDecoder {
             conf: *conf,
             cache: BTreeMap::new(),
             last_pruned: Instant::now(),
+            metrics: Arc::default(),
         }
     }
+
+    fn validate(conf: &Self::TConf) -> Vec<ConfigError> {
+        let zero_values = [
+            ("fec.decoder.cache_ttl", conf.cache_ttl.is_zero()),
+            ("fec.decoder.max_udp_len", conf.max_udp_len == 0),
+        ];
+        zero_values
+            .into_iter()
+            .filter(|(_, zero)| *zero)
+            .map(|(field, _)| ConfigError::ZeroValue(field))
+            .collect()
+    }
 }
 
 struct ReceivingInfo {
@@ -98,9 +115,11 @@ impl CacheStatus {
 
 impl Decoder for RaptorQDecoder {
     fn decode(&mut self, message: Message) -> io::Result<Option<Message>> {
-        if let Message::Broadcast(header, payload, ..) = message {
-            trace!("> Decoding broadcast chunk");
-            let chunked = ChunkedPayload::try_from(&payload)?;
+        if let Some(frame) = message.chunked_frame() {
+            trace!("> Decoding chunk");
+            self.metrics.inc(Counter::ChunksReceived);
+            let height = message.chunked_height();
+            let chunked = ChunkedPayload::try_from(frame)?;
             let ray_id = chunked.ray_id();
             let encode_info = chunked.transmission_info_bytes();
 
@@ -115,7 +134,7 @@ impl Decoder for RaptorQDecoder {
                 std::collections::btree_map::Entry::Vacant(v) => {
                     let receiving_info = ReceivingInfo {
                         expire_on: Instant::now() + self.conf.cache_ttl,
-                        max_kad_height: payload.height,
+                        max_kad_height: height,
                     };
                     v.insert(CacheStatus::Receiving(
                         receiving_info,
@@ -126,7 +145,10 @@ impl Decoder for RaptorQDecoder {
 
             let decoded = match status {
                 // Avoid to repropagate already processed messages
-                CacheStatus::Processed(_) => None,
+                CacheStatus::Processed(_) => {
+                    self.metrics.inc(Counter::ChunkCacheHits);
+                    None
+                }
                 CacheStatus::Receiving(recv, list) => {
                     // check right decoder according to the encoding info
                     let decoder_info = match list.entry(encode_info) {
@@ -172,8 +194,8 @@ impl Decoder for RaptorQDecoder {
                     // Those peers can send with different broadcast height.
                     // If those heights differs, we should check the highest one
                     // in order to preserve the propagation
-                    if payload.height > recv.max_kad_height {
-                        recv.max_kad_height = payload.height;
+                    if height > recv.max_kad_height {
+                        recv.max_kad_height = height;
                     }
 
                     let packet =
@@ -184,30 +206,34 @@ impl Decoder for RaptorQDecoder {
                         return Ok(None);
                     };
 
-                    decoder_info
+                    let decoded = decoder_info
                         .decoder
                         .decode(packet)
-                        // If decoded successfully, create the new
-                        // BroadcastMessage
-                        .and_then(|decoded| {
-                            let payload = BroadcastPayload {
-                                height: recv.max_kad_height,
-                                gossip_frame: decoded,
-                            };
-                            // Perform integrity check
-                            match payload.generate_ray_id() {
-                                // Compare received ID with the one generated
-                                Ok(ray_id) if chunked.ray_id().eq(&ray_id) => {
-                                    Some(Message::Broadcast(
-                                        header, payload, ray_id,
-                                    ))
-                                }
-                                _ => {
-                                    warn!("Invalid message decoded");
-                                    None
-                                }
-                            }
-                        })
+                        // If decoded successfully, create the new message
+                        .map(|decoded| {
+                            message.with_frame(
+                                decoded,
+                                recv.max_kad_height,
+                                ray_id,
+                            )
+                        });
+
+                    // Perform integrity check, comparing received ID with
+                    // the one generated
+                    if let Some(decoded) = &decoded
+                        && decoded.generate_ray_id().ok() != Some(ray_id)
+                    {
+                        warn!("Invalid message decoded");
+                        // Drop the poisoned decoder, so that the next chunks
+                        // can be decoded from scratch
+                        list.remove(&encode_info);
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "Invalid message decoded",
+                        ));
+                    }
+
+                    decoded
                         // If the message is succesfully decoded, update the
                         // cache with new status. This
                         // will drop useless Decoder and avoid
@@ -219,7 +245,8 @@ impl Decoder for RaptorQDecoder {
                                     Instant::now() + self.conf.cache_ttl,
                                 ),
                             );
-                            trace!("> Broadcast message decoded!");
+                            self.metrics.inc(Counter::RaysDecoded);
+                            trace!("> Chunked message decoded!");
                         })
                 }
             };
@@ -228,6 +255,7 @@ impl Decoder for RaptorQDecoder {
                 self.cache.retain(|ray_id, status| {
                     let keep = !status.expired();
                     if !keep && status.receiving() {
+                        self.metrics.inc(Counter::RaysExpired);
                         warn!(
                             event = "dupemap discard",
                             ray = hex::encode(ray_id)
@@ -242,14 +270,21 @@ impl Decoder for RaptorQDecoder {
             Ok(Some(message))
         }
     }
+
+    fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::thread;
     use std::time::Duration;
 
+    use tokio::time;
+
     use super::*;
+    use crate::encoding::payload::BroadcastPayload;
     use crate::peer::PeerNode;
     use crate::tests::Result;
     use crate::transport::encoding::Encoder;
@@ -261,8 +296,8 @@ mod tests {
         }
     }
 
-    #[test]
-    fn test_expiring_cache() -> Result<()> {
+    #[tokio::test(start_paused = true)]
+    async fn test_expiring_cache() -> Result<()> {
         let root = PeerNode::generate("192.168.0.1:666", 0)?;
         let enc =
             RaptorQEncoder::configure(&RaptorQEncoder::default_configuration());
@@ -278,6 +313,7 @@ mod tests {
             BroadcastPayload {
                 height: 0,
                 gossip_frame: vec![0],
+                origin: None,
             },
         ))? {
             dec.decode(n)?;
@@ -285,11 +321,11 @@ mod tests {
         assert_eq!(dec.cache_size(), 1);
 
         // Wait for first check, message should not expire
-        thread::sleep(Duration::from_millis(500));
+        time::advance(Duration::from_millis(500)).await;
         assert_eq!(dec.cache_size(), 1);
 
-        // Wait second check, next decode should remove first message
-        thread::sleep(Duration::from_millis(500));
+        // Wait past the ttl, next decode should remove first message
+        time::advance(Duration::from_millis(600)).await;
 
         // Decode other 3 messages
         for i in 1..4 {
@@ -298,15 +334,16 @@ mod tests {
                 BroadcastPayload {
                     height: 0,
                     gossip_frame: vec![i],
+                    origin: None,
                 },
             ))? {
                 dec.decode(n)?;
             }
         }
         assert_eq!(dec.cache_size(), 3);
-        thread::sleep(Duration::from_millis(500));
+        time::advance(Duration::from_millis(500)).await;
         assert_eq!(dec.cache_size(), 3);
-        thread::sleep(Duration::from_millis(500));
+        time::advance(Duration::from_millis(600)).await;
 
         // Decode message, it should remove the previous 3
         for n in enc.encode(Message::broadcast(
@@ -314,6 +351,7 @@ mod tests {
             BroadcastPayload {
                 height: 0,
                 gossip_frame: vec![0],
+                origin: None,
             },
         ))? {
             dec.decode(n)?;
@@ -6,8 +6,8 @@
 
 use std::io;
 
+use crate::config::ConfigError;
 use crate::encoding::message::Message;
-use crate::encoding::payload::BroadcastPayload;
 use crate::transport::Encoder;
 use crate::transport::encoding::Configurable;
 
@@ -64,50 +64,56 @@ impl Configurable for RaptorQEncoder {
         }
         Self { conf }
     }
+
+    fn validate(conf: &Self::TConf) -> Vec<ConfigError> {
+        let mut errors = vec![];
+        if !(MIN_MTU..=MAX_MTU).contains(&conf.mtu) {
+            errors.push(ConfigError::MtuOutOfRange {
+                mtu: conf.mtu,
+                min: MIN_MTU,
+                max: MAX_MTU,
+            });
+        }
+        if conf.fec_redundancy.is_nan() || conf.fec_redundancy < 0.0 {
+            errors.push(ConfigError::InvalidFecRedundancy(conf.fec_redundancy));
+        }
+        errors
+    }
 }
 
 impl Encoder for RaptorQEncoder {
     fn encode<'msg>(&self, msg: Message) -> io::Result<Vec<Message>> {
-        if let Message::Broadcast(header, payload, ..) = msg {
-            let encoder =
-                ExtEncoder::with_defaults(&payload.gossip_frame, self.conf.mtu);
-            let transmission_info = encoder.get_config().serialize();
-
-            let ray_id = payload.generate_ray_id()?;
-            let raptorq_header = [&ray_id[..], &transmission_info].concat();
-
-            debug!(
-                event = "Start encoding payload",
-                ray = hex::encode(ray_id),
-                encode_info = hex::encode(transmission_info)
-            );
-
-            let mut repair_packets =
-                (payload.gossip_frame.len() as f32 * self.conf.fec_redundancy
-                    / self.conf.mtu as f32) as u32;
-            if repair_packets < self.conf.min_repair_packets_per_block {
-                repair_packets = self.conf.min_repair_packets_per_block
-            }
-
-            let messages = encoder
-                .get_encoded_packets(repair_packets)
-                .iter()
-                .map(|encoded_packet| {
-                    let mut gossip_frame = raptorq_header.clone();
-                    gossip_frame.append(&mut encoded_packet.serialize());
-                    Message::Broadcast(
-                        header,
-                        BroadcastPayload {
-                            height: payload.height,
-                            gossip_frame,
-                        },
-                        ray_id,
-                    )
-                })
-                .collect();
-            Ok(messages)
-        } else {
-            Ok(vec![msg])
+        let Some(frame) = msg.chunked_frame() else {
+            return Ok(vec![msg]);
+        };
+        let encoder = ExtEncoder::with_defaults(frame, self.conf.mtu);
+        let transmission_info = encoder.get_config().serialize();
+
+        let ray_id = msg.generate_ray_id()?;
+        let raptorq_header = [&ray_id[..], &transmission_info].concat();
+
+        debug!(
+            event = "Start encoding payload",
+            ray = hex::encode(ray_id),
+            encode_info = hex::encode(transmission_info)
+        );
+
+        let mut repair_packets = (frame.len() as f32 * self.conf.fec_redundancy
+            / self.conf.mtu as f32) as u32;
+        if repair_packets < self.conf.min_repair_packets_per_block {
+            repair_packets = self.conf.min_repair_packets_per_block
         }
+
+        let height = msg.chunked_height();
+        let messages = encoder
+            .get_encoded_packets(repair_packets)
+            .iter()
+            .map(|encoded_packet| {
+                let mut chunk = raptorq_header.clone();
+                chunk.append(&mut encoded_packet.serialize());
+                msg.with_frame(chunk, height, ray_id)
+            })
+            .collect();
+        Ok(messages)
     }
 }
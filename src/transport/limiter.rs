@@ -0,0 +1,141 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::NetworkConfig;
+
+// Buckets idle for longer than a second are full, and can be forgotten
+const BUCKET_IDLE: Duration = Duration::from_secs(1);
+const PRUNE_EVERY: Duration = Duration::from_secs(10);
+
+/// Why a datagram has been dropped by the [RateLimiter]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Exceeded {
+    Packets,
+    Bytes,
+}
+
+struct Bucket {
+    packets: f64,
+    bytes: f64,
+    updated: Instant,
+}
+
+/// Token buckets limiting the datagrams and the bytes received from each
+/// source IP, refilled at the configured rate and holding up to one second
+/// worth of tokens
+pub(super) struct RateLimiter {
+    packets_per_sec: Option<f64>,
+    bytes_per_sec: Option<f64>,
+    buckets: HashMap<IpAddr, Bucket>,
+    last_pruned: Instant,
+}
+
+impl RateLimiter {
+    pub(super) fn new(conf: &NetworkConfig) -> Self {
+        Self {
+            packets_per_sec: conf.source_packets_per_sec.map(f64::from),
+            bytes_per_sec: conf.source_bytes_per_sec.map(|b| b as f64),
+            buckets: HashMap::new(),
+            last_pruned: Instant::now(),
+        }
+    }
+
+    /// Take the tokens needed by a datagram of `len` bytes received from
+    /// `ip`, returning the exceeded limit (if any)
+    pub(super) fn check(
+        &mut self,
+        ip: IpAddr,
+        len: usize,
+    ) -> Result<(), Exceeded> {
+        if self.packets_per_sec.is_none() && self.bytes_per_sec.is_none() {
+            return Ok(());
+        }
+        let now = Instant::now();
+        if now.duration_since(self.last_pruned) > PRUNE_EVERY {
+            self.buckets.retain(|_, bucket| {
+                now.duration_since(bucket.updated) < BUCKET_IDLE
+            });
+            self.last_pruned = now;
+        }
+
+        let packets_per_sec = self.packets_per_sec.unwrap_or_default();
+        let bytes_per_sec = self.bytes_per_sec.unwrap_or_default();
+        let bucket = self.buckets.entry(ip.to_canonical()).or_insert(Bucket {
+            packets: packets_per_sec,
+            bytes: bytes_per_sec,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.packets =
+            (bucket.packets + elapsed * packets_per_sec).min(packets_per_sec);
+        bucket.bytes =
+            (bucket.bytes + elapsed * bytes_per_sec).min(bytes_per_sec);
+        bucket.updated = now;
+
+        let len = len as f64;
+        if self.packets_per_sec.is_some() && bucket.packets < 1.0 {
+            return Err(Exceeded::Packets);
+        }
+        if self.bytes_per_sec.is_some() && bucket.bytes < len {
+            return Err(Exceeded::Bytes);
+        }
+        bucket.packets -= 1.0;
+        bucket.bytes -= len;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limits() {
+        let conf = NetworkConfig {
+            source_packets_per_sec: Some(10),
+            source_bytes_per_sec: Some(1000),
+            ..Default::default()
+        };
+        let mut limiter = RateLimiter::new(&conf);
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        let other: IpAddr = "10.1.2.4".parse().unwrap();
+
+        for _ in 0..10 {
+            assert_eq!(limiter.check(ip, 10), Ok(()));
+        }
+        assert_eq!(limiter.check(ip, 10), Err(Exceeded::Packets));
+        assert_eq!(limiter.check(other, 1000), Ok(()));
+        assert_eq!(limiter.check(other, 1), Err(Exceeded::Bytes));
+
+        // Refilled at the configured rate
+        tokio::time::advance(Duration::from_millis(100)).await;
+        assert_eq!(limiter.check(ip, 10), Ok(()));
+        assert_eq!(limiter.check(ip, 10), Err(Exceeded::Packets));
+        assert_eq!(limiter.check(other, 100), Ok(()));
+        assert_eq!(limiter.check(other, 100), Err(Exceeded::Bytes));
+
+        // Idle buckets are forgotten
+        tokio::time::advance(PRUNE_EVERY).await;
+        assert_eq!(limiter.check(ip, 10), Ok(()));
+        assert_eq!(limiter.buckets.len(), 1);
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let mut limiter = RateLimiter::new(&NetworkConfig::default());
+        let ip: IpAddr = "10.1.2.3".parse().unwrap();
+        for _ in 0..1000 {
+            assert_eq!(limiter.check(ip, 65_507), Ok(()));
+        }
+        assert!(limiter.buckets.is_empty());
+    }
+}
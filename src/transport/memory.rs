@@ -0,0 +1,316 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tracing::trace;
+
+use super::{Transport, TransportFuture};
+
+type Datagram = (Vec<u8>, SocketAddr);
+type Endpoints = HashMap<SocketAddr, UnboundedSender<Datagram>>;
+
+/// In-process network connecting [MemoryTransport]s through channels.
+///
+/// By default datagrams are delivered immediately, in order and never lost,
+/// unless their target is not attached to the network, so that many peers can
+/// run in a single process without binding any port. Loss, latency,
+/// duplication and reordering can be simulated through [LinkConditions],
+/// while [MemoryNetwork::partition] splits the network.
+#[derive(Clone, Default)]
+pub struct MemoryNetwork {
+    state: Arc<Mutex<NetworkState>>,
+}
+
+/// Conditions applied to the datagrams sent over a link
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct LinkConditions {
+    /// Probability (between 0 and 1) of a datagram to be lost
+    pub loss: f64,
+    /// Probability (between 0 and 1) of a datagram to be delivered twice
+    pub duplication: f64,
+    /// Delay applied to every datagram
+    pub latency: Duration,
+    /// Max random delay added to the latency of each datagram.
+    ///
+    /// Datagrams sent over a link with a jitter can be delivered out of
+    /// order.
+    pub jitter: Duration,
+}
+
+struct NetworkState {
+    endpoints: Endpoints,
+    default_link: LinkConditions,
+    links: HashMap<(SocketAddr, SocketAddr), LinkConditions>,
+    partitions: Vec<HashSet<SocketAddr>>,
+    rng: StdRng,
+}
+
+impl Default for NetworkState {
+    fn default() -> Self {
+        Self {
+            endpoints: Endpoints::default(),
+            default_link: LinkConditions::default(),
+            links: HashMap::new(),
+            partitions: vec![],
+            rng: StdRng::from_entropy(),
+        }
+    }
+}
+
+impl NetworkState {
+    fn is_partitioned(&self, from: &SocketAddr, to: &SocketAddr) -> bool {
+        self.partitions
+            .iter()
+            .any(|p| p.contains(from) != p.contains(to))
+    }
+
+    /// Return the delays of the copies of a datagram to deliver (if any)
+    fn plan_delivery(
+        &mut self,
+        from: SocketAddr,
+        to: SocketAddr,
+    ) -> Vec<Duration> {
+        if self.is_partitioned(&from, &to) {
+            return vec![];
+        }
+        let link = *self.links.get(&(from, to)).unwrap_or(&self.default_link);
+        if self.rng.gen_bool(link.loss.clamp(0.0, 1.0)) {
+            return vec![];
+        }
+        let copies = match self.rng.gen_bool(link.duplication.clamp(0.0, 1.0)) {
+            true => 2,
+            false => 1,
+        };
+        (0..copies)
+            .map(|_| {
+                let jitter = self.rng.gen_range(Duration::ZERO..=link.jitter);
+                link.latency + jitter
+            })
+            .collect()
+    }
+}
+
+/// [Transport] attached to a [MemoryNetwork]
+pub struct MemoryTransport {
+    address: SocketAddr,
+    network: MemoryNetwork,
+    receiver: tokio::sync::Mutex<UnboundedReceiver<Datagram>>,
+}
+
+impl MemoryNetwork {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Seed the generator deciding the fate of each datagram, so that the
+    /// same sequence of datagrams undergoes the same conditions
+    pub fn seed(&self, seed: u64) {
+        self.state().rng = StdRng::seed_from_u64(seed);
+    }
+
+    /// Set the conditions of the links without specific ones
+    pub fn set_default_link(&self, conditions: LinkConditions) {
+        self.state().default_link = conditions;
+    }
+
+    /// Set the conditions of the datagrams sent from `from` to `to`.
+    ///
+    /// Links are directional: the datagrams sent from `to` to `from` are not
+    /// affected.
+    pub fn set_link(
+        &self,
+        from: SocketAddr,
+        to: SocketAddr,
+        conditions: LinkConditions,
+    ) {
+        self.state().links.insert((from, to), conditions);
+    }
+
+    /// Isolate `nodes` from the rest of the network: datagrams between a node
+    /// belonging to `nodes` and one not belonging to it are dropped.
+    ///
+    /// Multiple partitions can be active at the same time.
+    pub fn partition(&self, nodes: impl IntoIterator<Item = SocketAddr>) {
+        self.state().partitions.push(nodes.into_iter().collect());
+    }
+
+    /// Remove every partition
+    pub fn heal(&self) {
+        self.state().partitions.clear();
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, NetworkState> {
+        self.state.lock().expect("Unpoisoned lock")
+    }
+
+    /// Attach a new [MemoryTransport] to the network, receiving the datagrams
+    /// sent to `address`.
+    ///
+    /// The `address` should match the
+    /// [public_address](crate::config::Config::public_address) of the peer
+    /// using the transport, as the other peers identify it by the source
+    /// address of its datagrams.
+    ///
+    /// Returns an error of kind [ErrorKind::AddrInUse] if a transport is
+    /// already attached to `address`.
+    pub fn transport(
+        &self,
+        address: SocketAddr,
+    ) -> io::Result<MemoryTransport> {
+        let endpoints = &mut self.state().endpoints;
+        if endpoints.contains_key(&address) {
+            return Err(io::Error::new(
+                ErrorKind::AddrInUse,
+                format!("{address} already attached"),
+            ));
+        }
+        let (sender, receiver) = mpsc::unbounded_channel();
+        endpoints.insert(address, sender);
+        Ok(MemoryTransport {
+            address,
+            network: self.clone(),
+            receiver: tokio::sync::Mutex::new(receiver),
+        })
+    }
+}
+
+impl MemoryTransport {
+    /// Returns the address the transport is attached to
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Transport for MemoryTransport {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            let (endpoint, delays) = {
+                let mut state = self.network.state();
+                let Some(endpoint) = state.endpoints.get(&target).cloned()
+                else {
+                    trace!("Datagram to unknown {target} dropped");
+                    return Ok(());
+                };
+                (endpoint, state.plan_delivery(self.address, target))
+            };
+            for delay in delays {
+                let datagram = (data.to_vec(), self.address);
+                if delay.is_zero() {
+                    // The target may be detaching in the meantime
+                    let _ = endpoint.send(datagram);
+                } else {
+                    let endpoint = endpoint.clone();
+                    tokio::spawn(async move {
+                        tokio::time::sleep(delay).await;
+                        let _ = endpoint.send(datagram);
+                    });
+                }
+            }
+            Ok(())
+        })
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let (data, src) = self
+                .receiver
+                .lock()
+                .await
+                .recv()
+                .await
+                .ok_or_else(|| io::Error::from(ErrorKind::NotConnected))?;
+            let len = data.len().min(buf.len());
+            buf[..len].copy_from_slice(&data[..len]);
+            Ok((len, src))
+        })
+    }
+}
+
+impl Drop for MemoryTransport {
+    fn drop(&mut self) {
+        self.network.state().endpoints.remove(&self.address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_transport() -> io::Result<()> {
+        let network = MemoryNetwork::new();
+        let a = network.transport("10.0.0.1:1".parse().unwrap())?;
+        let b = network.transport("10.0.0.2:1".parse().unwrap())?;
+        assert!(network.transport(a.address()).is_err());
+
+        a.send_to(b"hello", b.address()).await?;
+        a.send_to(b"lost", "10.0.0.3:1".parse().unwrap()).await?;
+        let mut buf = [0; 16];
+        let (len, src) = b.recv_from(&mut buf).await?;
+        assert_eq!((&buf[..len], src), (&b"hello"[..], a.address()));
+
+        let address = b.address();
+        drop(b);
+        assert!(network.transport(address).is_ok());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_link_conditions() -> io::Result<()> {
+        let network = MemoryNetwork::new();
+        network.seed(42);
+        let a = network.transport("10.0.0.1:1".parse().unwrap())?;
+        let b = network.transport("10.0.0.2:1".parse().unwrap())?;
+        let mut buf = [0; 16];
+
+        let lossy = LinkConditions {
+            loss: 1.0,
+            ..Default::default()
+        };
+        network.set_link(a.address(), b.address(), lossy);
+        a.send_to(b"lost", b.address()).await?;
+        b.send_to(b"delivered", a.address()).await?;
+        let (len, _) = a.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..len], b"delivered");
+
+        let duplicating = LinkConditions {
+            duplication: 1.0,
+            latency: Duration::from_millis(10),
+            ..Default::default()
+        };
+        network.set_link(a.address(), b.address(), duplicating);
+        a.send_to(b"twice", b.address()).await?;
+        for _ in 0..2 {
+            let (len, _) = b.recv_from(&mut buf).await?;
+            assert_eq!(&buf[..len], b"twice");
+        }
+
+        network.partition([a.address()]);
+        network.set_link(a.address(), b.address(), LinkConditions::default());
+        a.send_to(b"partitioned", b.address()).await?;
+        network.heal();
+        a.send_to(b"healed", b.address()).await?;
+        let (len, _) = b.recv_from(&mut buf).await?;
+        assert_eq!(&buf[..len], b"healed");
+        Ok(())
+    }
+}
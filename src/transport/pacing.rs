@@ -0,0 +1,209 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use tokio::time::Instant;
+
+use crate::config::NetworkConfig;
+
+/// Amount of traffic sent at once before the pacing applies, as the time
+/// needed to send it at the configured rate
+const BURST: Duration = Duration::from_millis(50);
+
+const PRUNE_EVERY: Duration = Duration::from_secs(10);
+
+/// Time taken by the datagrams of a [Schedule]
+enum Rate {
+    BytesPerSec(f64),
+    /// Same time for every datagram, whatever its size
+    Interval(Duration),
+}
+
+/// Schedule of the datagrams sent at a [Rate], as the time when the next
+/// datagram would be sent without any burst
+struct Schedule {
+    rate: Rate,
+    next: Instant,
+}
+
+impl Schedule {
+    fn new(bytes_per_sec: u64, now: Instant) -> Self {
+        Self {
+            rate: Rate::BytesPerSec(bytes_per_sec as f64),
+            next: now,
+        }
+    }
+
+    fn with_interval(interval: Duration, now: Instant) -> Self {
+        Self {
+            rate: Rate::Interval(interval),
+            next: now,
+        }
+    }
+
+    /// Time from which a datagram can be sent
+    fn ready_at(&self, now: Instant) -> Instant {
+        self.next.checked_sub(BURST).unwrap_or(now).max(now)
+    }
+
+    /// Account for a datagram of `len` bytes sent at `now`
+    fn consume(&mut self, len: usize, now: Instant) {
+        let duration = match self.rate {
+            Rate::BytesPerSec(rate) => {
+                Duration::from_secs_f64(len as f64 / rate)
+            }
+            Rate::Interval(interval) => interval,
+        };
+        self.next = self.next.max(now) + duration;
+    }
+}
+
+/// Spreads the datagrams over time, according to the per-destination and
+/// global rates configured, along with the min interval between two
+/// datagrams.
+///
+/// The pacer never waits: it tells when a datagram can be sent, so that the
+/// datagrams to the other destinations are sent meanwhile.
+pub(super) struct Pacer {
+    peer_bytes_per_sec: Option<u64>,
+    peers: HashMap<SocketAddr, Schedule>,
+    global: Option<Schedule>,
+    interval: Option<Schedule>,
+    last_pruned: Instant,
+}
+
+impl Pacer {
+    pub(super) fn new(conf: &NetworkConfig) -> Self {
+        let now = Instant::now();
+        Self {
+            peer_bytes_per_sec: conf.udp_send_peer_bytes_per_sec,
+            peers: HashMap::new(),
+            global: conf
+                .udp_send_bytes_per_sec
+                .map(|rate| Schedule::new(rate, now)),
+            interval: conf
+                .udp_send_backoff_timeout
+                .map(|interval| Schedule::with_interval(interval, now)),
+            last_pruned: now,
+        }
+    }
+
+    /// Time from which a datagram can be sent to `target`
+    pub(super) fn ready_at(
+        &self,
+        target: &SocketAddr,
+        now: Instant,
+    ) -> Instant {
+        let peer = self.peers.get(target).map(|p| p.ready_at(now));
+        let global = [&self.global, &self.interval]
+            .into_iter()
+            .flatten()
+            .map(|schedule| schedule.ready_at(now))
+            .max();
+        peer.max(global).unwrap_or(now)
+    }
+
+    /// Account for a datagram of `len` bytes sent to `target` at `now`
+    pub(super) fn consume(
+        &mut self,
+        target: SocketAddr,
+        len: usize,
+        now: Instant,
+    ) {
+        if now.duration_since(self.last_pruned) > PRUNE_EVERY {
+            self.peers.retain(|_, schedule| schedule.next > now);
+            self.last_pruned = now;
+        }
+        if let Some(rate) = self.peer_bytes_per_sec {
+            self.peers
+                .entry(target)
+                .or_insert_with(|| Schedule::new(rate, now))
+                .consume(len, now);
+        }
+        for schedule in
+            [&mut self.global, &mut self.interval].into_iter().flatten()
+        {
+            schedule.consume(len, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_pacing() {
+        let conf = NetworkConfig {
+            udp_send_peer_bytes_per_sec: Some(10_000),
+            udp_send_bytes_per_sec: Some(20_000),
+            ..Default::default()
+        };
+        let mut pacer = Pacer::new(&conf);
+        let a = "10.1.2.3:9000".parse().unwrap();
+        let b = "10.1.2.4:9000".parse().unwrap();
+        let now = Instant::now();
+        let ms = |ms| now + Duration::from_millis(ms);
+
+        // 50ms of burst, then 100ms per datagram towards the same peer
+        assert_eq!(pacer.ready_at(&a, now), now);
+        pacer.consume(a, 1000, now);
+        assert_eq!(pacer.ready_at(&a, now), ms(50));
+        pacer.consume(a, 1000, ms(50));
+        assert_eq!(pacer.ready_at(&a, now), ms(150));
+
+        // Other peers are limited by the global rate only
+        assert_eq!(pacer.ready_at(&b, now), ms(50));
+
+        // The datagrams sent without waiting count towards the limits
+        pacer.consume(a, 500, now);
+        assert_eq!(pacer.ready_at(&a, now), ms(200));
+
+        tokio::time::advance(Duration::from_secs(1)).await;
+        let now = Instant::now();
+        assert_eq!(pacer.ready_at(&a, now), now);
+        pacer.consume(b, 1000, now);
+        assert_eq!(pacer.peers.len(), 2);
+        tokio::time::advance(PRUNE_EVERY * 2).await;
+        pacer.consume(b, 1000, Instant::now());
+        assert_eq!(pacer.peers.len(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_interval() {
+        let conf = NetworkConfig {
+            udp_send_backoff_timeout: Some(Duration::from_millis(10)),
+            ..Default::default()
+        };
+        let mut pacer = Pacer::new(&conf);
+        let a = "10.1.2.3:9000".parse().unwrap();
+        let b = "10.1.2.4:9000".parse().unwrap();
+        let now = Instant::now();
+
+        // 50ms of burst, then 10ms per datagram whatever its size
+        for _ in 0..6 {
+            assert_eq!(pacer.ready_at(&a, now), now);
+            pacer.consume(a, 10, now);
+        }
+        assert_eq!(pacer.ready_at(&b, now), now + Duration::from_millis(10));
+        assert!(pacer.peers.is_empty());
+    }
+
+    #[test]
+    fn test_no_pacing() {
+        let mut pacer = Pacer::new(&NetworkConfig::default());
+        let a = "10.1.2.3:9000".parse().unwrap();
+        let now = Instant::now();
+        for _ in 0..100 {
+            assert_eq!(pacer.ready_at(&a, now), now);
+            pacer.consume(a, 65_507, now);
+        }
+        assert!(pacer.peers.is_empty());
+    }
+}
@@ -0,0 +1,287 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+
+use tokio::time::Instant;
+
+use super::UDPChunk;
+use super::pacing::Pacer;
+use crate::config::NetworkConfig;
+
+/// Datagrams sent from the higher priority lanes before a waiting lower
+/// priority lane is served once, so that it is slowed down but never starved
+const MAX_SKIPPED: usize = 64;
+
+const LANES: usize = 4;
+
+/// Priority class of the outbound messages.
+///
+/// The datagrams of the queued messages are interleaved, the next one sent
+/// being taken from the highest priority lane with a datagram ready to be
+/// sent: a large message being sent, or delayed by the pacing, doesn't delay
+/// the messages of higher priority queued after it.
+///
+/// The datagrams of the [Priority::Control] and [Priority::High] lanes are
+/// never delayed by the pacing, but count towards the configured rates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Protocol messages maintaining the routing table (`Ping`, `Pong`,
+    /// `FindNodes` and `Nodes`)
+    Control,
+    /// Latency sensitive messages, e.g. consensus votes
+    High,
+    /// Default priority of the application messages
+    #[default]
+    Normal,
+    /// Large messages whose delivery can be delayed
+    Bulk,
+}
+
+impl Priority {
+    const LANES: [Priority; LANES] = [
+        Priority::Control,
+        Priority::High,
+        Priority::Normal,
+        Priority::Bulk,
+    ];
+
+    /// Whether the datagrams are delayed by the pacing
+    fn is_paced(&self) -> bool {
+        matches!(self, Priority::Normal | Priority::Bulk)
+    }
+
+    fn lane(&self) -> usize {
+        match self {
+            Priority::Control => 0,
+            Priority::High => 1,
+            Priority::Normal => 2,
+            Priority::Bulk => 3,
+        }
+    }
+}
+
+/// Message queued for sending, each chunk being sent to every target
+struct Job {
+    chunks: Vec<Vec<u8>>,
+    /// Targets still waiting for chunks, along with the next chunk to send
+    targets: Vec<(SocketAddr, usize)>,
+}
+
+impl Job {
+    /// Position of the first target whose next chunk can be sent at `now`
+    fn ready(&self, pacer: &Pacer, now: Instant) -> Option<usize> {
+        self.targets
+            .iter()
+            .position(|(target, _)| pacer.ready_at(target, now) <= now)
+    }
+
+    /// Time from which the next chunk of a target can be sent
+    fn ready_at(&self, pacer: &Pacer, now: Instant) -> Option<Instant> {
+        self.targets
+            .iter()
+            .map(|(target, _)| pacer.ready_at(target, now))
+            .min()
+    }
+
+    /// Take the next chunk of the target at `position`
+    fn take(&mut self, position: usize) -> UDPChunk {
+        let (target, next) = &mut self.targets[position];
+        let datagram = (self.chunks[*next].clone(), *target);
+        *next += 1;
+        if *next == self.chunks.len() {
+            self.targets.remove(position);
+        }
+        datagram
+    }
+}
+
+/// Queues of the outbound messages, one per [Priority], along with the
+/// [Pacer] delaying the datagrams exceeding the configured rates
+pub(super) struct Scheduler {
+    lanes: [VecDeque<Job>; LANES],
+    skipped: [usize; LANES],
+    capacity: usize,
+    pacer: Pacer,
+}
+
+impl Scheduler {
+    pub(super) fn new(capacity: usize, conf: &NetworkConfig) -> Self {
+        Self {
+            lanes: Default::default(),
+            skipped: [0; LANES],
+            capacity,
+            pacer: Pacer::new(conf),
+        }
+    }
+
+    /// Queue the `chunks` of a message to send to `targets`
+    pub(super) fn push(
+        &mut self,
+        priority: Priority,
+        chunks: Vec<Vec<u8>>,
+        targets: Vec<SocketAddr>,
+    ) {
+        if chunks.is_empty() || targets.is_empty() {
+            return;
+        }
+        let targets = targets.into_iter().map(|target| (target, 0)).collect();
+        self.lanes[priority.lane()].push_back(Job { chunks, targets });
+    }
+
+    /// Returns `true` if no more messages can be queued
+    pub(super) fn is_full(&self) -> bool {
+        self.lanes.iter().map(VecDeque::len).sum::<usize>() >= self.capacity
+    }
+
+    /// Next datagram which can be sent now, with its target.
+    ///
+    /// The datagrams delayed by the pacing are skipped, so that they don't
+    /// hold back the ones to the other destinations nor the small ones.
+    pub(super) fn pop(&mut self) -> Option<UDPChunk> {
+        let now = Instant::now();
+        let ready: [Option<(usize, usize)>; LANES] = std::array::from_fn(|i| {
+            let paced = Priority::LANES[i].is_paced();
+            self.lanes[i].iter().enumerate().find_map(|(job, queued)| {
+                let position = match paced {
+                    true => queued.ready(&self.pacer, now)?,
+                    false => 0,
+                };
+                Some((job, position))
+            })
+        });
+        let highest = ready.iter().position(Option::is_some)?;
+        let lane = (highest + 1..LANES)
+            .find(|&i| ready[i].is_some() && self.skipped[i] >= MAX_SKIPPED)
+            .unwrap_or(highest);
+        for (skipped, ready) in
+            self.skipped.iter_mut().zip(&ready).skip(lane + 1)
+        {
+            if ready.is_some() {
+                *skipped += 1;
+            }
+        }
+        self.skipped[lane] = 0;
+
+        let (job, position) = ready[lane]?;
+        let queue = &mut self.lanes[lane];
+        let datagram = queue[job].take(position);
+        if queue[job].targets.is_empty() {
+            queue.remove(job);
+        }
+        self.pacer.consume(datagram.1, datagram.0.len(), now);
+        Some(datagram)
+    }
+
+    /// Time from which a queued datagram can be sent, if any is queued
+    pub(super) fn ready_at(&self) -> Option<Instant> {
+        let now = Instant::now();
+        self.lanes
+            .iter()
+            .zip(Priority::LANES)
+            .flat_map(|(lane, priority)| {
+                lane.iter()
+                    .filter_map(move |job| match priority.is_paced() {
+                        true => job.ready_at(&self.pacer, now),
+                        false => Some(now),
+                    })
+            })
+            .min()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn target(port: u16) -> SocketAddr {
+        SocketAddr::from(([10, 1, 2, 3], port))
+    }
+
+    #[test]
+    fn test_priority_lanes() {
+        let mut scheduler = Scheduler::new(10, &NetworkConfig::default());
+        let bulk = vec![vec![3; 10], vec![4; 10]];
+        scheduler.push(Priority::Bulk, bulk, vec![target(1), target(2)]);
+        assert_eq!(scheduler.pop(), Some((vec![3; 10], target(1))));
+
+        // Higher priority messages overtake the bulk message being sent
+        scheduler.push(Priority::Normal, vec![vec![2]], vec![target(3)]);
+        scheduler.push(Priority::Control, vec![vec![0]], vec![target(3)]);
+        scheduler.push(Priority::High, vec![vec![1]], vec![target(3)]);
+        scheduler.push(Priority::Control, vec![vec![0]], vec![]);
+        assert_eq!(scheduler.pop(), Some((vec![0], target(3))));
+        assert_eq!(scheduler.pop(), Some((vec![1], target(3))));
+        assert_eq!(scheduler.pop(), Some((vec![2], target(3))));
+
+        assert_eq!(scheduler.pop(), Some((vec![4; 10], target(1))));
+        assert_eq!(scheduler.pop(), Some((vec![3; 10], target(2))));
+        assert_eq!(scheduler.pop(), Some((vec![4; 10], target(2))));
+        assert_eq!(scheduler.pop(), None);
+    }
+
+    #[test]
+    fn test_no_starvation() {
+        let mut scheduler = Scheduler::new(1000, &NetworkConfig::default());
+        scheduler.push(Priority::Bulk, vec![vec![3]], vec![target(1)]);
+        for _ in 0..2 * MAX_SKIPPED {
+            scheduler.push(Priority::High, vec![vec![1]], vec![target(2)]);
+        }
+        assert!(!scheduler.is_full());
+
+        for _ in 0..MAX_SKIPPED {
+            assert_eq!(scheduler.pop(), Some((vec![1], target(2))));
+        }
+        assert_eq!(scheduler.pop(), Some((vec![3], target(1))));
+        assert_eq!(scheduler.pop(), Some((vec![1], target(2))));
+
+        let mut scheduler = Scheduler::new(1, &NetworkConfig::default());
+        scheduler.push(Priority::Bulk, vec![vec![3]], vec![target(1)]);
+        assert!(scheduler.is_full());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_paced_destination() {
+        let conf = NetworkConfig {
+            udp_send_peer_bytes_per_sec: Some(10_000),
+            ..Default::default()
+        };
+        let mut scheduler = Scheduler::new(10, &conf);
+        let bulk = vec![vec![3; 1000]; 3];
+        scheduler.push(Priority::Bulk, bulk.clone(), vec![target(1)]);
+        scheduler.push(Priority::Bulk, bulk, vec![target(2)]);
+
+        // The burst of the first destination is exhausted after one datagram,
+        // the second destination is not delayed meanwhile
+        assert_eq!(scheduler.pop(), Some((vec![3; 1000], target(1))));
+        assert_eq!(scheduler.pop(), Some((vec![3; 1000], target(2))));
+        assert_eq!(scheduler.pop(), None);
+
+        // Nor are the control and high priority datagrams to the paced
+        // destination, whatever their size
+        scheduler.push(Priority::Normal, vec![vec![2; 100]], vec![target(1)]);
+        scheduler.push(Priority::Control, vec![vec![0; 1000]], vec![target(1)]);
+        scheduler.push(Priority::High, vec![vec![1; 1000]], vec![target(1)]);
+        assert_eq!(scheduler.pop(), Some((vec![0; 1000], target(1))));
+        assert_eq!(scheduler.pop(), Some((vec![1; 1000], target(1))));
+        assert_eq!(scheduler.pop(), None);
+
+        // Ready once the pacing delay elapsed, which the unpaced datagrams
+        // extended for the first destination
+        let ready_at = scheduler.ready_at().expect("Datagrams queued");
+        assert_eq!(ready_at - Instant::now(), Duration::from_millis(50));
+        tokio::time::advance(Duration::from_millis(50)).await;
+        assert_eq!(scheduler.pop(), Some((vec![3; 1000], target(2))));
+        assert_eq!(scheduler.pop(), None);
+        tokio::time::advance(Duration::from_millis(200)).await;
+        assert_eq!(scheduler.pop(), Some((vec![2; 100], target(1))));
+        assert_eq!(scheduler.pop(), Some((vec![3; 1000], target(2))));
+        assert_eq!(scheduler.pop(), None);
+    }
+}
@@ -0,0 +1,887 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use blake2::{Blake2s256, Digest};
+use chacha20poly1305::aead::{AeadInPlace, KeyInit};
+use chacha20poly1305::{Key, Tag, XChaCha20Poly1305, XNonce};
+use rand::RngCore;
+use tracing::debug;
+use x25519_dalek::{PublicKey as ExchangeKey, StaticSecret};
+
+use super::{Transport, TransportFuture};
+use crate::amplification::Budget;
+use crate::config::Encryption;
+use crate::encoding::Marshallable;
+use crate::encoding::message::{Header, Message};
+use crate::identity::{self, Identity, PublicKey, SIGNATURE_LEN};
+use crate::metrics::{Counter, Metrics};
+
+// Marker of a datagram encrypted with an established session
+const ID_SEALED: u8 = 0x40;
+
+// Marker of an encrypted datagram also carrying the sender handshake, sent
+// until the receiver proves to know the session
+const ID_SEALED_HANDSHAKE: u8 = 0x41;
+
+// Tag of the handshake appended to a plaintext datagram
+const TAG_HANDSHAKE: u8 = 0x48;
+
+const EXCHANGE_KEY_LEN: usize = 32;
+const CREATED_LEN: usize = 8;
+const HANDSHAKE_LEN: usize = EXCHANGE_KEY_LEN + CREATED_LEN + SIGNATURE_LEN;
+const NONCE_LEN: usize = 24;
+const TAG_LEN: usize = 16;
+
+// The nonces hold a random prefix followed by the counter of the datagram
+const COUNTER_LEN: usize = 8;
+const NONCE_PREFIX_LEN: usize = NONCE_LEN - COUNTER_LEN;
+
+// Counters accepted below the highest one received, to tolerate the datagrams
+// delivered out of order
+const REPLAY_WINDOW: u64 = 64;
+
+// Domain separation of the session keys
+const SESSION_CONTEXT: &[u8] = b"kadcast-session";
+
+type ExchangeBytes = [u8; EXCHANGE_KEY_LEN];
+
+/// Key exchange offered by a peer, signed with its [Identity]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Handshake {
+    exchange_key: ExchangeBytes,
+    /// Creation time of the exchange key, in milliseconds since the Unix
+    /// epoch, telling the current handshake of a peer from the previous ones
+    created: u64,
+    signature: [u8; SIGNATURE_LEN],
+}
+
+impl Handshake {
+    fn write(&self, datagram: &mut Vec<u8>) {
+        datagram.extend_from_slice(&self.exchange_key);
+        datagram.extend_from_slice(&self.created.to_le_bytes());
+        datagram.extend_from_slice(&self.signature);
+    }
+
+    /// Read a handshake from the beginning of `bytes`, returning the bytes
+    /// following it
+    fn read(bytes: &[u8]) -> Option<(Self, &[u8])> {
+        if bytes.len() < HANDSHAKE_LEN {
+            return None;
+        }
+        let (exchange_key, rest) = bytes.split_at(EXCHANGE_KEY_LEN);
+        let (created, rest) = rest.split_at(CREATED_LEN);
+        let (signature, rest) = rest.split_at(SIGNATURE_LEN);
+        let handshake = Handshake {
+            exchange_key: exchange_key.try_into().ok()?,
+            created: u64::from_le_bytes(created.try_into().ok()?),
+            signature: signature.try_into().ok()?,
+        };
+        Some((handshake, rest))
+    }
+
+    /// Check that the handshake is signed by `public_key`
+    fn verify(&self, public_key: &PublicKey, network_id: u8) -> bool {
+        identity::verify_handshake(
+            public_key,
+            network_id,
+            &self.exchange_key,
+            self.created,
+            &self.signature,
+        )
+    }
+}
+
+/// Counters of the datagrams received through a session, rejecting the
+/// replayed ones
+#[derive(Default)]
+struct ReplayWindow {
+    /// Counter following the highest one received
+    next: u64,
+    /// Counters received below `next`, the least significant bit standing for
+    /// `next - 1`
+    received: u64,
+}
+
+impl ReplayWindow {
+    /// Check that `counter` is neither received yet nor too old
+    fn check(&self, counter: u64) -> bool {
+        if counter >= self.next {
+            return true;
+        }
+        let age = self.next - 1 - counter;
+        age < REPLAY_WINDOW && self.received & (1 << age) == 0
+    }
+
+    /// Record `counter` as received
+    fn update(&mut self, counter: u64) {
+        if counter >= self.next {
+            let shift = counter - self.next + 1;
+            self.received = match shift < REPLAY_WINDOW {
+                true => self.received << shift,
+                false => 0,
+            };
+            self.received |= 1;
+            self.next = counter + 1;
+        } else {
+            self.received |= 1 << (self.next - 1 - counter);
+        }
+    }
+}
+
+/// Keys shared with a remote peer
+struct Session {
+    /// Address the remote peer listens on
+    address: SocketAddr,
+    /// Handshake the session derives from
+    handshake: Handshake,
+    send: XChaCha20Poly1305,
+    recv: XChaCha20Poly1305,
+    /// Counter of the next datagram sent
+    sent: u64,
+    received: ReplayWindow,
+    /// Whether the remote peer proved to know the session, by sending an
+    /// encrypted datagram
+    confirmed: bool,
+    /// Newer handshake offered in plaintext by the remote peer, after the
+    /// session was confirmed. The peer then restarted, and needs our
+    /// handshake to establish a new session.
+    renewal: Option<Handshake>,
+}
+
+/// Sessions established with the remote peers, keyed by their public key
+#[derive(Default)]
+struct Sessions {
+    by_key: HashMap<PublicKey, Session>,
+    by_address: HashMap<SocketAddr, PublicKey>,
+    by_exchange_key: HashMap<ExchangeBytes, PublicKey>,
+}
+
+impl Sessions {
+    /// Returns the session used to send datagrams to `address`
+    fn for_address(&mut self, address: &SocketAddr) -> Option<&mut Session> {
+        let public_key = self.by_address.get(address)?;
+        self.by_key.get_mut(public_key)
+    }
+
+    /// Insert the session established with the owner of `public_key`,
+    /// replacing its previous one
+    fn insert(&mut self, public_key: PublicKey, session: Session) {
+        let exchange_key = session.handshake.exchange_key;
+        let address = session.address;
+        if let Some(replaced) = self.by_key.insert(public_key, session) {
+            self.by_exchange_key
+                .remove(&replaced.handshake.exchange_key);
+            if self.by_address.get(&replaced.address) == Some(&public_key) {
+                self.by_address.remove(&replaced.address);
+            }
+        }
+        self.by_exchange_key.insert(exchange_key, public_key);
+        self.route(address, public_key);
+    }
+
+    /// Send the datagrams to `address` through the session of `public_key`.
+    ///
+    /// An unconfirmed session never takes over the address of a confirmed
+    /// one, so that a handshake replayed from a spoofed address can't divert
+    /// the datagrams of an established peer.
+    fn route(&mut self, address: SocketAddr, public_key: PublicKey) {
+        let confirmed =
+            |key: &PublicKey| self.by_key.get(key).is_some_and(|s| s.confirmed);
+        if let Some(routed) = self.by_address.get(&address)
+            && routed != &public_key
+            && confirmed(routed)
+            && !confirmed(&public_key)
+        {
+            return;
+        }
+        let Some(session) = self.by_key.get_mut(&public_key) else {
+            return;
+        };
+        let previous = std::mem::replace(&mut session.address, address);
+        if previous != address
+            && self.by_address.get(&previous) == Some(&public_key)
+        {
+            self.by_address.remove(&previous);
+        }
+        self.by_address.insert(address, public_key);
+    }
+}
+
+/// [Transport] encrypting the datagrams exchanged through an inner transport.
+///
+/// Each peer offers the same x25519 key to all the other peers for its whole
+/// lifetime. Once both the keys are known, the datagrams are encrypted with
+/// XChaCha20-Poly1305, using a key for each direction.
+///
+/// The sessions are bound to the identity of the remote peers: a session is
+/// only replaced by a newer handshake of the same identity, and a confirmed
+/// session only by an encrypted one.
+pub(crate) struct SecureTransport {
+    inner: Arc<dyn Transport>,
+    mode: Encryption,
+    network_id: u8,
+    secret: StaticSecret,
+    handshake: Handshake,
+    sessions: Mutex<Sessions>,
+    handshake_budget: Mutex<Budget>,
+    metrics: Arc<Metrics>,
+}
+
+impl SecureTransport {
+    pub(crate) fn new(
+        inner: Arc<dyn Transport>,
+        mode: Encryption,
+        identity: &Identity,
+        network_id: u8,
+        handshakes_per_sec: u32,
+        metrics: Arc<Metrics>,
+    ) -> Self {
+        let secret = StaticSecret::random_from_rng(rand::thread_rng());
+        let exchange_key = ExchangeKey::from(&secret).to_bytes();
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis() as u64)
+            .unwrap_or_default();
+        let handshake = Handshake {
+            exchange_key,
+            created,
+            signature: identity.sign_handshake(
+                network_id,
+                &exchange_key,
+                created,
+            ),
+        };
+        Self {
+            inner,
+            mode,
+            network_id,
+            secret,
+            handshake,
+            sessions: Mutex::new(Sessions::default()),
+            handshake_budget: Mutex::new(Budget::new(handshakes_per_sec)),
+            metrics,
+        }
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, Sessions> {
+        self.sessions.lock().expect("Unpoisoned lock")
+    }
+
+    /// Take the authentication of a new handshake from the budget, returning
+    /// `false` if exhausted
+    fn take_handshake(&self) -> bool {
+        let allowed = self
+            .handshake_budget
+            .lock()
+            .expect("Unpoisoned lock")
+            .take();
+        if !allowed {
+            self.metrics.inc(Counter::HandshakesThrottled);
+        }
+        allowed
+    }
+
+    /// Derive the session shared with the peer listening on `address`, which
+    /// offered `handshake`.
+    ///
+    /// Returns `None` if the key is a low order point, which would lead to a
+    /// predictable session.
+    fn derive(
+        &self,
+        handshake: Handshake,
+        address: SocketAddr,
+    ) -> Option<Session> {
+        let exchange_key = handshake.exchange_key;
+        let shared =
+            self.secret.diffie_hellman(&ExchangeKey::from(exchange_key));
+        if !shared.was_contributory() {
+            return None;
+        }
+        let cipher = |from: &[u8], to: &[u8]| {
+            let key = Blake2s256::new()
+                .chain_update(SESSION_CONTEXT)
+                .chain_update(shared.as_bytes())
+                .chain_update(from)
+                .chain_update(to)
+                .finalize();
+            XChaCha20Poly1305::new(Key::from_slice(&key))
+        };
+        let local = &self.handshake.exchange_key;
+        Some(Session {
+            address,
+            handshake,
+            send: cipher(local, &exchange_key),
+            recv: cipher(&exchange_key, local),
+            sent: 0,
+            received: ReplayWindow::default(),
+            confirmed: false,
+            renewal: None,
+        })
+    }
+
+    /// Wrap the marshalled message `bytes` into the datagram sent to
+    /// `target`.
+    ///
+    /// Returns `None` if the message can't be sent in plaintext.
+    fn seal(&self, bytes: &[u8], target: SocketAddr) -> Option<Vec<u8>> {
+        let discovery =
+            bytes.first().is_some_and(|t| Message::is_discovery(*t));
+        let mut sessions = self.sessions();
+        let session = sessions
+            .for_address(&target)
+            .filter(|session| !(discovery && session.renewal.is_some()));
+        let Some(session) = session else {
+            if self.mode == Encryption::Required && !discovery {
+                return None;
+            }
+            let mut datagram = bytes.to_vec();
+            datagram.push(TAG_HANDSHAKE);
+            self.handshake.write(&mut datagram);
+            return Some(datagram);
+        };
+
+        let mut datagram = vec![];
+        if session.confirmed {
+            datagram.push(ID_SEALED);
+            datagram.extend_from_slice(&self.handshake.exchange_key);
+        } else {
+            datagram.push(ID_SEALED_HANDSHAKE);
+            self.handshake.write(&mut datagram);
+        }
+        let aad_len = datagram.len();
+        let mut nonce = [0; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce[..NONCE_PREFIX_LEN]);
+        nonce[NONCE_PREFIX_LEN..].copy_from_slice(&session.sent.to_le_bytes());
+        session.sent += 1;
+        datagram.extend_from_slice(&nonce);
+        datagram.extend_from_slice(bytes);
+
+        let (aad, rest) = datagram.split_at_mut(aad_len);
+        let tag = session
+            .send
+            .encrypt_in_place_detached(
+                XNonce::from_slice(&nonce),
+                aad,
+                &mut rest[NONCE_LEN..],
+            )
+            .ok()?;
+        datagram.extend_from_slice(&tag);
+        Some(datagram)
+    }
+
+    /// Extract the marshalled message carried by `datagram`, moving it to the
+    /// beginning of the buffer.
+    ///
+    /// Returns the length of the message, or `None` if the datagram is
+    /// discarded.
+    fn open(&self, datagram: &mut [u8], src: SocketAddr) -> Option<usize> {
+        match datagram.first() {
+            Some(&ID_SEALED) | Some(&ID_SEALED_HANDSHAKE) => {
+                let opened = self.open_sealed(datagram, src);
+                if opened.is_none() {
+                    self.metrics.inc(Counter::UndecryptableDatagrams);
+                    debug!("Undecryptable datagram from {src} discarded");
+                }
+                opened
+            }
+            _ => self.open_plain(datagram, src),
+        }
+    }
+
+    fn open_plain(&self, datagram: &[u8], src: SocketAddr) -> Option<usize> {
+        let mut reader = datagram;
+        let Ok(message) = Message::unmarshal_binary(&mut reader) else {
+            // Leave the reporting of the malformed datagram to the decoder
+            return Some(datagram.len());
+        };
+        let message_len = datagram.len() - reader.len();
+        let header = message.header();
+
+        let handshake = reader
+            .strip_prefix(&[TAG_HANDSHAKE])
+            .and_then(Handshake::read)
+            .map(|(handshake, _)| handshake);
+        let accepted = handshake.is_some_and(|handshake| {
+            let address = SocketAddr::new(src.ip(), header.sender_port);
+            self.accept_plain(handshake, header, address)
+        });
+
+        let discovery = Message::is_discovery(message.type_byte());
+        if self.mode == Encryption::Required && !(discovery && accepted) {
+            self.metrics.inc(Counter::PlaintextRejected);
+            debug!("Plaintext datagram from {src} discarded");
+            return None;
+        }
+        Some(message_len)
+    }
+
+    /// Establish a session with the sender of a plaintext `handshake`,
+    /// returning whether the handshake is valid.
+    ///
+    /// A confirmed session is never replaced in plaintext: a newer handshake
+    /// only marks it as renewed, so that the sender receives our handshake
+    /// and re-establishes the session through an encrypted one.
+    fn accept_plain(
+        &self,
+        handshake: Handshake,
+        header: &Header,
+        address: SocketAddr,
+    ) -> bool {
+        let Some(public_key) = header.public_key else {
+            return false;
+        };
+        let mut sessions = self.sessions();
+        if let Some(session) = sessions.by_key.get(&public_key) {
+            if session.handshake == handshake
+                || session.renewal == Some(handshake)
+            {
+                return true;
+            }
+            // A previous handshake, replayed
+            if handshake.created <= session.handshake.created {
+                return false;
+            }
+        }
+        if !self.take_handshake()
+            || !handshake.verify(&public_key, self.network_id)
+        {
+            return false;
+        }
+        if let Some(session) = sessions.by_key.get_mut(&public_key)
+            && session.confirmed
+        {
+            debug!("Encrypted session with {address} to be renewed");
+            session.renewal = Some(handshake);
+            return true;
+        }
+        let Some(session) = self.derive(handshake, address) else {
+            return false;
+        };
+        debug!("Encrypted session established with {address}");
+        sessions.insert(public_key, session);
+        self.metrics.inc(Counter::SessionsEstablished);
+        true
+    }
+
+    fn open_sealed(
+        &self,
+        datagram: &mut [u8],
+        src: SocketAddr,
+    ) -> Option<usize> {
+        let handshake = match datagram.first() {
+            Some(&ID_SEALED_HANDSHAKE) => {
+                Some(Handshake::read(&datagram[1..])?.0)
+            }
+            _ => None,
+        };
+        let aad_len = 1 + match handshake {
+            Some(_) => HANDSHAKE_LEN,
+            None => EXCHANGE_KEY_LEN,
+        };
+        if datagram.len() < aad_len + NONCE_LEN + TAG_LEN {
+            return None;
+        }
+        // The handshake starts with the exchange key as well
+        let exchange_key: ExchangeBytes =
+            datagram[1..1 + EXCHANGE_KEY_LEN].try_into().ok()?;
+        let (aad, rest) = datagram.split_at_mut(aad_len);
+        let (nonce, rest) = rest.split_at_mut(NONCE_LEN);
+        let (message, tag) = rest.split_at_mut(rest.len() - TAG_LEN);
+        let counter =
+            u64::from_le_bytes(nonce[NONCE_PREFIX_LEN..].try_into().ok()?);
+        let message_len = message.len();
+        let decrypt = |cipher: &XChaCha20Poly1305, message: &mut [u8]| {
+            cipher
+                .decrypt_in_place_detached(
+                    XNonce::from_slice(nonce),
+                    aad,
+                    message,
+                    Tag::from_slice(tag),
+                )
+                .ok()
+        };
+
+        let mut sessions = self.sessions();
+        match sessions.by_exchange_key.get(&exchange_key) {
+            Some(public_key) => {
+                let public_key = *public_key;
+                let session = sessions.by_key.get_mut(&public_key)?;
+                if !session.received.check(counter) {
+                    return None;
+                }
+                decrypt(&session.recv, message)?;
+                let header = sender_header(message)?;
+                if header.public_key != Some(public_key) {
+                    return None;
+                }
+                session.received.update(counter);
+                session.confirmed = true;
+                let address = SocketAddr::new(src.ip(), header.sender_port);
+                sessions.route(address, public_key);
+            }
+            None => {
+                // Unknown session, which the sender established through a
+                // handshake we missed, or renewed after restarting
+                let handshake = handshake?;
+                if !self.take_handshake() {
+                    return None;
+                }
+                let mut session = self.derive(handshake, src)?;
+                decrypt(&session.recv, message)?;
+                let header = sender_header(message)?;
+                let public_key = header.public_key?;
+                if !handshake.verify(&public_key, self.network_id) {
+                    return None;
+                }
+                // A previous handshake, replayed
+                if sessions.by_key.get(&public_key).is_some_and(|current| {
+                    current.handshake.created >= handshake.created
+                }) {
+                    return None;
+                }
+                session.address = SocketAddr::new(src.ip(), header.sender_port);
+                session.received.update(counter);
+                session.confirmed = true;
+                debug!(
+                    "Encrypted session established with {}",
+                    session.address
+                );
+                sessions.insert(public_key, session);
+                self.metrics.inc(Counter::SessionsEstablished);
+            }
+        }
+
+        let start = aad_len + NONCE_LEN;
+        datagram.copy_within(start..start + message_len, 0);
+        Some(message_len)
+    }
+}
+
+/// Read the header of the decrypted message `bytes`
+fn sender_header(bytes: &[u8]) -> Option<Header> {
+    Message::unmarshal_header(&mut &bytes[..])
+        .map(|(_, header)| header)
+        .ok()
+}
+
+impl Transport for SecureTransport {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            match self.seal(data, target) {
+                Some(datagram) => self.inner.send_to(&datagram, target).await,
+                None => {
+                    self.metrics.inc(Counter::PlaintextRejected);
+                    debug!("Plaintext message to {target} not sent");
+                    Ok(())
+                }
+            }
+        })
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            loop {
+                let (len, src) = self.inner.recv_from(buf).await?;
+                if let Some(len) = self.open(&mut buf[..len], src) {
+                    return Ok((len, src));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use std::time::Duration;
+
+    use semver::Version;
+
+    use super::*;
+    use crate::config::DEFAULT_HANDSHAKES_PER_SEC;
+    use crate::encoding::message::{BroadcastPayload, Message};
+    use crate::peer::PeerNode;
+    use crate::transport::MemoryNetwork;
+
+    struct TestPeer {
+        address: SocketAddr,
+        header: Header,
+        transport: SecureTransport,
+    }
+
+    impl TestPeer {
+        /// Open `datagram`, as received from `src`
+        fn open(&self, datagram: &[u8], src: SocketAddr) -> Option<Vec<u8>> {
+            let mut buf = datagram.to_vec();
+            let len = self.transport.open(&mut buf, src)?;
+            buf.truncate(len);
+            Some(buf)
+        }
+    }
+
+    fn peer(
+        network: &MemoryNetwork,
+        address: &str,
+        mode: Encryption,
+    ) -> TestPeer {
+        let identity = Identity::generate();
+        peer_with(
+            network,
+            address,
+            mode,
+            &identity,
+            DEFAULT_HANDSHAKES_PER_SEC,
+        )
+    }
+
+    fn peer_with(
+        network: &MemoryNetwork,
+        address: &str,
+        mode: Encryption,
+        identity: &Identity,
+        handshakes_per_sec: u32,
+    ) -> TestPeer {
+        let node =
+            PeerNode::generate_with_identity(address, identity, 0).unwrap();
+        let address = address.parse().unwrap();
+        let inner = Arc::new(network.transport(address).unwrap());
+        let metrics = Arc::new(Metrics::default());
+        TestPeer {
+            address,
+            header: node.to_header(),
+            transport: SecureTransport::new(
+                inner,
+                mode,
+                identity,
+                0,
+                handshakes_per_sec,
+                metrics,
+            ),
+        }
+    }
+
+    fn ping(header: Header) -> Vec<u8> {
+        let version = Version::new(0, 0, 1);
+        Message::Ping(header, version).bytes().unwrap()
+    }
+
+    fn broadcast(header: Header) -> Vec<u8> {
+        let payload = BroadcastPayload {
+            height: 0,
+            gossip_frame: b"secret".to_vec(),
+            origin: None,
+        };
+        Message::broadcast(header, payload).bytes().unwrap()
+    }
+
+    /// Establish a confirmed session between `a` and `b`
+    fn establish(a: &TestPeer, b: &TestPeer) {
+        let datagram = a.transport.seal(&ping(a.header), b.address).unwrap();
+        b.open(&datagram, a.address).unwrap();
+        let datagram =
+            b.transport.seal(&broadcast(b.header), a.address).unwrap();
+        a.open(&datagram, b.address).unwrap();
+        let datagram =
+            a.transport.seal(&broadcast(a.header), b.address).unwrap();
+        b.open(&datagram, a.address).unwrap();
+    }
+
+    #[test]
+    fn test_session_handshake() {
+        let network = MemoryNetwork::new();
+        let a = peer(&network, "10.0.0.1:9000", Encryption::Optional);
+        let b = peer(&network, "10.0.0.2:9000", Encryption::Required);
+
+        // Discovery messages carry the handshake in plaintext
+        let ping = ping(a.header);
+        let datagram = a.transport.seal(&ping, b.address).unwrap();
+        assert!(datagram.starts_with(&ping));
+        assert_eq!(b.open(&datagram, a.address), Some(ping));
+
+        // Encrypted along with the handshake, until the session is confirmed
+        let message = broadcast(b.header);
+        let datagram = b.transport.seal(&message, a.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED_HANDSHAKE);
+        let secret = b"secret";
+        assert!(!datagram.windows(secret.len()).any(|w| w == secret));
+        assert_eq!(a.open(&datagram, b.address), Some(message));
+
+        let message = broadcast(a.header);
+        let datagram = a.transport.seal(&message, b.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED);
+        assert_eq!(b.open(&datagram, a.address), Some(message));
+        let message = broadcast(b.header);
+        let datagram = b.transport.seal(&message, a.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED);
+
+        // Tampered datagrams are discarded
+        let mut tampered = datagram.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert_eq!(a.open(&tampered, b.address), None);
+        assert_eq!(a.open(&datagram, b.address), Some(message));
+    }
+
+    #[test]
+    fn test_replayed_datagrams() {
+        let network = MemoryNetwork::new();
+        let a = peer(&network, "10.0.0.1:9000", Encryption::Required);
+        let b = peer(&network, "10.0.0.2:9000", Encryption::Required);
+        establish(&a, &b);
+
+        let message = broadcast(a.header);
+        let first = a.transport.seal(&message, b.address).unwrap();
+        let second = a.transport.seal(&message, b.address).unwrap();
+
+        // Accepted out of order, but only once
+        assert_eq!(b.open(&second, a.address), Some(message.clone()));
+        assert_eq!(b.open(&first, a.address), Some(message));
+        assert_eq!(b.open(&first, a.address), None);
+        assert_eq!(b.open(&second, a.address), None);
+    }
+
+    #[test]
+    fn test_replay_window() {
+        let mut window = ReplayWindow::default();
+        window.update(0);
+        assert!(!window.check(0));
+        window.update(REPLAY_WINDOW + 1);
+        assert!(!window.check(REPLAY_WINDOW + 1));
+        assert!(window.check(REPLAY_WINDOW));
+        assert!(window.check(2));
+        // Too old to be told from a replay
+        assert!(!window.check(1));
+        window.update(2);
+        assert!(!window.check(2));
+        window.update(REPLAY_WINDOW * 3);
+        assert!(!window.check(REPLAY_WINDOW));
+        assert!(window.check(REPLAY_WINDOW * 3 - 1));
+    }
+
+    #[test]
+    fn test_session_bound_to_identity() {
+        let network = MemoryNetwork::new();
+        let a = peer(&network, "10.0.0.1:9000", Encryption::Required);
+        let b = peer(&network, "10.0.0.2:9000", Encryption::Required);
+        let c = peer(&network, "10.0.0.3:9000", Encryption::Required);
+        establish(&a, &b);
+
+        // A handshake from the address of `a` doesn't replace its session
+        let spoofed = c.transport.seal(&ping(c.header), b.address).unwrap();
+        assert!(b.open(&spoofed, a.address).is_some());
+        let datagram =
+            b.transport.seal(&broadcast(b.header), a.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED);
+        assert!(a.open(&datagram, b.address).is_some());
+
+        // Messages sealed by a session are bound to its identity
+        let forged = a.transport.seal(&broadcast(c.header), b.address).unwrap();
+        assert_eq!(b.open(&forged, a.address), None);
+    }
+
+    #[test]
+    fn test_restarted_peer() {
+        let network = MemoryNetwork::new();
+        let identity = Identity::generate();
+        let a = peer_with(
+            &network,
+            "10.0.0.1:9000",
+            Encryption::Required,
+            &identity,
+            DEFAULT_HANDSHAKES_PER_SEC,
+        );
+        let b = peer(&network, "10.0.0.2:9000", Encryption::Required);
+        establish(&a, &b);
+        let replayed = a.transport.seal(&ping(a.header), b.address).unwrap();
+        drop(a);
+
+        // The restarted peer offers a newer handshake
+        std::thread::sleep(Duration::from_millis(2));
+        let a = peer_with(
+            &MemoryNetwork::new(),
+            "10.0.0.1:9000",
+            Encryption::Required,
+            &identity,
+            DEFAULT_HANDSHAKES_PER_SEC,
+        );
+        let datagram = a.transport.seal(&ping(a.header), b.address).unwrap();
+        assert!(b.open(&datagram, a.address).is_some());
+
+        // Discovery messages are answered with our handshake, until the
+        // session is renewed through an encrypted handshake
+        let pong = b.transport.seal(&ping(b.header), a.address).unwrap();
+        assert!(pong.starts_with(&ping(b.header)));
+        assert!(a.open(&pong, b.address).is_some());
+        let message = broadcast(a.header);
+        let datagram = a.transport.seal(&message, b.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED_HANDSHAKE);
+        assert_eq!(b.open(&datagram, a.address), Some(message));
+        let message = broadcast(b.header);
+        let datagram = b.transport.seal(&message, a.address).unwrap();
+        assert_eq!(datagram[0], ID_SEALED);
+        assert_eq!(a.open(&datagram, b.address), Some(message));
+
+        // The previous session can't be restored by a replay
+        assert!(b.open(&replayed, a.address).is_none());
+        let datagram =
+            b.transport.seal(&broadcast(b.header), a.address).unwrap();
+        assert!(a.open(&datagram, b.address).is_some());
+    }
+
+    #[test]
+    fn test_handshakes_throttled() {
+        let network = MemoryNetwork::new();
+        let a = peer_with(
+            &network,
+            "10.0.0.1:9000",
+            Encryption::Required,
+            &Identity::generate(),
+            1,
+        );
+        let b = peer(&network, "10.0.0.2:9000", Encryption::Required);
+        let c = peer(&network, "10.0.0.3:9000", Encryption::Required);
+
+        let datagram = b.transport.seal(&ping(b.header), a.address).unwrap();
+        assert!(a.open(&datagram, b.address).is_some());
+        // Known handshakes are not authenticated again
+        assert!(a.open(&datagram, b.address).is_some());
+
+        let datagram = c.transport.seal(&ping(c.header), a.address).unwrap();
+        assert!(a.open(&datagram, c.address).is_none());
+    }
+
+    #[test]
+    fn test_plaintext_rejected() {
+        let network = MemoryNetwork::new();
+        let a = peer(&network, "10.0.0.1:9000", Encryption::Required);
+        let plain = PeerNode::generate("10.0.0.2:9000", 0).unwrap();
+        let plain_address = plain.value().address();
+
+        // Only discovery messages are sent in plaintext
+        assert!(a.transport.seal(&ping(a.header), *plain_address).is_some());
+        assert!(
+            a.transport
+                .seal(&broadcast(a.header), *plain_address)
+                .is_none()
+        );
+
+        // Plaintext without handshake is discarded
+        let header = plain.to_header();
+        assert!(a.open(&ping(header), *plain_address).is_none());
+        assert!(a.open(&broadcast(header), *plain_address).is_none());
+    }
+}
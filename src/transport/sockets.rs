@@ -9,71 +9,55 @@ use std::net::SocketAddr;
 use std::time::Duration;
 
 use tokio::net::UdpSocket;
-use tokio::runtime::Handle;
-use tokio::task::block_in_place;
-use tokio::time::{self, Interval, timeout};
+use tokio::time::timeout;
 use tracing::{info, warn};
 
-use super::encoding::Configurable;
 use crate::config::NetworkConfig;
+use crate::error::KadcastError;
 
 const MIN_RETRY_COUNT: u8 = 1;
 
 pub(super) struct MultipleOutSocket {
     ipv4: UdpSocket,
     ipv6: UdpSocket,
-    udp_backoff_timeout: Option<Interval>,
     retry_count: u8,
     udp_send_retry_interval: Duration,
 }
 
-impl Configurable for MultipleOutSocket {
-    type TConf = NetworkConfig;
-
-    fn default_configuration() -> Self::TConf {
-        NetworkConfig::default()
-    }
+/// Bind a non-blocking socket to `address`, without waiting for the runtime.
+///
+/// Must be called within a tokio runtime.
+pub(super) fn bind(address: &str) -> Result<UdpSocket, KadcastError> {
+    std::net::UdpSocket::bind(address)
+        .and_then(|socket| {
+            socket.set_nonblocking(true)?;
+            UdpSocket::from_std(socket)
+        })
+        .map_err(|e| KadcastError::Bind(address.to_string(), e))
+}
 
-    fn configure(conf: &Self::TConf) -> Self {
-        let udp_backoff_timeout =
-            conf.udp_send_backoff_timeout.map(time::interval);
-        let retry_count = {
-            match conf.udp_send_retry_count > MIN_RETRY_COUNT {
-                true => conf.udp_send_retry_count,
-                _ => MIN_RETRY_COUNT,
-            }
-        };
+impl MultipleOutSocket {
+    pub(super) fn bind(conf: &NetworkConfig) -> Result<Self, KadcastError> {
+        let retry_count = conf.udp_send_retry_count;
+        if retry_count < MIN_RETRY_COUNT {
+            let min = MIN_RETRY_COUNT;
+            warn!("udp_send_retry_count={retry_count} too low, using {min}");
+        }
+        let retry_count = retry_count.max(MIN_RETRY_COUNT);
         let udp_send_retry_interval = conf.udp_send_retry_interval;
 
-        let ipv4 = block_in_place(move || {
-            Handle::current()
-                .block_on(async move { UdpSocket::bind("0.0.0.0:0").await })
-        })
-        .unwrap();
-        let ipv6 = block_in_place(move || {
-            Handle::current()
-                .block_on(async move { UdpSocket::bind("[::]:0").await })
-        })
-        .unwrap();
-        MultipleOutSocket {
-            ipv4,
-            ipv6,
-            udp_backoff_timeout,
+        Ok(MultipleOutSocket {
+            ipv4: bind("0.0.0.0:0")?,
+            ipv6: bind("[::]:0")?,
             retry_count,
             udp_send_retry_interval,
-        }
+        })
     }
-}
-
-impl MultipleOutSocket {
     pub(super) async fn send(
         &mut self,
         data: &[u8],
         remote_addr: &SocketAddr,
     ) -> io::Result<()> {
-        if let Some(sleep) = &mut self.udp_backoff_timeout {
-            sleep.tick().await;
-        }
         let max_retry = self.retry_count;
 
         for i in 1..=max_retry {
@@ -128,9 +112,7 @@ mod tests {
         // work in the `log` crate.
         tracing::subscriber::set_global_default(subscriber)
             .expect("Failed on subscribe tracing");
-        let mut socket = MultipleOutSocket::configure(
-            &MultipleOutSocket::default_configuration(),
-        );
+        let mut socket = MultipleOutSocket::bind(&NetworkConfig::default())?;
         let data = [0u8; 1000];
         let root = PeerNode::generate("192.168.0.1:666", 0)?;
         let target = root.as_peer_info().to_socket_address();
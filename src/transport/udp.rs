@@ -0,0 +1,88 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+//
+// Copyright (c) DUSK NETWORK. All rights reserved.
+
+use std::io;
+use std::net::SocketAddr;
+
+use socket2::SockRef;
+use tokio::net::UdpSocket;
+use tokio::sync::Mutex;
+use tracing::{error, info, warn};
+
+use super::sockets::{self, MultipleOutSocket};
+use super::{Transport, TransportFuture};
+use crate::config::Config;
+use crate::error::KadcastError;
+
+/// The default [Transport], exchanging datagrams over UDP.
+///
+/// The listen socket is bound when the transport is created, while the
+/// messages are sent through ephemeral sockets (one for IPv4 and one for
+/// IPv6).
+pub(crate) struct UdpTransport {
+    socket: UdpSocket,
+    out_socket: Mutex<MultipleOutSocket>,
+}
+
+impl UdpTransport {
+    pub(crate) fn bind(conf: &Config) -> Result<Self, KadcastError> {
+        let listen_address =
+            conf.listen_address.as_ref().unwrap_or(&conf.public_address);
+        let socket = sockets::bind(listen_address)?;
+        if let Ok(address) = socket.local_addr() {
+            info!("Listening on: {address}");
+        }
+
+        // Try to extend socket recv buffer size
+        Self::configure_socket(&socket, conf)
+            .map_err(|e| KadcastError::Bind(listen_address.clone(), e))?;
+
+        let out_socket = MultipleOutSocket::bind(&conf.network)?;
+        Ok(Self {
+            socket,
+            out_socket: Mutex::new(out_socket),
+        })
+    }
+
+    pub fn configure_socket(
+        socket: &UdpSocket,
+        conf: &Config,
+    ) -> io::Result<()> {
+        if let Some(size) = conf.network.udp_recv_buffer_size {
+            let sock = SockRef::from(socket);
+            match sock.set_recv_buffer_size(size) {
+                Ok(_) => info!("udp_recv_buffer is now {size}"),
+                Err(e) => {
+                    error!("Error setting udp_recv_buffer to {size} - {e}",);
+                    warn!(
+                        "udp_recv_buffer is still {}",
+                        sock.recv_buffer_size().unwrap_or(0)
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Transport for UdpTransport {
+    fn send_to<'a>(
+        &'a self,
+        data: &'a [u8],
+        target: SocketAddr,
+    ) -> TransportFuture<'a, ()> {
+        Box::pin(async move {
+            self.out_socket.lock().await.send(data, &target).await
+        })
+    }
+
+    fn recv_from<'a>(
+        &'a self,
+        buf: &'a mut [u8],
+    ) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move { self.socket.recv_from(buf).await })
+    }
+}
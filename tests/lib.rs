@@ -8,12 +8,20 @@
 mod tests {
 
     use std::collections::{HashMap, HashSet};
-    use std::net::{AddrParseError, SocketAddr, ToSocketAddrs};
+    use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
     use std::ops::Range;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
     use std::time::Duration;
 
-    use kadcast::config::Config;
-    use kadcast::{MessageInfo, NetworkListen, Peer};
+    use kadcast::config::{Config, ConfigError, Encryption};
+    use kadcast::simulation::Simulation;
+    use kadcast::transport::{LinkConditions, MemoryNetwork, Transport};
+    use kadcast::{
+        BroadcastValidator, Counter, Identity, IpRange, KadcastError,
+        MessageInfo, NetworkListen, Peer, PeerEvent, Priority, Responder,
+        ResponseFuture, Validation, ValidationFuture,
+    };
     use tokio::sync::mpsc;
     use tokio::time::timeout;
     use tracing::{info, warn};
@@ -24,6 +32,8 @@ mod tests {
     const WAIT_SEC: u64 = 20;
     const MESSAGE_SIZE: usize = 100_000;
 
+    type Received = (usize, (Vec<u8>, SocketAddr, u8));
+
     #[test]
     fn test_dns_resolver() {
         let server_details = "192.168.1.5:80";
@@ -72,9 +82,7 @@ mod tests {
 
         tokio::time::sleep(Duration::from_millis(2000)).await;
         let mut data: Vec<u8> = vec![0; MESSAGE_SIZE];
-        for i in 0..data.len() {
-            data[i] = rand::Rng::r#gen(&mut rand::thread_rng());
-        }
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut data);
         for i in 0..NODES {
             info!("ROUTING TABLE PEER #{}", i);
             peers.get(&i).unwrap().report().await;
@@ -118,8 +126,844 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn shutdown_releases_listen_port()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let (tx, _rx) = mpsc::channel(100);
+        let listen_address = format!("127.0.0.1:{}", BASE_PORT + 100);
+
+        for _ in 0..2 {
+            let peer = create_peer(100, vec![], tx.clone(), None)?;
+            // Give the incoming loop the time to bind the listen socket
+            tokio::time::sleep(Duration::from_millis(500)).await;
+            assert!(
+                UdpSocket::bind(&listen_address).is_err(),
+                "The listen socket should be bound by the peer"
+            );
+            peer.shutdown().await;
+            drop(UdpSocket::bind(&listen_address)?);
+        }
+
+        let peer = create_peer(100, vec![], tx, None)?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        drop(peer);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        drop(UdpSocket::bind(&listen_address)?);
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn receive_from_channel() -> Result<(), Box<dyn std::error::Error>> {
+        let (receiver, mut rx) =
+            Peer::new_with_receiver(peer_config(200, vec![], None))?;
+        let (sender, _) =
+            Peer::new_with_receiver(peer_config(201, vec![], None))?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let receiver_addr = format!("127.0.0.1:{}", BASE_PORT + 200).parse()?;
+        sender.send(b"direct message", receiver_addr).await;
+
+        let (message, metadata) =
+            timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+                .await?
+                .expect("The receiver should be open");
+        assert_eq!(message, b"direct message");
+        assert_eq!(metadata.src().port(), (BASE_PORT + 201) as u16);
+
+        let metrics = receiver.metrics().await;
+        assert_eq!(metrics.counter(Counter::BroadcastsReceived), 1);
+        assert!(metrics.counter(Counter::DatagramsIn) >= 1);
+        assert!(
+            metrics
+                .to_prometheus()
+                .contains("kadcast_datagrams_in_total")
+        );
+
+        receiver.shutdown().await;
+        sender.shutdown().await;
+        assert!(rx.recv().await.is_none(), "The receiver should be closed");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn validator_gates_broadcasts()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let config = Config {
+            penalize_rejected: true,
+            ..peer_config(202, vec![], None)
+        };
+        let (receiver, mut rx) = Peer::builder(config)
+            .validator(RejectInvalid)
+            .build_with_receiver()?;
+        let (sender, _) =
+            Peer::new_with_receiver(peer_config(203, vec![], None))?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let receiver_addr: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 202).parse()?;
+        sender.send(b"invalid message", receiver_addr).await;
+        sender.send(b"ignored message", receiver_addr).await;
+        sender.send(b"valid message", receiver_addr).await;
+
+        let (message, _) = timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+            .await?
+            .expect("The receiver should be open");
+        assert_eq!(message, b"valid message");
+
+        // The sender is penalized, not banned
+        let sender_ip = receiver_addr.ip();
+        assert!(receiver.source_scores().contains_key(&sender_ip));
+        assert!(receiver.blocked_sources().await.is_empty());
+
+        receiver.shutdown().await;
+        sender.shutdown().await;
+        assert!(rx.recv().await.is_none(), "No other message expected");
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn manual_propagation() -> Result<(), Box<dyn std::error::Error>> {
+        const PEERS: i32 = 8;
+        let bootstrap = vec![format!("127.0.0.1:{}", BASE_PORT + 300)];
+        let (tx, mut rx) = mpsc::channel(100);
+
+        let mut tasks = vec![];
+        for i in 300..300 + PEERS - 1 {
+            let config = Config {
+                auto_propagate: false,
+                ..peer_config(i, bootstrap.clone(), None)
+            };
+            let (peer, mut peer_rx) = Peer::new_with_receiver(config)?;
+            let tx = tx.clone();
+            tasks.push(tokio::spawn(async move {
+                while let Some((message, metadata)) = peer_rx.recv().await {
+                    peer.propagate(&metadata, &message).await;
+                    let _ = tx.send((i, message)).await;
+                }
+            }));
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        let sender_config = Config {
+            auto_propagate: false,
+            ..peer_config(300 + PEERS - 1, bootstrap, None)
+        };
+        let (sender, _) = Peer::new_with_receiver(sender_config)?;
+        tokio::time::sleep(Duration::from_millis(2000)).await;
+
+        sender.broadcast(b"propagated message", None).await;
+        let mut received = HashSet::new();
+        while received.len() < (PEERS - 1) as usize {
+            let (i, message) =
+                timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+                    .await?
+                    .expect("The channel should be open");
+            assert_eq!(message, b"propagated message");
+            assert!(received.insert(i), "Message received twice by {i}");
+        }
+
+        // Messages received at height 0 are not propagated
+        let receiver_addr = format!("127.0.0.1:{}", BASE_PORT + 300).parse()?;
+        sender.send(b"direct message", receiver_addr).await;
+        let (i, message) = timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+            .await?
+            .expect("The channel should be open");
+        assert_eq!((i, &message[..]), (300, &b"direct message"[..]));
+        assert!(
+            timeout(Duration::from_secs(2), rx.recv()).await.is_err(),
+            "The direct message should not be propagated"
+        );
+
+        tasks.iter().for_each(|t| t.abort());
+        sender.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn restore_routing_table() -> Result<(), Box<dyn std::error::Error>> {
+        let snapshot_path = std::env::temp_dir()
+            .join(format!("kadcast-snapshot-{}", std::process::id()));
+        let bootstrapper_addr: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 400).parse()?;
+        let restarted_config = |bootstrap| {
+            let mut config = peer_config(401, bootstrap, None);
+            config.snapshot.path = Some(snapshot_path.clone());
+            config
+        };
+
+        let (bootstrapper, _) =
+            Peer::new_with_receiver(peer_config(400, vec![], None))?;
+        let bootstrap = vec![bootstrapper_addr.to_string()];
+        let (peer, _) = Peer::new_with_receiver(restarted_config(bootstrap))?;
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(peer.alive_nodes(10).await, vec![bootstrapper_addr]);
+        peer.shutdown().await;
+
+        // Without bootstrappers, the peer joins through the saved table
+        let (peer, _) = Peer::new_with_receiver(restarted_config(vec![]))?;
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(peer.alive_nodes(10).await, vec![bootstrapper_addr]);
+
+        peer.shutdown().await;
+        bootstrapper.shutdown().await;
+        std::fs::remove_file(snapshot_path)?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn find_closest_nodes() -> Result<(), Box<dyn std::error::Error>> {
+        const PEERS: i32 = 6;
+        let bootstrap = vec![format!("127.0.0.1:{}", BASE_PORT + 500)];
+        let mut peers = vec![];
+        for i in 500..500 + PEERS {
+            let config = peer_config(i, bootstrap.clone(), None);
+            peers.push(Peer::new_with_receiver(config)?.0);
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+
+        let searcher = peers.last().expect("peers to be created");
+        let target = peers.get(1).expect("peers to be created");
+        let found = searcher.find_closest(*target.id()).await;
+        assert_eq!(found.len(), (PEERS - 1) as usize);
+        assert_eq!(found[0].port(), (BASE_PORT + 501) as u16);
+
+        let found = searcher.find_closest([0; 16]).await;
+        assert_eq!(found.len(), (PEERS - 1) as usize);
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn request_response() -> Result<(), Box<dyn std::error::Error>> {
+        let (responder, _) = Peer::builder(peer_config(600, vec![], None))
+            .responder(ReverseRequest)
+            .build_with_receiver()?;
+        let (silent, _) =
+            Peer::new_with_receiver(peer_config(601, vec![], None))?;
+        let (requester, _) =
+            Peer::new_with_receiver(peer_config(602, vec![], None))?;
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let responder_addr =
+            format!("127.0.0.1:{}", BASE_PORT + 600).parse()?;
+        let response = requester
+            .request(b"request", responder_addr, Duration::from_secs(WAIT_SEC))
+            .await?;
+        assert_eq!(response, b"tseuqer");
+
+        // Bodies exceeding the MTU are split in chunks
+        let request: Vec<_> = (0..MESSAGE_SIZE).map(|i| i as u8).collect();
+        let response = requester
+            .request(&request, responder_addr, Duration::from_secs(WAIT_SEC))
+            .await?;
+        assert_eq!(response, request.iter().rev().copied().collect::<Vec<_>>());
+
+        let silent_addr = format!("127.0.0.1:{}", BASE_PORT + 601).parse()?;
+        let err = requester
+            .request(b"request", silent_addr, Duration::from_millis(500))
+            .await
+            .expect_err("No response expected");
+        assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+
+        responder.shutdown().await;
+        silent.shutdown().await;
+        requester.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn concurrent_requests_bounded()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.5.{i}:9000");
+        let active = Arc::new(AtomicUsize::new(0));
+        let config = Config {
+            public_address: address(1),
+            max_concurrent_requests: 1,
+            ..Default::default()
+        };
+        let (responder, _) = Peer::builder(config)
+            .transport(network.transport(address(1).parse()?)?)
+            .responder(StalledResponder(active.clone()))
+            .build_with_receiver()?;
+        let config = Config {
+            public_address: address(2),
+            ..Default::default()
+        };
+        let (requester, _) = Peer::builder(config)
+            .transport(network.transport(address(2).parse()?)?)
+            .build_with_receiver()?;
+
+        // The second request is dropped while the first one is answered
+        let target = address(1).parse()?;
+        let wait = Duration::from_millis(500);
+        for _ in 0..2 {
+            let err = requester
+                .request(b"request", target, wait)
+                .await
+                .expect_err("No response expected");
+            assert_eq!(err.kind(), std::io::ErrorKind::TimedOut);
+        }
+        assert_eq!(active.load(Ordering::SeqCst), 1);
+        let metrics = responder.metrics().await;
+        assert_eq!(metrics.counter(Counter::RequestsReceived), 2);
+        assert_eq!(metrics.counter(Counter::RequestsDropped), 1);
+
+        // The request being answered is aborted on shutdown
+        responder.shutdown().await;
+        timeout(Duration::from_secs(WAIT_SEC), async {
+            while active.load(Ordering::SeqCst) > 0 {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+        requester.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 1)]
+    async fn peer_events() -> Result<(), Box<dyn std::error::Error>> {
+        let (peer, _) =
+            Peer::new_with_receiver(peer_config(700, vec![], None))?;
+        let mut events = peer.events();
+        let bootstrap = vec![format!("127.0.0.1:{}", BASE_PORT + 700)];
+        let (joining, _) =
+            Peer::new_with_receiver(peer_config(701, bootstrap, None))?;
+        let joining_addr: SocketAddr =
+            format!("127.0.0.1:{}", BASE_PORT + 701).parse()?;
+
+        let event =
+            timeout(Duration::from_secs(WAIT_SEC), events.recv()).await??;
+        let PeerEvent::PeerAdded {
+            id,
+            address,
+            height,
+        } = event
+        else {
+            panic!("Unexpected event {event:?}");
+        };
+        assert_eq!((&id, address), (joining.id(), joining_addr));
+
+        peer.block_source(joining_addr).await;
+        let mut event = events.recv().await?;
+        while matches!(event, PeerEvent::PeerRefreshed { .. }) {
+            event = events.recv().await?;
+        }
+        assert_eq!(
+            event,
+            PeerEvent::PeerBlocked {
+                source: joining_addr.into()
+            }
+        );
+        assert_eq!(events.recv().await?, PeerEvent::BucketEmpty { height });
+
+        let range = IpRange::new(joining_addr.ip(), 24).unwrap();
+        peer.block_source_for(range, Some(Duration::from_secs(60)))
+            .await;
+        let bans = peer.blocked_sources().await;
+        assert_eq!(bans.len(), 2);
+        assert!(peer.unblock_source(joining_addr).await);
+        assert!(!peer.unblock_source(joining_addr).await);
+        let bans = peer.blocked_sources().await;
+        assert_eq!(bans[0].source, range.into());
+        assert!(bans[0].expires_in.is_some());
+
+        peer.shutdown().await;
+        joining.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn bootstrap_with_address_tokens()
+    -> Result<(), Box<dyn std::error::Error>> {
+        const PEERS: usize = 8;
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.6.{i}:9000");
+        let bootstrap = vec![address(1)];
+        let start = |i: usize, recursive_discovery: bool| {
+            let config = Config {
+                public_address: address(i),
+                bootstrapping_nodes: bootstrap.clone(),
+                recursive_discovery,
+                ..Default::default()
+            };
+            let transport = network.transport(address(i).parse()?)?;
+            Peer::builder(config)
+                .transport(transport)
+                .build_with_receiver()
+                .map(|(peer, _)| peer)
+                .map_err(Box::<dyn std::error::Error>::from)
+        };
+        let mut peers = vec![];
+        for i in 1..=PEERS {
+            peers.push(start(i, true)?);
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+
+        // The first reply of the bootstrapper, to a request without token,
+        // holds a single node. Without recursive discovery, the other ones
+        // are only learned by asking again with the token received.
+        let joining = start(PEERS + 1, false)?;
+        timeout(Duration::from_secs(WAIT_SEC), async {
+            while joining.alive_nodes(PEERS).await.len() < PEERS {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+
+        joining.shutdown().await;
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn keyed_peer_moves() -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.7.{i}:9000");
+        let identity = Identity::generate();
+        let start = |i: usize, identity: Option<Identity>| {
+            let config = Config {
+                public_address: address(i),
+                bootstrapping_nodes: vec![address(1)],
+                ..Default::default()
+            };
+            let mut builder = Peer::builder(config)
+                .transport(network.transport(address(i).parse()?)?);
+            if let Some(identity) = identity {
+                builder = builder.identity(identity);
+            }
+            builder
+                .build_with_receiver()
+                .map(|(peer, _)| peer)
+                .map_err(Box::<dyn std::error::Error>::from)
+        };
+        let bootstrapper = start(1, None)?;
+        let moved: SocketAddr = address(2).parse()?;
+        let peer = start(2, Some(identity.clone()))?;
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert_eq!(bootstrapper.alive_nodes(10).await, vec![moved]);
+        peer.shutdown().await;
+
+        // The same identity proves its key at the new address
+        let new_address: SocketAddr = address(3).parse()?;
+        let peer = start(3, Some(identity))?;
+        timeout(Duration::from_secs(WAIT_SEC), async {
+            while bootstrapper.alive_nodes(10).await != vec![new_address] {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+
+        peer.shutdown().await;
+        bootstrapper.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn memory_transport_broadcast()
+    -> Result<(), Box<dyn std::error::Error>> {
+        const PEERS: usize = 24;
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.{}.{}:9000", i / 256, i % 256);
+        let bootstrap = vec![address(1)];
+
+        let mut peers = vec![];
+        let mut receivers = vec![];
+        for i in 1..=PEERS {
+            let config = Config {
+                public_address: address(i),
+                bootstrapping_nodes: bootstrap.clone(),
+                ..Default::default()
+            };
+            let transport = network.transport(address(i).parse()?)?;
+            let (peer, rx) = Peer::builder(config)
+                .transport(transport)
+                .build_with_receiver()?;
+            peers.push(peer);
+            receivers.push(rx);
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+
+        let sender = peers.last().expect("peers to be created");
+        assert!(sender.alive_nodes(PEERS).await.len() > 1);
+        let mut data = vec![0; MESSAGE_SIZE];
+        rand::RngCore::fill_bytes(&mut rand::thread_rng(), &mut data);
+        sender.broadcast(&data, None).await;
+
+        for rx in receivers.iter_mut().take(PEERS - 1) {
+            let (message, _) =
+                timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+                    .await?
+                    .expect("The receiver should be open");
+            assert_eq!(message, data);
+        }
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn signed_broadcast() -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.1.{i}:9000");
+        let identity = Identity::generate();
+        let public_key = identity.public_key();
+
+        let mut peers = vec![];
+        let mut receivers = vec![];
+        for i in 1..=3 {
+            let config = Config {
+                public_address: address(i),
+                bootstrapping_nodes: vec![address(1)],
+                // The second peer accepts signed broadcasts only
+                require_signatures: i == 2,
+                ..Default::default()
+            };
+            let mut builder = Peer::builder(config)
+                .transport(network.transport(address(i).parse()?)?);
+            match i {
+                1 => builder = builder.identity(identity.clone()),
+                2 => builder = builder.identity(Identity::generate()),
+                _ => {}
+            }
+            let (peer, rx) = builder.build_with_receiver()?;
+            peers.push(peer);
+            receivers.push(rx);
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+
+        // Signed by the first peer, whose id derives from its key
+        peers[0].broadcast(b"signed", None).await;
+        for rx in &mut receivers[1..] {
+            let (message, info) =
+                timeout(Duration::from_secs(WAIT_SEC), rx.recv())
+                    .await?
+                    .expect("The receiver should be open");
+            assert_eq!(message, b"signed");
+            assert_eq!(info.origin(), Some(&public_key));
+        }
+
+        // Not signed, since the third peer runs without identity
+        peers[2].broadcast(b"unsigned", None).await;
+        let (message, info) =
+            timeout(Duration::from_secs(WAIT_SEC), receivers[0].recv())
+                .await?
+                .expect("The receiver should be open");
+        assert_eq!(message, b"unsigned");
+        assert_eq!(info.origin(), None);
+        let discarded =
+            timeout(Duration::from_millis(500), receivers[1].recv()).await;
+        assert!(discarded.is_err(), "Unsigned broadcast notified");
+        let metrics = peers[1].metrics().await;
+        assert!(metrics.counter(Counter::InvalidSignatures) > 0);
+
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn misbehaving_source_banned()
+    -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let mut config = Config {
+            public_address: "10.0.3.1:9000".to_string(),
+            ..Default::default()
+        };
+        config.reputation.auto_ban = true;
+        let (peer, _rx) = Peer::builder(config)
+            .transport(network.transport("10.0.3.1:9000".parse()?)?)
+            .build_with_receiver()?;
+        let mut events = peer.events();
+
+        // Undecodable datagrams, from a source sending garbage
+        let attacker = network.transport("10.0.3.2:9000".parse()?)?;
+        let target = "10.0.3.1:9000".parse()?;
+        attacker.send_to(&[0xff; 8], target).await?;
+        let ip = attacker.address().ip();
+        timeout(Duration::from_secs(WAIT_SEC), async {
+            while !peer.source_scores().contains_key(&ip) {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await?;
+
+        for _ in 0..10 {
+            attacker.send_to(&[0xff; 8], target).await?;
+        }
+        let event =
+            timeout(Duration::from_secs(WAIT_SEC), events.recv()).await??;
+        assert_eq!(event, PeerEvent::PeerBlocked { source: ip.into() });
+        let bans = peer.blocked_sources().await;
+        assert_eq!(bans.len(), 1);
+        assert_eq!(bans[0].source, ip.into());
+        assert!(bans[0].expires_in.is_some());
+        assert!(!peer.source_scores().contains_key(&ip));
+        let metrics = peer.metrics().await;
+        assert_eq!(metrics.counter(Counter::SourcesBanned), 1);
+
+        peer.shutdown().await;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn priority_lanes() -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.4.{i}:9000");
+        let mut peers = vec![];
+        let mut receivers = vec![];
+        for i in 1..=2 {
+            let mut config = Config {
+                public_address: address(i),
+                ..Default::default()
+            };
+            config.network.udp_send_peer_bytes_per_sec = Some(4_000_000);
+            let (peer, rx) = Peer::builder(config)
+                .transport(network.transport(address(i).parse()?)?)
+                .build_with_receiver()?;
+            peers.push(peer);
+            receivers.push(rx);
+        }
+
+        // The vote overtakes the bulk message being paced
+        let target = vec![address(2).parse()?];
+        let bulk = vec![1; 20 * MESSAGE_SIZE];
+        peers[0]
+            .send_to_peers_with(&bulk, target.clone(), Priority::Bulk)
+            .await;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        peers[0]
+            .send_to_peers_with(b"vote", target, Priority::High)
+            .await;
+        for expected in [&b"vote"[..], &bulk] {
+            let (message, _) =
+                timeout(Duration::from_secs(WAIT_SEC), receivers[1].recv())
+                    .await?
+                    .expect("The receiver should be open");
+            assert_eq!(message, expected);
+        }
+
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn startup_errors() -> Result<(), Box<dyn std::error::Error>> {
+        let address = format!("127.0.0.1:{}", BASE_PORT + 800);
+        let config = Config {
+            public_address: address.clone(),
+            ..Default::default()
+        };
+        let start = |config: Config| Peer::new_with_receiver(config).err();
+
+        let invalid = Config {
+            version: "1.x".to_string(),
+            ..config.clone()
+        };
+        let error = start(invalid);
+        assert!(matches!(error, Some(KadcastError::InvalidVersion(..))));
+
+        let invalid = Config {
+            version_match: "~>1".to_string(),
+            ..config.clone()
+        };
+        let error = start(invalid);
+        assert!(matches!(error, Some(KadcastError::InvalidVersionMatch(..))));
+
+        let invalid = Config {
+            public_address: "localhost".to_string(),
+            ..config.clone()
+        };
+        let error = start(invalid);
+        assert!(matches!(error, Some(KadcastError::InvalidAddress(..))));
+
+        let invalid = Config {
+            channel_size: 0,
+            ..config.clone()
+        };
+        let error = start(invalid);
+        assert!(matches!(
+            error,
+            Some(KadcastError::InvalidConfig(errors))
+                if errors == [ConfigError::ZeroValue("channel_size")]
+        ));
+
+        // The listen socket is bound before the peer starts
+        let _socket = UdpSocket::bind(&address)?;
+        let error = start(config);
+        assert!(matches!(error, Some(KadcastError::Bind(..))));
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn encrypted_sessions() -> Result<(), Box<dyn std::error::Error>> {
+        let network = MemoryNetwork::new();
+        let address = |i: usize| format!("10.0.2.{i}:9000");
+        let modes = [
+            Encryption::Required,
+            Encryption::Optional,
+            Encryption::Disabled,
+        ];
+
+        let mut peers = vec![];
+        let mut receivers = vec![];
+        for (i, encryption) in (1..).zip(modes) {
+            let mut config = Config {
+                public_address: address(i),
+                bootstrapping_nodes: vec![address(1)],
+                ..Default::default()
+            };
+            config.network.encryption = encryption;
+            let (peer, rx) = Peer::builder(config)
+                .transport(network.transport(address(i).parse()?)?)
+                .build_with_receiver()?;
+            peers.push(peer);
+            receivers.push(rx);
+        }
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+
+        // Both the encrypting peers established a session
+        for peer in &peers[..2] {
+            let metrics = peer.metrics().await;
+            assert!(metrics.counter(Counter::SessionsEstablished) > 0);
+        }
+        peers[1]
+            .send_to_peers(b"private", vec![address(1).parse()?])
+            .await;
+        let (message, _) =
+            timeout(Duration::from_secs(WAIT_SEC), receivers[0].recv())
+                .await?
+                .expect("The receiver should be open");
+        assert_eq!(message, b"private");
+
+        // The unencrypted peer can't join the first one
+        let plain = address(3).parse()?;
+        assert!(!peers[0].alive_nodes(10).await.contains(&plain));
+        peers[2]
+            .send_to_peers(b"plain", vec![address(1).parse()?])
+            .await;
+        peers[0].send_to_peers(b"private", vec![plain]).await;
+        for rx in receivers.iter_mut().step_by(2) {
+            let discarded =
+                timeout(Duration::from_millis(500), rx.recv()).await;
+            assert!(discarded.is_err(), "Plaintext message notified");
+        }
+        let metrics = peers[0].metrics().await;
+        assert!(metrics.counter(Counter::PlaintextRejected) > 0);
+
+        for peer in peers {
+            peer.shutdown().await;
+        }
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn simulated_network() -> Result<(), Box<dyn std::error::Error>> {
+        const PEERS: usize = 16;
+        let mut sim = Simulation::start(PEERS, Config::default())?;
+        sim.network().seed(7);
+        sim.settle(Duration::from_millis(1000)).await;
+
+        let timeout = Duration::from_secs(WAIT_SEC);
+        let report = sim.broadcast(PEERS - 1, b"reliable", timeout).await;
+        assert_eq!(report.coverage(), 1.0);
+
+        // RaptorQ redundancy recovers the lost chunks
+        let data = vec![7; MESSAGE_SIZE];
+        sim.network().set_default_link(LinkConditions {
+            loss: 0.05,
+            latency: Duration::from_millis(5),
+            jitter: Duration::from_millis(5),
+            ..Default::default()
+        });
+        let report = sim.broadcast(PEERS - 1, &data, timeout).await;
+        assert_eq!(report.coverage(), 1.0);
+        assert!(report.median_latency() >= Some(Duration::from_millis(5)));
+
+        // Isolated peers don't receive anything
+        sim.network().set_default_link(LinkConditions::default());
+        sim.network()
+            .partition((0..PEERS / 2).map(|i| sim.address(i)));
+        let report = sim
+            .broadcast(PEERS - 1, b"partitioned", Duration::from_secs(2))
+            .await;
+        assert!(report.latencies().iter().all(|(i, _)| *i >= PEERS / 2));
+        assert!(report.received() < PEERS / 2);
+
+        sim.network().heal();
+        let report = sim.broadcast(PEERS - 1, b"healed", timeout).await;
+        assert_eq!(report.coverage(), 1.0);
+        sim.shutdown().await;
+        Ok(())
+    }
+
+    struct ReverseRequest;
+
+    impl Responder for ReverseRequest {
+        fn respond<'a>(
+            &'a self,
+            request: &'a [u8],
+            _: SocketAddr,
+        ) -> ResponseFuture<'a> {
+            Box::pin(
+                async move { Some(request.iter().rev().copied().collect()) },
+            )
+        }
+    }
+
+    /// Never answers, counting the requests being answered
+    struct StalledResponder(Arc<AtomicUsize>);
+
+    struct Answering(Arc<AtomicUsize>);
+
+    impl Drop for Answering {
+        fn drop(&mut self) {
+            self.0.fetch_sub(1, Ordering::SeqCst);
+        }
+    }
+
+    impl Responder for StalledResponder {
+        fn respond<'a>(
+            &'a self,
+            _: &'a [u8],
+            _: SocketAddr,
+        ) -> ResponseFuture<'a> {
+            Box::pin(async move {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                let _answering = Answering(self.0.clone());
+                std::future::pending().await
+            })
+        }
+    }
+
+    struct RejectInvalid;
+
+    impl BroadcastValidator for RejectInvalid {
+        fn validate<'a>(
+            &'a self,
+            message: &'a [u8],
+            _: &'a MessageInfo,
+        ) -> ValidationFuture<'a> {
+            Box::pin(async move {
+                match message {
+                    b"invalid message" => Validation::Reject,
+                    b"ignored message" => Validation::Ignore,
+                    _ => Validation::Accept,
+                }
+            })
+        }
+    }
+
     async fn receive(
-        mut rx: mpsc::Receiver<(usize, (Vec<u8>, SocketAddr, u8))>,
+        mut rx: mpsc::Receiver<Received>,
         expected_from: Range<i32>,
     ) {
         let mut missing = HashSet::new();
@@ -131,7 +975,7 @@ mod tests {
         let mut i = 0;
         while !missing.is_empty() {
             if let Some((receiver_port, message)) = rx.recv().await {
-                i = i + 1;
+                i += 1;
                 let removed = missing.remove(&(receiver_port as i32));
                 info!(
                     "RECEIVER PORT: {} - Message N° {} got from {:?} -  Left {} - Removed {:?}",
@@ -150,27 +994,36 @@ mod tests {
     fn create_peer(
         i: i32,
         bootstrap: Vec<String>,
-        grpc_sender: mpsc::Sender<(usize, (Vec<u8>, SocketAddr, u8))>,
+        grpc_sender: mpsc::Sender<Received>,
         network_id: Option<u8>,
-    ) -> core::result::Result<Peer, AddrParseError> {
-        let port = BASE_PORT + i;
-        let public_addr = format!("127.0.0.1:{port}");
+    ) -> core::result::Result<Peer, KadcastError> {
         let listener = KadcastListener {
             grpc_sender,
-            receiver_port: port as usize,
+            receiver_port: (BASE_PORT + i) as usize,
         };
-        let mut conf = Config::default();
-        conf.kadcast_id = network_id;
-        conf.bootstrapping_nodes = bootstrap;
-        conf.public_address = public_addr;
-        conf.version_match = ">=1.2.2".to_string();
-        conf.version = format!("1.2.{i}");
-        conf.recursive_discovery = false;
-        Peer::new(conf, listener)
+        Peer::new(peer_config(i, bootstrap, network_id), listener)
+    }
+
+    fn peer_config(
+        i: i32,
+        bootstrap: Vec<String>,
+        network_id: Option<u8>,
+    ) -> Config {
+        let port = BASE_PORT + i;
+        let public_addr = format!("127.0.0.1:{port}");
+        Config {
+            kadcast_id: network_id,
+            bootstrapping_nodes: bootstrap,
+            public_address: public_addr,
+            version_match: ">=1.2.2".to_string(),
+            version: format!("1.2.{i}"),
+            recursive_discovery: false,
+            ..Default::default()
+        }
     }
 
     struct KadcastListener {
-        grpc_sender: mpsc::Sender<(usize, (Vec<u8>, SocketAddr, u8))>,
+        grpc_sender: mpsc::Sender<Received>,
         receiver_port: usize,
     }
 